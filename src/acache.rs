@@ -0,0 +1,120 @@
+//! Attribute cache keyed by qid.path.
+
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+use crate::session::FileAttr;
+
+/// Cache consistency mode, modelled on the Linux v9fs `cache=` option.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CacheMode {
+    /// Nothing is cached; every request goes to the server.
+    #[default]
+    None,
+    /// Cached data is trusted until its TTL expires or qid.version changes.
+    Loose,
+    /// Like `Loose`, but opening a file revalidates it with a TGETATTR, keeping
+    /// the cached entry only if qid.version and data_version are unchanged.
+    CloseToOpen,
+}
+
+/// Configuration for the attribute cache.
+#[derive(Clone, Copy, Debug)]
+pub struct AttrCacheConfig {
+    pub mode: CacheMode,
+    /// Lifetime of an entry; only enforced when a clock is installed.
+    pub ttl: Duration,
+    /// Maximum number of cached entries.
+    pub capacity: usize,
+}
+
+impl Default for AttrCacheConfig {
+    fn default() -> Self {
+        Self {
+            mode: CacheMode::Loose,
+            ttl: Duration::from_secs(1),
+            capacity: 1024,
+        }
+    }
+}
+
+struct AttrEntry {
+    attr: FileAttr,
+    expires: Option<Duration>,
+    last_used: u64,
+}
+
+/// Cached TGETATTR results, validated against the qid returned by each walk.
+pub(crate) struct AttrCache {
+    config: AttrCacheConfig,
+    entries: BTreeMap<u64, AttrEntry>,
+    tick: u64,
+}
+
+impl AttrCache {
+    pub(crate) fn new(config: AttrCacheConfig) -> Self {
+        Self {
+            config,
+            entries: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> CacheMode {
+        self.config.mode
+    }
+
+    /// Return cached attributes for `qid_path` if they are still valid for
+    /// `qid_version` and, when the server reported one, `data_version`.
+    pub(crate) fn get(
+        &mut self,
+        qid_path: u64,
+        qid_version: u32,
+        data_version: Option<u64>,
+        now: Option<Duration>,
+    ) -> Option<FileAttr> {
+        let entry = self.entries.get_mut(&qid_path)?;
+        let expired = matches!((entry.expires, now), (Some(expires), Some(now)) if now >= expires);
+        let changed = entry.attr.qid_version != qid_version
+            || data_version.is_some_and(|version| entry.attr.data_version != Some(version));
+        if expired || changed {
+            self.entries.remove(&qid_path);
+            return None;
+        }
+        self.tick += 1;
+        entry.last_used = self.tick;
+        Some(entry.attr.clone())
+    }
+
+    /// Store freshly fetched attributes.
+    pub(crate) fn insert(&mut self, attr: FileAttr, now: Option<Duration>) {
+        if !self.entries.contains_key(&attr.qid_path) && self.entries.len() >= self.config.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(key) = oldest {
+                self.entries.remove(&key);
+            }
+        }
+        self.tick += 1;
+        let entry = AttrEntry {
+            expires: now.map(|now| now + self.config.ttl),
+            last_used: self.tick,
+            attr,
+        };
+        self.entries.insert(entry.attr.qid_path, entry);
+    }
+
+    /// Apply a local change to a cached entry, if present.
+    pub(crate) fn update(&mut self, qid_path: u64, f: impl FnOnce(&mut FileAttr)) {
+        if let Some(entry) = self.entries.get_mut(&qid_path) {
+            f(&mut entry.attr);
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self, qid_path: u64) {
+        self.entries.remove(&qid_path);
+    }
+}
@@ -0,0 +1,86 @@
+//! Time source used for cache expiry and rate limiting.
+
+use alloc::sync::Arc;
+use core::time::Duration;
+use spin::Mutex;
+
+/// Monotonic clock supplied by the embedding environment.
+///
+/// The crate is `no_std` and has no notion of time of its own; TTL-based
+/// caches only expire entries when a clock has been installed.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+
+    /// Block for `duration`; the default spins on [`Clock::now`].
+    fn sleep(&self, duration: Duration) {
+        let deadline = self.now() + duration;
+        while self.now() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Clock backed by `std::time::Instant` and `std::thread::sleep`.
+#[cfg(feature = "std")]
+pub struct StdClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Clock that only moves when advanced; [`Clock::sleep`] advances it instantly.
+///
+/// Clones share the same time, so one handle can drive a session or transport
+/// while a test keeps another.
+#[derive(Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock();
+        *now = now.saturating_add(duration);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
@@ -0,0 +1,958 @@
+//! Typed encoding and decoding of every 9P2000, 9P2000.u and 9P2000.L message.
+//!
+//! Decoded messages borrow strings and payloads from the input buffer, and
+//! encoding reuses the caller's buffer, so a client, server or proxy can move
+//! messages without per-message copies.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::Deref;
+
+use crate::message::{read_bstr, read_bytes, read_qid, read_str, read_u8, read_u16, read_u32, read_u64, Message};
+use crate::protocol::*;
+
+/// Protocol variant, which changes the layout of a few messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    /// Plain 9P2000.
+    P2000,
+    /// 9P2000.u: numeric ids in auth/attach, errno in Rerror, create extensions.
+    P2000U,
+    /// 9P2000.L: Linux messages; auth/attach carry numeric ids.
+    P2000L,
+}
+
+impl Dialect {
+    /// The dialect named by a version string, if it is one this codec speaks.
+    pub(crate) fn from_version(version: &str) -> Option<Self> {
+        match version.to_ascii_lowercase().as_str() {
+            "9p2000.l" => Some(Dialect::P2000L),
+            "9p2000.u" => Some(Dialect::P2000U),
+            "9p2000" => Some(Dialect::P2000),
+            _ => None,
+        }
+    }
+
+    /// Whether Tauth and Tattach carry a numeric uname.
+    pub(crate) fn has_n_uname(self) -> bool {
+        self != Dialect::P2000
+    }
+}
+
+/// Up to 16 walk names or qids, stored inline so walks do not allocate.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WalkList<T> {
+    len: usize,
+    items: [T; MAXWELEM],
+}
+
+impl<T: Copy + Default> WalkList<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `item`, failing once the list holds 16 elements.
+    pub fn push(&mut self, item: T) -> Result<(), String> {
+        let slot = self
+            .items
+            .get_mut(self.len)
+            .ok_or_else(|| format!("walk has more than {} elements", MAXWELEM))?;
+        *slot = item;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl<T: Copy + Default> TryFrom<&[T]> for WalkList<T> {
+    type Error = String;
+
+    fn try_from(items: &[T]) -> Result<Self, String> {
+        if items.len() > MAXWELEM {
+            return Err(format!("walk has {} elements, more than {}", items.len(), MAXWELEM));
+        }
+        let mut list = Self::default();
+        list.items[..items.len()].copy_from_slice(items);
+        list.len = items.len();
+        Ok(list)
+    }
+}
+
+impl<T> Deref for WalkList<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.items[..self.len]
+    }
+}
+
+impl<'b, T> IntoIterator for &'b WalkList<T> {
+    type Item = &'b T;
+    type IntoIter = core::slice::Iter<'b, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Fields of a Tsetattr request; `valid` selects which ones apply.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SetAttr {
+    pub valid: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+}
+
+/// Body of an Rgetattr reply; `valid` says which fields the server filled in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Attr {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+    pub ctime_sec: u64,
+    pub ctime_nsec: u64,
+    pub btime_sec: u64,
+    pub btime_nsec: u64,
+    pub generation: u64,
+    pub data_version: u64,
+}
+
+/// Body of an Rstatfs reply.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StatFs {
+    pub type_: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+/// POSIX lock request carried by Tlock.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flock<'a> {
+    pub type_: u8,
+    pub flags: u32,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    pub client_id: &'a str,
+}
+
+/// Lock description carried by Tgetlock and Rgetlock.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Getlock<'a> {
+    pub type_: u8,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    pub client_id: &'a str,
+}
+
+/// Size, type and tag common to every message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub size: u32,
+    pub msg_type: u8,
+    pub tag: u16,
+}
+
+/// Length of the size, type and tag header.
+pub const HEADER_LEN: usize = 7;
+
+/// Decode the header at the start of `buf`.
+///
+/// Only the header is checked; [`Tmessage::decode`] and [`Rmessage::decode`]
+/// also require the size field to match the length of the message.
+pub fn decode_header(buf: &[u8]) -> Result<Header, String> {
+    if buf.len() < HEADER_LEN {
+        return Err(String::from("short 9p message"));
+    }
+    Ok(Header {
+        size: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        msg_type: buf[4],
+        tag: u16::from_le_bytes([buf[5], buf[6]]),
+    })
+}
+
+/// Decode the header of a complete message, whose size field must equal its length.
+fn decode_frame(buf: &[u8]) -> Result<Header, String> {
+    let header = decode_header(buf)?;
+    if header.size as usize != buf.len() {
+        return Err(format!(
+            "9p size field {} does not match message length {}",
+            header.size,
+            buf.len()
+        ));
+    }
+    Ok(header)
+}
+
+/// Reject bytes left over after the last field of a message.
+fn check_consumed(buf: &[u8], offset: usize, msg_type: u8) -> Result<(), String> {
+    if offset != buf.len() {
+        return Err(format!(
+            "{} has {} trailing bytes",
+            message_name(msg_type),
+            buf.len() - offset
+        ));
+    }
+    Ok(())
+}
+
+/// A request (T-message).
+#[derive(Clone, Debug, Eq, PartialEq)]
+// Walk lists are inline so that walks do not allocate.
+#[allow(clippy::large_enum_variant)]
+pub enum Tmessage<'a> {
+    Version { msize: u32, version: &'a str },
+    Auth { afid: u32, uname: &'a str, aname: &'a str, n_uname: Option<u32> },
+    Flush { oldtag: u16 },
+    Attach { fid: u32, afid: u32, uname: &'a str, aname: &'a str, n_uname: Option<u32> },
+    Walk { fid: u32, newfid: u32, wnames: WalkList<&'a [u8]> },
+    Open { fid: u32, mode: u8 },
+    Create { fid: u32, name: &'a [u8], perm: u32, mode: u8, extension: Option<&'a [u8]> },
+    Read { fid: u32, offset: u64, count: u32 },
+    Write { fid: u32, offset: u64, data: &'a [u8] },
+    Clunk { fid: u32 },
+    Remove { fid: u32 },
+    Stat { fid: u32 },
+    /// The stat structure, including its own leading size field.
+    Wstat { fid: u32, stat: &'a [u8] },
+    Statfs { fid: u32 },
+    Lopen { fid: u32, flags: u32 },
+    Lcreate { fid: u32, name: &'a [u8], flags: u32, mode: u32, gid: u32 },
+    Symlink { fid: u32, name: &'a [u8], symtgt: &'a [u8], gid: u32 },
+    Mknod { dfid: u32, name: &'a [u8], mode: u32, major: u32, minor: u32, gid: u32 },
+    Rename { fid: u32, dfid: u32, name: &'a [u8] },
+    Readlink { fid: u32 },
+    Getattr { fid: u32, request_mask: u64 },
+    Setattr { fid: u32, attr: SetAttr },
+    Xattrwalk { fid: u32, newfid: u32, name: &'a str },
+    Xattrcreate { fid: u32, name: &'a str, attr_size: u64, flags: u32 },
+    Readdir { fid: u32, offset: u64, count: u32 },
+    Fsync { fid: u32, datasync: u32 },
+    Lock { fid: u32, lock: Flock<'a> },
+    Getlock { fid: u32, lock: Getlock<'a> },
+    Link { dfid: u32, fid: u32, name: &'a [u8] },
+    Mkdir { dfid: u32, name: &'a [u8], mode: u32, gid: u32 },
+    Renameat { olddirfid: u32, oldname: &'a [u8], newdirfid: u32, newname: &'a [u8] },
+    Unlinkat { dirfd: u32, name: &'a [u8], flags: u32 },
+}
+
+/// A reply (R-message).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Rmessage<'a> {
+    Version { msize: u32, version: &'a str },
+    Auth { aqid: Qid },
+    /// 9P2000 error; 9P2000.u adds an errno.
+    Error { ename: &'a str, errno: Option<u32> },
+    Flush,
+    Attach { qid: Qid },
+    Walk { wqids: WalkList<Qid> },
+    Open { qid: Qid, iounit: u32 },
+    Create { qid: Qid, iounit: u32 },
+    Read { data: &'a [u8] },
+    Write { count: u32 },
+    Clunk,
+    Remove,
+    /// The stat structure, including its own leading size field.
+    Stat { stat: &'a [u8] },
+    Wstat,
+    Lerror { ecode: u32 },
+    Statfs(StatFs),
+    Lopen { qid: Qid, iounit: u32 },
+    Lcreate { qid: Qid, iounit: u32 },
+    Symlink { qid: Qid },
+    Mknod { qid: Qid },
+    Rename,
+    Readlink { target: &'a [u8] },
+    Getattr(Attr),
+    Setattr,
+    Xattrwalk { size: u64 },
+    Xattrcreate,
+    /// Raw 9P2000.L directory entries.
+    Readdir { data: &'a [u8] },
+    Fsync,
+    Lock { status: u8 },
+    Getlock(Getlock<'a>),
+    Link,
+    Mkdir { qid: Qid },
+    Renameat,
+    Unlinkat,
+}
+
+impl<'a> Tmessage<'a> {
+    /// Message type byte on the wire.
+    pub fn msg_type(&self) -> u8 {
+        match self {
+            Tmessage::Version { .. } => TVERSION,
+            Tmessage::Auth { .. } => TAUTH,
+            Tmessage::Flush { .. } => TFLUSH,
+            Tmessage::Attach { .. } => TATTACH,
+            Tmessage::Walk { .. } => TWALK,
+            Tmessage::Open { .. } => TOPEN,
+            Tmessage::Create { .. } => TCREATE,
+            Tmessage::Read { .. } => TREAD,
+            Tmessage::Write { .. } => TWRITE,
+            Tmessage::Clunk { .. } => TCLUNK,
+            Tmessage::Remove { .. } => TREMOVE,
+            Tmessage::Stat { .. } => TSTAT,
+            Tmessage::Wstat { .. } => TWSTAT,
+            Tmessage::Statfs { .. } => TSTATFS,
+            Tmessage::Lopen { .. } => TLOPEN,
+            Tmessage::Lcreate { .. } => TLCREATE,
+            Tmessage::Symlink { .. } => TSYMLINK,
+            Tmessage::Mknod { .. } => TMKNOD,
+            Tmessage::Rename { .. } => TRENAME,
+            Tmessage::Readlink { .. } => TREADLINK,
+            Tmessage::Getattr { .. } => TGETATTR,
+            Tmessage::Setattr { .. } => TSETATTR,
+            Tmessage::Xattrwalk { .. } => TXATTRWALK,
+            Tmessage::Xattrcreate { .. } => TXATTRCREATE,
+            Tmessage::Readdir { .. } => TREADDIR,
+            Tmessage::Fsync { .. } => TFSYNC,
+            Tmessage::Lock { .. } => TLOCK,
+            Tmessage::Getlock { .. } => TGETLOCK,
+            Tmessage::Link { .. } => TLINK,
+            Tmessage::Mkdir { .. } => TMKDIR,
+            Tmessage::Renameat { .. } => TRENAMEAT,
+            Tmessage::Unlinkat { .. } => TUNLINKAT,
+        }
+    }
+
+    /// Encode as a complete frame with `tag`, replacing the contents of `buf`.
+    ///
+    /// Strings over 65535 bytes or containing NUL, optional fields that do
+    /// not match `dialect` and frames over 4 GiB are rejected, leaving `buf`
+    /// empty.
+    pub fn encode(&self, tag: u16, dialect: Dialect, buf: &mut Vec<u8>) -> Result<(), String> {
+        let mut msg = Message::with_buffer(mem::take(buf), self.msg_type(), tag);
+        match self {
+            Tmessage::Version { msize, version } => {
+                msg.push_u32(*msize);
+                msg.push_str(version);
+            }
+            Tmessage::Auth { afid, uname, aname, n_uname } => {
+                check_field(&mut msg, "n_uname", n_uname.is_some(), dialect.has_n_uname(), dialect);
+                msg.push_u32(*afid);
+                msg.push_str(uname);
+                msg.push_str(aname);
+                if let Some(n_uname) = n_uname {
+                    msg.push_u32(*n_uname);
+                }
+            }
+            Tmessage::Flush { oldtag } => msg.push_u16(*oldtag),
+            Tmessage::Attach { fid, afid, uname, aname, n_uname } => {
+                check_field(&mut msg, "n_uname", n_uname.is_some(), dialect.has_n_uname(), dialect);
+                msg.push_u32(*fid);
+                msg.push_u32(*afid);
+                msg.push_str(uname);
+                msg.push_str(aname);
+                if let Some(n_uname) = n_uname {
+                    msg.push_u32(*n_uname);
+                }
+            }
+            Tmessage::Walk { fid, newfid, wnames } => {
+                msg.push_u32(*fid);
+                msg.push_u32(*newfid);
+                msg.push_u16(wnames.len() as u16);
+                for name in wnames {
+                    msg.push_bstr(name);
+                }
+            }
+            Tmessage::Open { fid, mode } => {
+                msg.push_u32(*fid);
+                msg.push_u8(*mode);
+            }
+            Tmessage::Create { fid, name, perm, mode, extension } => {
+                check_field(&mut msg, "extension", extension.is_some(), dialect == Dialect::P2000U, dialect);
+                msg.push_u32(*fid);
+                msg.push_bstr(name);
+                msg.push_u32(*perm);
+                msg.push_u8(*mode);
+                if let Some(extension) = extension {
+                    msg.push_bstr(extension);
+                }
+            }
+            Tmessage::Read { fid, offset, count } | Tmessage::Readdir { fid, offset, count } => {
+                msg.push_u32(*fid);
+                msg.push_u64(*offset);
+                msg.push_u32(*count);
+            }
+            Tmessage::Write { fid, offset, data } => {
+                msg.push_u32(*fid);
+                msg.push_u64(*offset);
+                msg.push_data(data);
+            }
+            Tmessage::Clunk { fid }
+            | Tmessage::Remove { fid }
+            | Tmessage::Stat { fid }
+            | Tmessage::Statfs { fid }
+            | Tmessage::Readlink { fid } => msg.push_u32(*fid),
+            Tmessage::Wstat { fid, stat } => {
+                msg.push_u32(*fid);
+                msg.push_stat(stat);
+            }
+            Tmessage::Lopen { fid, flags } => {
+                msg.push_u32(*fid);
+                msg.push_u32(*flags);
+            }
+            Tmessage::Lcreate { fid, name, flags, mode, gid } => {
+                msg.push_u32(*fid);
+                msg.push_bstr(name);
+                msg.push_u32(*flags);
+                msg.push_u32(*mode);
+                msg.push_u32(*gid);
+            }
+            Tmessage::Symlink { fid, name, symtgt, gid } => {
+                msg.push_u32(*fid);
+                msg.push_bstr(name);
+                msg.push_bstr(symtgt);
+                msg.push_u32(*gid);
+            }
+            Tmessage::Mknod { dfid, name, mode, major, minor, gid } => {
+                msg.push_u32(*dfid);
+                msg.push_bstr(name);
+                msg.push_u32(*mode);
+                msg.push_u32(*major);
+                msg.push_u32(*minor);
+                msg.push_u32(*gid);
+            }
+            Tmessage::Rename { fid, dfid, name } => {
+                msg.push_u32(*fid);
+                msg.push_u32(*dfid);
+                msg.push_bstr(name);
+            }
+            Tmessage::Getattr { fid, request_mask } => {
+                msg.push_u32(*fid);
+                msg.push_u64(*request_mask);
+            }
+            Tmessage::Setattr { fid, attr } => {
+                msg.push_u32(*fid);
+                msg.push_u32(attr.valid);
+                msg.push_u32(attr.mode);
+                msg.push_u32(attr.uid);
+                msg.push_u32(attr.gid);
+                msg.push_u64(attr.size);
+                msg.push_u64(attr.atime_sec);
+                msg.push_u64(attr.atime_nsec);
+                msg.push_u64(attr.mtime_sec);
+                msg.push_u64(attr.mtime_nsec);
+            }
+            Tmessage::Xattrwalk { fid, newfid, name } => {
+                msg.push_u32(*fid);
+                msg.push_u32(*newfid);
+                msg.push_str(name);
+            }
+            Tmessage::Xattrcreate { fid, name, attr_size, flags } => {
+                msg.push_u32(*fid);
+                msg.push_str(name);
+                msg.push_u64(*attr_size);
+                msg.push_u32(*flags);
+            }
+            Tmessage::Fsync { fid, datasync } => {
+                msg.push_u32(*fid);
+                msg.push_u32(*datasync);
+            }
+            Tmessage::Lock { fid, lock } => {
+                msg.push_u32(*fid);
+                msg.push_u8(lock.type_);
+                msg.push_u32(lock.flags);
+                msg.push_u64(lock.start);
+                msg.push_u64(lock.length);
+                msg.push_u32(lock.proc_id);
+                msg.push_str(lock.client_id);
+            }
+            Tmessage::Getlock { fid, lock } => {
+                msg.push_u32(*fid);
+                push_getlock(&mut msg, lock);
+            }
+            Tmessage::Link { dfid, fid, name } => {
+                msg.push_u32(*dfid);
+                msg.push_u32(*fid);
+                msg.push_bstr(name);
+            }
+            Tmessage::Mkdir { dfid, name, mode, gid } => {
+                msg.push_u32(*dfid);
+                msg.push_bstr(name);
+                msg.push_u32(*mode);
+                msg.push_u32(*gid);
+            }
+            Tmessage::Renameat { olddirfid, oldname, newdirfid, newname } => {
+                msg.push_u32(*olddirfid);
+                msg.push_bstr(oldname);
+                msg.push_u32(*newdirfid);
+                msg.push_bstr(newname);
+            }
+            Tmessage::Unlinkat { dirfd, name, flags } => {
+                msg.push_u32(*dirfd);
+                msg.push_bstr(name);
+                msg.push_u32(*flags);
+            }
+        }
+        *buf = msg.finish()?;
+        Ok(())
+    }
+
+    /// Encode a Twrite header whose `count` payload bytes the caller sends separately.
+    ///
+    /// The size field covers the payload, so appending exactly `count` bytes to
+    /// `buf` (or sending them after it) yields a complete Twrite.
+    pub fn encode_write_header(tag: u16, fid: u32, offset: u64, count: u32, buf: &mut Vec<u8>) -> Result<(), String> {
+        let mut msg = Message::with_buffer(mem::take(buf), TWRITE, tag);
+        msg.push_u32(fid);
+        msg.push_u64(offset);
+        msg.push_u32(count);
+        *buf = msg.finish_with_payload(count as usize)?;
+        Ok(())
+    }
+
+    /// Decode a complete frame, returning its tag and message.
+    ///
+    /// The size field, counts and string lengths must all agree with `buf`.
+    pub fn decode(buf: &'a [u8], dialect: Dialect) -> Result<(u16, Self), String> {
+        let header = decode_frame(buf)?;
+        let mut off = HEADER_LEN;
+        let b = buf;
+        let o = &mut off;
+        let msg = match header.msg_type {
+            TVERSION => Tmessage::Version { msize: read_u32(b, o)?, version: read_str(b, o)? },
+            TAUTH => Tmessage::Auth {
+                afid: read_u32(b, o)?,
+                uname: read_str(b, o)?,
+                aname: read_str(b, o)?,
+                n_uname: read_n_uname(b, o, dialect)?,
+            },
+            TFLUSH => Tmessage::Flush { oldtag: read_u16(b, o)? },
+            TATTACH => Tmessage::Attach {
+                fid: read_u32(b, o)?,
+                afid: read_u32(b, o)?,
+                uname: read_str(b, o)?,
+                aname: read_str(b, o)?,
+                n_uname: read_n_uname(b, o, dialect)?,
+            },
+            TWALK => {
+                let fid = read_u32(b, o)?;
+                let newfid = read_u32(b, o)?;
+                let nwname = read_u16(b, o)? as usize;
+                if nwname > MAXWELEM {
+                    return Err(format!("Twalk has {} names, more than {}", nwname, MAXWELEM));
+                }
+                let mut wnames = WalkList::new();
+                for _ in 0..nwname {
+                    wnames.push(read_bstr(b, o)?)?;
+                }
+                Tmessage::Walk { fid, newfid, wnames }
+            }
+            TOPEN => Tmessage::Open { fid: read_u32(b, o)?, mode: read_u8(b, o)? },
+            TCREATE => Tmessage::Create {
+                fid: read_u32(b, o)?,
+                name: read_bstr(b, o)?,
+                perm: read_u32(b, o)?,
+                mode: read_u8(b, o)?,
+                extension: if dialect == Dialect::P2000U { Some(read_bstr(b, o)?) } else { None },
+            },
+            TREAD => Tmessage::Read { fid: read_u32(b, o)?, offset: read_u64(b, o)?, count: read_u32(b, o)? },
+            TWRITE => {
+                let fid = read_u32(b, o)?;
+                let offset = read_u64(b, o)?;
+                let count = read_u32(b, o)? as usize;
+                Tmessage::Write { fid, offset, data: read_bytes(b, o, count)? }
+            }
+            TCLUNK => Tmessage::Clunk { fid: read_u32(b, o)? },
+            TREMOVE => Tmessage::Remove { fid: read_u32(b, o)? },
+            TSTAT => Tmessage::Stat { fid: read_u32(b, o)? },
+            TWSTAT => {
+                let fid = read_u32(b, o)?;
+                let len = read_u16(b, o)? as usize;
+                Tmessage::Wstat { fid, stat: read_bytes(b, o, len)? }
+            }
+            TSTATFS => Tmessage::Statfs { fid: read_u32(b, o)? },
+            TLOPEN => Tmessage::Lopen { fid: read_u32(b, o)?, flags: read_u32(b, o)? },
+            TLCREATE => Tmessage::Lcreate {
+                fid: read_u32(b, o)?,
+                name: read_bstr(b, o)?,
+                flags: read_u32(b, o)?,
+                mode: read_u32(b, o)?,
+                gid: read_u32(b, o)?,
+            },
+            TSYMLINK => Tmessage::Symlink {
+                fid: read_u32(b, o)?,
+                name: read_bstr(b, o)?,
+                symtgt: read_bstr(b, o)?,
+                gid: read_u32(b, o)?,
+            },
+            TMKNOD => Tmessage::Mknod {
+                dfid: read_u32(b, o)?,
+                name: read_bstr(b, o)?,
+                mode: read_u32(b, o)?,
+                major: read_u32(b, o)?,
+                minor: read_u32(b, o)?,
+                gid: read_u32(b, o)?,
+            },
+            TRENAME => Tmessage::Rename { fid: read_u32(b, o)?, dfid: read_u32(b, o)?, name: read_bstr(b, o)? },
+            TREADLINK => Tmessage::Readlink { fid: read_u32(b, o)? },
+            TGETATTR => Tmessage::Getattr { fid: read_u32(b, o)?, request_mask: read_u64(b, o)? },
+            TSETATTR => Tmessage::Setattr {
+                fid: read_u32(b, o)?,
+                attr: SetAttr {
+                    valid: read_u32(b, o)?,
+                    mode: read_u32(b, o)?,
+                    uid: read_u32(b, o)?,
+                    gid: read_u32(b, o)?,
+                    size: read_u64(b, o)?,
+                    atime_sec: read_u64(b, o)?,
+                    atime_nsec: read_u64(b, o)?,
+                    mtime_sec: read_u64(b, o)?,
+                    mtime_nsec: read_u64(b, o)?,
+                },
+            },
+            TXATTRWALK => Tmessage::Xattrwalk { fid: read_u32(b, o)?, newfid: read_u32(b, o)?, name: read_str(b, o)? },
+            TXATTRCREATE => Tmessage::Xattrcreate {
+                fid: read_u32(b, o)?,
+                name: read_str(b, o)?,
+                attr_size: read_u64(b, o)?,
+                flags: read_u32(b, o)?,
+            },
+            TREADDIR => Tmessage::Readdir { fid: read_u32(b, o)?, offset: read_u64(b, o)?, count: read_u32(b, o)? },
+            TFSYNC => Tmessage::Fsync { fid: read_u32(b, o)?, datasync: read_u32(b, o)? },
+            TLOCK => Tmessage::Lock {
+                fid: read_u32(b, o)?,
+                lock: Flock {
+                    type_: read_u8(b, o)?,
+                    flags: read_u32(b, o)?,
+                    start: read_u64(b, o)?,
+                    length: read_u64(b, o)?,
+                    proc_id: read_u32(b, o)?,
+                    client_id: read_str(b, o)?,
+                },
+            },
+            TGETLOCK => Tmessage::Getlock { fid: read_u32(b, o)?, lock: read_getlock(b, o)? },
+            TLINK => Tmessage::Link { dfid: read_u32(b, o)?, fid: read_u32(b, o)?, name: read_bstr(b, o)? },
+            TMKDIR => Tmessage::Mkdir {
+                dfid: read_u32(b, o)?,
+                name: read_bstr(b, o)?,
+                mode: read_u32(b, o)?,
+                gid: read_u32(b, o)?,
+            },
+            TRENAMEAT => Tmessage::Renameat {
+                olddirfid: read_u32(b, o)?,
+                oldname: read_bstr(b, o)?,
+                newdirfid: read_u32(b, o)?,
+                newname: read_bstr(b, o)?,
+            },
+            TUNLINKAT => Tmessage::Unlinkat { dirfd: read_u32(b, o)?, name: read_bstr(b, o)?, flags: read_u32(b, o)? },
+            other => return Err(format!("unknown request type: {}", other)),
+        };
+        check_consumed(buf, off, header.msg_type)?;
+        Ok((header.tag, msg))
+    }
+}
+
+impl<'a> Rmessage<'a> {
+    /// Message type byte on the wire.
+    pub fn msg_type(&self) -> u8 {
+        match self {
+            Rmessage::Version { .. } => RVERSION,
+            Rmessage::Auth { .. } => RAUTH,
+            Rmessage::Error { .. } => RERROR,
+            Rmessage::Flush => RFLUSH,
+            Rmessage::Attach { .. } => RATTACH,
+            Rmessage::Walk { .. } => RWALK,
+            Rmessage::Open { .. } => ROPEN,
+            Rmessage::Create { .. } => RCREATE,
+            Rmessage::Read { .. } => RREAD,
+            Rmessage::Write { .. } => RWRITE,
+            Rmessage::Clunk => RCLUNK,
+            Rmessage::Remove => RREMOVE,
+            Rmessage::Stat { .. } => RSTAT,
+            Rmessage::Wstat => RWSTAT,
+            Rmessage::Lerror { .. } => RLERROR,
+            Rmessage::Statfs(_) => RSTATFS,
+            Rmessage::Lopen { .. } => RLOPEN,
+            Rmessage::Lcreate { .. } => RLCREATE,
+            Rmessage::Symlink { .. } => RSYMLINK,
+            Rmessage::Mknod { .. } => RMKNOD,
+            Rmessage::Rename => RRENAME,
+            Rmessage::Readlink { .. } => RREADLINK,
+            Rmessage::Getattr(_) => RGETATTR,
+            Rmessage::Setattr => RSETATTR,
+            Rmessage::Xattrwalk { .. } => RXATTRWALK,
+            Rmessage::Xattrcreate => RXATTRCREATE,
+            Rmessage::Readdir { .. } => RREADDIR,
+            Rmessage::Fsync => RFSYNC,
+            Rmessage::Lock { .. } => RLOCK,
+            Rmessage::Getlock(_) => RGETLOCK,
+            Rmessage::Link => RLINK,
+            Rmessage::Mkdir { .. } => RMKDIR,
+            Rmessage::Renameat => RRENAMEAT,
+            Rmessage::Unlinkat => RUNLINKAT,
+        }
+    }
+
+    /// Encode as a complete frame with `tag`, replacing the contents of `buf`.
+    ///
+    /// Strings over 65535 bytes or containing NUL, optional fields that do
+    /// not match `dialect` and frames over 4 GiB are rejected, leaving `buf`
+    /// empty.
+    pub fn encode(&self, tag: u16, dialect: Dialect, buf: &mut Vec<u8>) -> Result<(), String> {
+        let mut msg = Message::with_buffer(mem::take(buf), self.msg_type(), tag);
+        match self {
+            Rmessage::Version { msize, version } => {
+                msg.push_u32(*msize);
+                msg.push_str(version);
+            }
+            Rmessage::Auth { aqid: qid }
+            | Rmessage::Attach { qid }
+            | Rmessage::Symlink { qid }
+            | Rmessage::Mknod { qid }
+            | Rmessage::Mkdir { qid } => push_qid(&mut msg, qid),
+            Rmessage::Error { ename, errno } => {
+                check_field(&mut msg, "errno", errno.is_some(), dialect == Dialect::P2000U, dialect);
+                msg.push_str(ename);
+                if let Some(errno) = errno {
+                    msg.push_u32(*errno);
+                }
+            }
+            Rmessage::Walk { wqids } => {
+                msg.push_u16(wqids.len() as u16);
+                for qid in wqids {
+                    push_qid(&mut msg, qid);
+                }
+            }
+            Rmessage::Open { qid, iounit }
+            | Rmessage::Create { qid, iounit }
+            | Rmessage::Lopen { qid, iounit }
+            | Rmessage::Lcreate { qid, iounit } => {
+                push_qid(&mut msg, qid);
+                msg.push_u32(*iounit);
+            }
+            Rmessage::Read { data } | Rmessage::Readdir { data } => {
+                msg.push_data(data);
+            }
+            Rmessage::Write { count } => msg.push_u32(*count),
+            Rmessage::Stat { stat } => {
+                msg.push_stat(stat);
+            }
+            Rmessage::Lerror { ecode } => msg.push_u32(*ecode),
+            Rmessage::Statfs(statfs) => {
+                msg.push_u32(statfs.type_);
+                msg.push_u32(statfs.bsize);
+                msg.push_u64(statfs.blocks);
+                msg.push_u64(statfs.bfree);
+                msg.push_u64(statfs.bavail);
+                msg.push_u64(statfs.files);
+                msg.push_u64(statfs.ffree);
+                msg.push_u64(statfs.fsid);
+                msg.push_u32(statfs.namelen);
+            }
+            Rmessage::Readlink { target } => msg.push_bstr(target),
+            Rmessage::Getattr(attr) => {
+                msg.push_u64(attr.valid);
+                push_qid(&mut msg, &attr.qid);
+                msg.push_u32(attr.mode);
+                msg.push_u32(attr.uid);
+                msg.push_u32(attr.gid);
+                for value in [
+                    attr.nlink,
+                    attr.rdev,
+                    attr.size,
+                    attr.blksize,
+                    attr.blocks,
+                    attr.atime_sec,
+                    attr.atime_nsec,
+                    attr.mtime_sec,
+                    attr.mtime_nsec,
+                    attr.ctime_sec,
+                    attr.ctime_nsec,
+                    attr.btime_sec,
+                    attr.btime_nsec,
+                    attr.generation,
+                    attr.data_version,
+                ] {
+                    msg.push_u64(value);
+                }
+            }
+            Rmessage::Xattrwalk { size } => msg.push_u64(*size),
+            Rmessage::Lock { status } => msg.push_u8(*status),
+            Rmessage::Getlock(lock) => push_getlock(&mut msg, lock),
+            Rmessage::Flush
+            | Rmessage::Clunk
+            | Rmessage::Remove
+            | Rmessage::Wstat
+            | Rmessage::Rename
+            | Rmessage::Setattr
+            | Rmessage::Xattrcreate
+            | Rmessage::Fsync
+            | Rmessage::Link
+            | Rmessage::Renameat
+            | Rmessage::Unlinkat => {}
+        }
+        *buf = msg.finish()?;
+        Ok(())
+    }
+
+    /// Decode a complete frame, returning its tag and message.
+    ///
+    /// The size field, counts and string lengths must all agree with `buf`.
+    pub fn decode(buf: &'a [u8], dialect: Dialect) -> Result<(u16, Self), String> {
+        let header = decode_frame(buf)?;
+        let mut off = HEADER_LEN;
+        let b = buf;
+        let o = &mut off;
+        let msg = match header.msg_type {
+            RVERSION => Rmessage::Version { msize: read_u32(b, o)?, version: read_str(b, o)? },
+            RAUTH => Rmessage::Auth { aqid: read_qid(b, o)? },
+            RERROR => Rmessage::Error {
+                ename: read_str(b, o)?,
+                errno: if dialect == Dialect::P2000U { Some(read_u32(b, o)?) } else { None },
+            },
+            RFLUSH => Rmessage::Flush,
+            RATTACH => Rmessage::Attach { qid: read_qid(b, o)? },
+            RWALK => {
+                let nwqid = read_u16(b, o)? as usize;
+                if nwqid > MAXWELEM {
+                    return Err(format!("Rwalk has {} qids, more than {}", nwqid, MAXWELEM));
+                }
+                let mut wqids = WalkList::new();
+                for _ in 0..nwqid {
+                    wqids.push(read_qid(b, o)?)?;
+                }
+                Rmessage::Walk { wqids }
+            }
+            ROPEN => Rmessage::Open { qid: read_qid(b, o)?, iounit: read_u32(b, o)? },
+            RCREATE => Rmessage::Create { qid: read_qid(b, o)?, iounit: read_u32(b, o)? },
+            RREAD => {
+                let count = read_u32(b, o)? as usize;
+                Rmessage::Read { data: read_bytes(b, o, count)? }
+            }
+            RWRITE => Rmessage::Write { count: read_u32(b, o)? },
+            RCLUNK => Rmessage::Clunk,
+            RREMOVE => Rmessage::Remove,
+            RSTAT => {
+                let len = read_u16(b, o)? as usize;
+                Rmessage::Stat { stat: read_bytes(b, o, len)? }
+            }
+            RWSTAT => Rmessage::Wstat,
+            RLERROR => Rmessage::Lerror { ecode: read_u32(b, o)? },
+            RSTATFS => Rmessage::Statfs(StatFs {
+                type_: read_u32(b, o)?,
+                bsize: read_u32(b, o)?,
+                blocks: read_u64(b, o)?,
+                bfree: read_u64(b, o)?,
+                bavail: read_u64(b, o)?,
+                files: read_u64(b, o)?,
+                ffree: read_u64(b, o)?,
+                fsid: read_u64(b, o)?,
+                namelen: read_u32(b, o)?,
+            }),
+            RLOPEN => Rmessage::Lopen { qid: read_qid(b, o)?, iounit: read_u32(b, o)? },
+            RLCREATE => Rmessage::Lcreate { qid: read_qid(b, o)?, iounit: read_u32(b, o)? },
+            RSYMLINK => Rmessage::Symlink { qid: read_qid(b, o)? },
+            RMKNOD => Rmessage::Mknod { qid: read_qid(b, o)? },
+            RRENAME => Rmessage::Rename,
+            RREADLINK => Rmessage::Readlink { target: read_bstr(b, o)? },
+            RGETATTR => Rmessage::Getattr(Attr {
+                valid: read_u64(b, o)?,
+                qid: read_qid(b, o)?,
+                mode: read_u32(b, o)?,
+                uid: read_u32(b, o)?,
+                gid: read_u32(b, o)?,
+                nlink: read_u64(b, o)?,
+                rdev: read_u64(b, o)?,
+                size: read_u64(b, o)?,
+                blksize: read_u64(b, o)?,
+                blocks: read_u64(b, o)?,
+                atime_sec: read_u64(b, o)?,
+                atime_nsec: read_u64(b, o)?,
+                mtime_sec: read_u64(b, o)?,
+                mtime_nsec: read_u64(b, o)?,
+                ctime_sec: read_u64(b, o)?,
+                ctime_nsec: read_u64(b, o)?,
+                btime_sec: read_u64(b, o)?,
+                btime_nsec: read_u64(b, o)?,
+                generation: read_u64(b, o)?,
+                data_version: read_u64(b, o)?,
+            }),
+            RSETATTR => Rmessage::Setattr,
+            RXATTRWALK => Rmessage::Xattrwalk { size: read_u64(b, o)? },
+            RXATTRCREATE => Rmessage::Xattrcreate,
+            RREADDIR => {
+                let count = read_u32(b, o)? as usize;
+                Rmessage::Readdir { data: read_bytes(b, o, count)? }
+            }
+            RFSYNC => Rmessage::Fsync,
+            RLOCK => Rmessage::Lock { status: read_u8(b, o)? },
+            RGETLOCK => Rmessage::Getlock(read_getlock(b, o)?),
+            RLINK => Rmessage::Link,
+            RMKDIR => Rmessage::Mkdir { qid: read_qid(b, o)? },
+            RRENAMEAT => Rmessage::Renameat,
+            RUNLINKAT => Rmessage::Unlinkat,
+            other => return Err(format!("unknown response type: {}", other)),
+        };
+        check_consumed(buf, off, header.msg_type)?;
+        Ok((header.tag, msg))
+    }
+}
+
+fn push_qid(msg: &mut Message, qid: &Qid) {
+    msg.push_u8(qid.type_);
+    msg.push_u32(qid.version);
+    msg.push_u64(qid.path);
+}
+
+fn push_getlock(msg: &mut Message, lock: &Getlock) {
+    msg.push_u8(lock.type_);
+    msg.push_u64(lock.start);
+    msg.push_u64(lock.length);
+    msg.push_u32(lock.proc_id);
+    msg.push_str(lock.client_id);
+}
+
+fn read_getlock<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Getlock<'a>, String> {
+    Ok(Getlock {
+        type_: read_u8(buf, offset)?,
+        start: read_u64(buf, offset)?,
+        length: read_u64(buf, offset)?,
+        proc_id: read_u32(buf, offset)?,
+        client_id: read_str(buf, offset)?,
+    })
+}
+
+/// Fail `msg` if an optional field's presence does not match what `dialect` carries.
+fn check_field(msg: &mut Message, field: &str, present: bool, expected: bool, dialect: Dialect) {
+    if present != expected {
+        let verb = if expected { "requires" } else { "has no" };
+        msg.fail(format!("{:?} {} {} field", dialect, verb, field));
+    }
+}
+
+fn read_n_uname(buf: &[u8], offset: &mut usize, dialect: Dialect) -> Result<Option<u32>, String> {
+    if dialect.has_n_uname() {
+        Ok(Some(read_u32(buf, offset)?))
+    } else {
+        Ok(None)
+    }
+}
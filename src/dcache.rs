@@ -0,0 +1,145 @@
+//! Walk fid (dentry) cache keyed by normalized path.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Configuration for the walk fid cache.
+#[derive(Clone, Copy, Debug)]
+pub struct DentryCacheConfig {
+    /// Maximum number of directory fids kept open by the cache.
+    pub capacity: usize,
+    /// How long a failed lookup is remembered; `None` disables negative entries.
+    pub negative_ttl: Option<Duration>,
+}
+
+impl Default for DentryCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64,
+            negative_ttl: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+struct Dentry {
+    fid: u32,
+    last_used: u64,
+}
+
+/// Open fids for recently walked directories plus negative lookup entries.
+///
+/// Keys are path components joined by `/` without a leading slash.
+pub(crate) struct DentryCache {
+    config: DentryCacheConfig,
+    entries: BTreeMap<Vec<u8>, Dentry>,
+    negative: BTreeMap<Vec<u8>, Duration>,
+    tick: u64,
+}
+
+impl DentryCache {
+    pub(crate) fn new(config: DentryCacheConfig) -> Self {
+        Self {
+            config,
+            entries: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Find the deepest cached directory among the prefixes of `parts`.
+    ///
+    /// Returns the directory fid and how many components it covers.
+    pub(crate) fn lookup(&mut self, parts: &[&[u8]]) -> Option<(u32, usize)> {
+        for depth in (1..=parts.len()).rev() {
+            let key = parts[..depth].join(&b'/');
+            if let Some(entry) = self.entries.get_mut(&key) {
+                self.tick += 1;
+                entry.last_used = self.tick;
+                return Some((entry.fid, depth));
+            }
+        }
+        None
+    }
+
+    /// Cache `fid` for `key`, returning any fids the caller must clunk.
+    pub(crate) fn insert(&mut self, key: Vec<u8>, fid: u32) -> Vec<u32> {
+        let mut evicted = Vec::new();
+        while !self.entries.contains_key(&key) && self.entries.len() >= self.config.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest.and_then(|key| self.entries.remove(&key)) {
+                Some(entry) => evicted.push(entry.fid),
+                None => break,
+            }
+        }
+        self.tick += 1;
+        if let Some(old) = self.entries.insert(
+            key,
+            Dentry {
+                fid,
+                last_used: self.tick,
+            },
+        ) {
+            evicted.push(old.fid);
+        }
+        evicted
+    }
+
+    /// Drop `key` and everything below it, returning the fids to clunk.
+    pub(crate) fn invalidate(&mut self, key: &[u8]) -> Vec<u32> {
+        let doomed: Vec<Vec<u8>> = self
+            .entries
+            .keys()
+            .filter(|candidate| is_same_or_below(candidate, key))
+            .cloned()
+            .collect();
+        doomed
+            .into_iter()
+            .filter_map(|key| self.entries.remove(&key))
+            .map(|entry| entry.fid)
+            .collect()
+    }
+
+    /// Remove every cached fid, returning them for the caller to clunk.
+    pub(crate) fn drain(&mut self) -> Vec<u32> {
+        self.negative.clear();
+        let entries = core::mem::take(&mut self.entries);
+        entries.into_values().map(|entry| entry.fid).collect()
+    }
+
+    /// Returns true if `key` or one of its ancestors recently failed to resolve.
+    pub(crate) fn is_negative(&mut self, key: &[u8], now: Option<Duration>) -> bool {
+        let Some(now) = now else {
+            return false;
+        };
+        self.negative.retain(|_, expires| *expires > now);
+        self.negative
+            .keys()
+            .any(|candidate| is_same_or_below(key, candidate))
+    }
+
+    /// Remember that `key` does not exist.
+    pub(crate) fn insert_negative(&mut self, key: Vec<u8>, now: Option<Duration>) {
+        if let (Some(now), Some(ttl)) = (now, self.config.negative_ttl) {
+            self.negative.insert(key, now + ttl);
+        }
+    }
+
+    /// Forget negative entries for `key`, its ancestors and descendants.
+    pub(crate) fn forget_negative(&mut self, key: &[u8]) {
+        self.negative.retain(|candidate, _| {
+            !is_same_or_below(candidate, key) && !is_same_or_below(key, candidate)
+        });
+    }
+}
+
+/// Returns true if `path` equals `base` or lies beneath it.
+fn is_same_or_below(path: &[u8], base: &[u8]) -> bool {
+    base.is_empty()
+        || path == base
+        || (path.starts_with(base) && path.get(base.len()) == Some(&b'/'))
+}
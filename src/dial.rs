@@ -0,0 +1,176 @@
+//! Socket and file-descriptor connectors addressed by Plan 9 dial strings.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use core::time::Duration;
+#[cfg(unix)]
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::protocol::DEFAULT_MSIZE;
+use crate::stream::{io_error, StreamTransport};
+use crate::transport::Transport;
+
+/// Well-known 9P port.
+const P9_PORT: u16 = 564;
+
+/// Options for [`connect_with`].
+#[derive(Clone, Copy, Debug)]
+pub struct DialOptions {
+    /// Limit for establishing a TCP connection.
+    pub connect_timeout: Option<Duration>,
+    /// Limit for each read of a reply; not supported by [`connect_fd`].
+    pub read_timeout: Option<Duration>,
+    /// Largest reply accepted; should match the msize the session offers.
+    pub max_size: u32,
+}
+
+impl Default for DialOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            read_timeout: None,
+            max_size: DEFAULT_MSIZE,
+        }
+    }
+}
+
+/// Connect to a 9P server using a dial string with default options.
+///
+/// Supported forms are `tcp!host!port` and `unix!/path/to/socket`. The port
+/// defaults to 564 and may be given as `9fs`/`9pfs`. Inherited descriptors
+/// are connected with [`connect_fd`] instead.
+pub fn connect(addr: &str) -> Result<Box<dyn Transport>, String> {
+    connect_with(addr, DialOptions::default())
+}
+
+/// Connect to a 9P server using a dial string; see [`connect`].
+pub fn connect_with(addr: &str, options: DialOptions) -> Result<Box<dyn Transport>, String> {
+    let stream = dial(addr, &options)?;
+    Ok(Box::new(StreamTransport::from_std(stream, options.max_size)))
+}
+
+/// Speak 9P over descriptors that are already open, such as a pipe pair
+/// inherited from a parent process.
+///
+/// The transport closes both descriptors when dropped. A single bidirectional
+/// descriptor is passed together with a `try_clone` of itself. Read timeouts
+/// are not supported.
+#[cfg(unix)]
+pub fn connect_fd(read: OwnedFd, write: OwnedFd, options: DialOptions) -> Result<Box<dyn Transport>, String> {
+    if options.read_timeout.is_some() {
+        return Err(String::from("read timeout not supported for fd transports"));
+    }
+    let stream = DialStream::Fd(FdPair { read: File::from(read), write: File::from(write) });
+    Ok(Box::new(StreamTransport::from_std(stream, options.max_size)))
+}
+
+/// Open the byte stream named by a dial string.
+pub(crate) fn dial(addr: &str, options: &DialOptions) -> Result<DialStream, String> {
+    let (network, rest) = addr
+        .split_once('!')
+        .ok_or_else(|| format!("invalid dial string: {}", addr))?;
+    match network {
+        "tcp" | "net" => dial_tcp(rest, options),
+        #[cfg(unix)]
+        "unix" => dial_unix(rest, options),
+        _ => Err(format!("unsupported network in dial string: {}", addr)),
+    }
+}
+
+fn dial_tcp(rest: &str, options: &DialOptions) -> Result<DialStream, String> {
+    let (host, port) = match rest.rsplit_once('!') {
+        Some((host, port)) => (host, parse_port(port)?),
+        None => (rest, P9_PORT),
+    };
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| format!("cannot resolve {}: {}", host, err))?;
+    let mut last_err = format!("no addresses for {}", host);
+    for addr in addrs {
+        let stream = match options.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match stream {
+            Ok(stream) => {
+                stream.set_nodelay(true).map_err(io_error)?;
+                stream.set_read_timeout(options.read_timeout).map_err(io_error)?;
+                return Ok(DialStream::Tcp(stream));
+            }
+            Err(err) => last_err = format!("connect {}: {}", addr, io_error(err)),
+        }
+    }
+    Err(last_err)
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port {
+        "9fs" | "9pfs" => Ok(P9_PORT),
+        _ => port.parse().map_err(|_| format!("invalid port: {}", port)),
+    }
+}
+
+#[cfg(unix)]
+fn dial_unix(path: &str, options: &DialOptions) -> Result<DialStream, String> {
+    let stream = UnixStream::connect(path)
+        .map_err(|err| format!("connect {}: {}", path, io_error(err)))?;
+    stream.set_read_timeout(options.read_timeout).map_err(io_error)?;
+    Ok(DialStream::Unix(stream))
+}
+
+/// Connected stream returned by [`dial`].
+pub(crate) enum DialStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(unix)]
+    Fd(FdPair),
+}
+
+impl Read for DialStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Fd(pair) => pair.read.read(buf),
+        }
+    }
+}
+
+impl Write for DialStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Fd(pair) => pair.write.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Fd(pair) => pair.write.flush(),
+        }
+    }
+}
+
+/// Separate descriptors for the read and write directions.
+#[cfg(unix)]
+pub(crate) struct FdPair {
+    read: File,
+    write: File,
+}
@@ -0,0 +1,382 @@
+//! Seeded fault injection for exercising session error paths.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::clock::VirtualClock;
+use crate::protocol::{RATTACH, RCLUNK, RLERROR, RREAD, RWALK, TATTACH, TCLUNK, TREMOVE, TWALK};
+use crate::transport::Transport;
+
+/// A single kind of misbehaviour.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// Deliver the request but lose the reply, reporting a timeout.
+    Drop,
+    /// Cut the reply short at a random length.
+    Truncate,
+    /// Flip one random bit in the reply body.
+    Corrupt,
+    /// Advance the virtual clock by this much before replying.
+    Delay(Duration),
+    /// Reply with a tag other than the request's.
+    SwapTag,
+    /// Reply with a valid but unexpected message type.
+    WrongType,
+    /// Answer with `Rlerror` carrying this errno without contacting the server.
+    Errno(u32),
+}
+
+/// When to inject a [`Fault`].
+#[derive(Clone, Copy, Debug)]
+pub struct FaultRule {
+    fault: Fault,
+    msg_type: Option<u8>,
+    one_in: u32,
+}
+
+impl FaultRule {
+    /// Inject `fault` on every request.
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            fault,
+            msg_type: None,
+            one_in: 1,
+        }
+    }
+
+    /// Only consider requests of this T-message type, such as
+    /// [`msg_type::TWALK`](crate::msg_type::TWALK).
+    pub fn on(mut self, msg_type: u8) -> Self {
+        self.msg_type = Some(msg_type);
+        self
+    }
+
+    /// Fire on roughly one in `n` matching requests.
+    pub fn one_in(mut self, n: u32) -> Self {
+        self.one_in = n.max(1);
+        self
+    }
+}
+
+struct FaultState {
+    rng: u64,
+    injected: u32,
+    live_fids: BTreeSet<u32>,
+}
+
+/// Transport wrapper that injects faults chosen by a seeded generator.
+///
+/// The same seed, rules and request sequence always produce the same faults.
+/// Rules are tried in order and at most one fires per request. The wrapper
+/// also tracks which fids the client holds according to the replies it
+/// delivered, so tests can check for leaks with [`FaultTransport::live_fids`].
+pub struct FaultTransport<T> {
+    inner: T,
+    rules: Vec<FaultRule>,
+    clock: VirtualClock,
+    state: Mutex<FaultState>,
+}
+
+impl<T: Transport> FaultTransport<T> {
+    /// Wrap `inner` with no rules, seeding the generator with `seed`.
+    pub fn new(inner: T, seed: u64) -> Self {
+        Self {
+            inner,
+            rules: Vec::new(),
+            clock: VirtualClock::new(),
+            state: Mutex::new(FaultState {
+                rng: seed | 1,
+                injected: 0,
+                live_fids: BTreeSet::new(),
+            }),
+        }
+    }
+
+    /// Add a rule, tried after those already added.
+    pub fn with_rule(mut self, rule: FaultRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Advance `clock` for [`Fault::Delay`] instead of a private one.
+    pub fn with_clock(mut self, clock: VirtualClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Clock advanced by delay faults.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Number of faults injected so far.
+    pub fn injected(&self) -> u32 {
+        self.state.lock().injected
+    }
+
+    /// Fids attached or walked to and not yet clunked or removed.
+    pub fn live_fids(&self) -> Vec<u32> {
+        self.state.lock().live_fids.iter().copied().collect()
+    }
+
+    /// Return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn pick(&self, state: &mut FaultState, msg_type: u8) -> Option<Fault> {
+        for rule in &self.rules {
+            if rule.msg_type.is_some_and(|t| t != msg_type) {
+                continue;
+            }
+            if next_random(&mut state.rng).is_multiple_of(u64::from(rule.one_in)) {
+                state.injected += 1;
+                return Some(rule.fault);
+            }
+        }
+        None
+    }
+}
+
+/// xorshift64* step.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn field_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn field_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Update the live fid set from a request and the reply delivered for it.
+///
+/// Only a complete reply carrying the request's tag counts; anything else is
+/// one the session rejects.
+fn track_fids(live: &mut BTreeSet<u32>, req: &[u8], resp: Option<&[u8]>) {
+    let resp = resp.filter(|resp| {
+        field_u32(resp, 0) == u32::try_from(resp.len()).ok() && resp.get(5..7) == req.get(5..7)
+    });
+    let reply_type = resp.and_then(|resp| resp.get(4).copied());
+    match req[4] {
+        TATTACH if reply_type == Some(RATTACH) => {
+            live.extend(field_u32(req, 7));
+        }
+        // A walk only establishes newfid when every name was walked.
+        TWALK if reply_type == Some(RWALK) && field_u16(req, 15) == resp.and_then(|resp| field_u16(resp, 7)) => {
+            live.extend(field_u32(req, 11));
+        }
+        // A clunk or remove releases the fid whatever the reply says.
+        TCLUNK | TREMOVE => {
+            if let Some(fid) = field_u32(req, 7) {
+                live.remove(&fid);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl<T: Transport> FaultTransport<T> {
+    /// Pick the fault for `req`, plus a random value for applying it.
+    fn choose(&self, req: &[u8]) -> (Option<Fault>, u64) {
+        if req.len() < 7 {
+            return (None, 0);
+        }
+        let mut state = self.state.lock();
+        let fault = self.pick(&mut state, req[4]);
+        if let Some(Fault::Delay(duration)) = fault {
+            self.clock.advance(duration);
+        }
+        (fault, next_random(&mut state.rng))
+    }
+
+    /// Record the fids `req` gained or released given the reply delivered for it.
+    fn track(&self, req: &[u8], delivered: Option<&[u8]>) {
+        if req.len() >= 7 {
+            track_fids(&mut self.state.lock().live_fids, req, delivered);
+        }
+    }
+}
+
+/// Write an `Rlerror` carrying `errno` for the request tagged `tag`.
+fn errno_reply(resp: &mut [u8], tag: [u8; 2], errno: u32) -> Result<usize, String> {
+    if resp.len() < 11 {
+        return Err(String::from("response buffer too small"));
+    }
+    resp[..4].copy_from_slice(&11u32.to_le_bytes());
+    resp[4] = RLERROR;
+    resp[5..7].copy_from_slice(&tag);
+    resp[7..11].copy_from_slice(&errno.to_le_bytes());
+    Ok(11)
+}
+
+/// Apply a reply-side fault to the `size` bytes the server sent back.
+fn damage(fault: Option<Fault>, random: u64, tag: [u8; 2], resp: &mut [u8], size: usize) -> Result<usize, String> {
+    let size = size.min(resp.len());
+    match fault {
+        Some(Fault::Drop) => Err(format!("transport timed out: reply to tag {} dropped", u16::from_le_bytes(tag))),
+        Some(Fault::Truncate) => Ok(random as usize % size.max(1)),
+        Some(Fault::Corrupt) if size > 7 => {
+            let bit = random as usize % ((size - 7) * 8);
+            resp[7 + bit / 8] ^= 1 << (bit % 8);
+            Ok(size)
+        }
+        Some(Fault::Corrupt) if size > 0 => {
+            resp[random as usize % size] ^= 0xFF;
+            Ok(size)
+        }
+        Some(Fault::SwapTag) if size >= 7 => {
+            let tag = u16::from_le_bytes(tag).wrapping_add(1 + (random % 0xFFFE) as u16);
+            resp[5..7].copy_from_slice(&tag.to_le_bytes());
+            Ok(size)
+        }
+        Some(Fault::WrongType) if size >= 7 => {
+            resp[4] = if resp[4] == RCLUNK { RREAD } else { RCLUNK };
+            Ok(size)
+        }
+        _ => Ok(size),
+    }
+}
+
+/// Copy the first `size` bytes of a reply split across `head` and `data`.
+fn gather(head: &[u8], data: &[u8], size: usize) -> Vec<u8> {
+    head.iter().chain(data).take(size).copied().collect()
+}
+
+/// Split `reply` back across `head` and `data`, returning its length.
+fn scatter(reply: &[u8], head: &mut [u8], data: &mut [u8]) -> Result<usize, String> {
+    if reply.len() > head.len() + data.len() {
+        return Err(String::from("response buffer too small"));
+    }
+    let split = reply.len().min(head.len());
+    head[..split].copy_from_slice(&reply[..split]);
+    data[..reply.len() - split].copy_from_slice(&reply[split..]);
+    Ok(reply.len())
+}
+
+fn tag_of(req: &[u8]) -> [u8; 2] {
+    match req.get(5..7) {
+        Some(tag) => [tag[0], tag[1]],
+        None => [0; 2],
+    }
+}
+
+impl<T: Transport> Transport for FaultTransport<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let (fault, random) = self.choose(req);
+        let tag = tag_of(req);
+        let result = match fault {
+            Some(Fault::Errno(errno)) => errno_reply(resp, tag, errno),
+            _ => self
+                .inner
+                .request(req, resp)
+                .and_then(|size| damage(fault, random, tag, resp, size)),
+        };
+        self.track(req, result.as_ref().ok().map(|size| &resp[..*size]));
+        result
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    /// Faults apply to a copy of the reply, which is then scattered back.
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let (fault, random) = self.choose(req_head);
+        let tag = tag_of(req_head);
+        let result = match fault {
+            Some(Fault::Errno(errno)) => {
+                let mut reply = [0; 11];
+                errno_reply(&mut reply, tag, errno).and_then(|size| scatter(&reply[..size], resp_head, resp_data))
+            }
+            _ => self
+                .inner
+                .request_vectored(req_head, req_data, resp_head, resp_data)
+                .and_then(|size| {
+                    let mut reply = gather(resp_head, resp_data, size);
+                    let size = damage(fault, random, tag, &mut reply, size)?;
+                    scatter(&reply[..size], resp_head, resp_data)
+                }),
+        };
+        let delivered = result.as_ref().ok().map(|size| gather(resp_head, resp_data, *size));
+        self.track(req_head, delivered.as_deref());
+        result
+    }
+
+    /// Faults are chosen per request; `Errno` answers stay local and the rest
+    /// of the batch goes to the inner transport as one batch.
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        if resps.len() < reqs.len() {
+            return Err(String::from("fewer reply buffers than requests"));
+        }
+        let faults: Vec<_> = reqs.iter().map(|req| self.choose(req)).collect();
+        let local = |index: usize| matches!(faults[index].0, Some(Fault::Errno(_)));
+        let forwarded: Vec<&[u8]> = (0..reqs.len()).filter(|index| !local(*index)).map(|index| reqs[index]).collect();
+        let sent = {
+            let mut bufs: Vec<&mut [u8]> = resps
+                .iter_mut()
+                .take(reqs.len())
+                .enumerate()
+                .filter(|(index, _)| !local(*index))
+                .map(|(_, resp)| &mut **resp)
+                .collect();
+            self.inner.request_batch(&forwarded, &mut bufs)
+        };
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(err) => {
+                for req in reqs {
+                    self.track(req, None);
+                }
+                return Err(err);
+            }
+        };
+
+        let mut sent = sent.into_iter();
+        let mut sizes = Vec::with_capacity(reqs.len());
+        let mut first_err = None;
+        for (index, (req, resp)) in reqs.iter().zip(resps.iter_mut()).enumerate() {
+            let (fault, random) = faults[index];
+            let tag = tag_of(req);
+            let result = match fault {
+                Some(Fault::Errno(errno)) => errno_reply(resp, tag, errno),
+                _ => match sent.next() {
+                    Some(size) => damage(fault, random, tag, resp, size),
+                    None => Err(String::from("batch returned too few replies")),
+                },
+            };
+            match result {
+                Ok(size) => sizes.push(size),
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                    sizes.push(0);
+                }
+            }
+        }
+        // A failed batch delivers none of its replies.
+        for ((req, resp), size) in reqs.iter().zip(resps.iter()).zip(&sizes) {
+            self.track(req, first_err.is_none().then(|| &resp[..*size]));
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(sizes),
+        }
+    }
+}
@@ -3,12 +3,81 @@
 //! Lightweight 9P client library for no_std targets.
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
+mod acache;
+mod clock;
+mod codec;
+mod dcache;
+#[cfg(feature = "std")]
+mod dial;
+mod fault;
 mod message;
+mod middleware;
 mod parse;
+mod pcache;
+mod pcapng;
+#[cfg(feature = "std")]
+mod process;
 mod protocol;
+mod readdir;
 mod session;
+mod stream;
+#[cfg(feature = "tls")]
+mod tls;
+mod trace;
 mod transport;
+#[cfg(feature = "virtio")]
+mod virtio;
+mod walktree;
 
+pub use acache::{AttrCacheConfig, CacheMode};
+pub use clock::Clock;
+#[cfg(feature = "std")]
+pub use clock::StdClock;
+pub use clock::VirtualClock;
+pub use codec::{decode_header, Attr, Dialect, Flock, Getlock, Header, Rmessage, SetAttr, StatFs, Tmessage, WalkList, HEADER_LEN};
+pub use dcache::DentryCacheConfig;
+#[cfg(feature = "std")]
+pub use dial::{connect, connect_with, DialOptions};
+#[cfg(all(feature = "std", unix))]
+pub use dial::connect_fd;
+pub use fault::{Fault, FaultRule, FaultTransport};
+pub use middleware::{LoggingTransport, RateLimit, RateLimitTransport, RetryTransport};
+pub use parse::DotDotPolicy;
+pub use pcache::{PageCacheConfig, PageCacheMode, PAGE_SIZE};
+pub use pcapng::CaptureTransport;
+#[cfg(feature = "std")]
+pub use process::ProcessTransport;
+pub use protocol::Qid;
+pub use readdir::ReadDir;
 pub use session::{FileAttr, P9DirEntry, P9Session as Session};
+pub use stream::{ByteStream, StreamTransport};
+#[cfg(feature = "embedded-io")]
+pub use stream::EmbeddedStream;
+#[cfg(feature = "std")]
+pub use stream::StdStream;
+#[cfg(feature = "tls")]
+pub use rustls;
+#[cfg(feature = "tls")]
+pub use tls::{connect_tls, TlsOptions, TlsVerify};
+pub use trace::{RecordingTransport, ReplayTransport};
 pub use transport::Transport;
+#[cfg(feature = "virtio")]
+pub use virtio::VirtioTransport;
+pub use walktree::{TreeEntry, WalkEvent, WalkOrder, WalkTree, WalkTreeOptions};
+
+/// Message type bytes on the wire, as [`Tmessage::msg_type`] reports them and
+/// [`FaultRule::on`] matches them.
+pub mod msg_type {
+    pub use crate::protocol::{
+        RATTACH, RAUTH, RCLUNK, RCREATE, RERROR, RFLUSH, RFSYNC, RGETATTR, RGETLOCK, RLCREATE,
+        RLERROR, RLINK, RLOCK, RLOPEN, RMKDIR, RMKNOD, ROPEN, RREAD, RREADDIR, RREADLINK, RREMOVE,
+        RRENAME, RRENAMEAT, RSETATTR, RSTAT, RSTATFS, RSYMLINK, RUNLINKAT, RVERSION, RWALK, RWRITE,
+        RWSTAT, RXATTRCREATE, RXATTRWALK, TATTACH, TAUTH, TCLUNK, TCREATE, TFLUSH, TFSYNC, TGETATTR,
+        TGETLOCK, TLCREATE, TLINK, TLOCK, TLOPEN, TMKDIR, TMKNOD, TOPEN, TREAD, TREADDIR, TREADLINK,
+        TREMOVE, TRENAME, TRENAMEAT, TSETATTR, TSTAT, TSTATFS, TSYMLINK, TUNLINKAT, TVERSION, TWALK,
+        TWRITE, TWSTAT, TXATTRCREATE, TXATTRWALK,
+    };
+}
@@ -1,23 +1,28 @@
 //! Message builder and decoding helpers for 9P packets.
 
 use alloc::format;
-use alloc::string::{String, ToString};
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use crate::protocol::Qid;
 
 /// 9P message encoder with size prefix.
+///
+/// A field that cannot be encoded is not written; the first such error is
+/// returned from [`Message::finish`].
 pub(crate) struct Message {
     buf: Vec<u8>,
+    error: Option<String>,
 }
 
 impl Message {
-    pub(crate) fn new(msg_type: u8, tag: u16) -> Self {
-        let mut buf = Vec::with_capacity(64);
+    /// Start a message in `buf`, discarding its previous contents but keeping its capacity.
+    pub(crate) fn with_buffer(mut buf: Vec<u8>, msg_type: u8, tag: u16) -> Self {
+        buf.clear();
         buf.extend_from_slice(&[0, 0, 0, 0]);
         buf.push(msg_type);
         buf.extend_from_slice(&tag.to_le_bytes());
-        Self { buf }
+        Self { buf, error: None }
     }
 
     pub(crate) fn push_u8(&mut self, value: u8) {
@@ -37,20 +42,61 @@ impl Message {
     }
 
     pub(crate) fn push_str(&mut self, value: &str) {
-        let bytes = value.as_bytes();
-        let len = bytes.len() as u16;
+        self.push_bstr(value.as_bytes());
+    }
+
+    /// Append a string field whose bytes need not be UTF-8, such as a file name.
+    pub(crate) fn push_bstr(&mut self, bytes: &[u8]) {
+        let Ok(len) = u16::try_from(bytes.len()) else {
+            self.fail(format!("9p string of {} bytes exceeds 65535", bytes.len()));
+            return;
+        };
+        if bytes.contains(&0) {
+            self.fail(format!("9p string {:?} contains a NUL byte", String::from_utf8_lossy(bytes)));
+            return;
+        }
         self.push_u16(len);
         self.buf.extend_from_slice(bytes);
     }
 
-    pub(crate) fn push_bytes(&mut self, bytes: &[u8]) {
-        self.buf.extend_from_slice(bytes);
+    /// Append `data` preceded by its 32-bit length.
+    pub(crate) fn push_data(&mut self, data: &[u8]) {
+        let Ok(len) = u32::try_from(data.len()) else {
+            self.fail(format!("9p payload of {} bytes exceeds 4 GiB", data.len()));
+            return;
+        };
+        self.push_u32(len);
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Append a stat structure preceded by its 16-bit length.
+    pub(crate) fn push_stat(&mut self, stat: &[u8]) {
+        let Ok(len) = u16::try_from(stat.len()) else {
+            self.fail(format!("9p stat of {} bytes exceeds 65535", stat.len()));
+            return;
+        };
+        self.push_u16(len);
+        self.buf.extend_from_slice(stat);
+    }
+
+    /// Record an encoding error; only the first is kept.
+    pub(crate) fn fail(&mut self, err: String) {
+        self.error.get_or_insert(err);
     }
 
-    pub(crate) fn finish(mut self) -> Vec<u8> {
-        let size = self.buf.len() as u32;
+    pub(crate) fn finish(self) -> Result<Vec<u8>, String> {
+        self.finish_with_payload(0)
+    }
+
+    /// Finish a header whose `payload_len` trailing bytes are sent separately.
+    pub(crate) fn finish_with_payload(mut self, payload_len: usize) -> Result<Vec<u8>, String> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let size = u32::try_from(self.buf.len() + payload_len)
+            .map_err(|_| String::from("9p message exceeds 4 GiB"))?;
         self.buf[0..4].copy_from_slice(&size.to_le_bytes());
-        self.buf
+        Ok(self.buf)
     }
 }
 
@@ -104,15 +150,25 @@ pub(crate) fn read_u64(buf: &[u8], offset: &mut usize) -> Result<u64, String> {
     Ok(value)
 }
 
-pub(crate) fn read_str(buf: &[u8], offset: &mut usize) -> Result<String, String> {
+pub(crate) fn read_str<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, String> {
+    let bytes = read_bstr(buf, offset)?;
+    core::str::from_utf8(bytes).map_err(|_| String::from("invalid utf8"))
+}
+
+/// Read a string field as raw bytes, without requiring UTF-8.
+pub(crate) fn read_bstr<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a [u8], String> {
     let len = read_u16(buf, offset)? as usize;
-    if *offset + len > buf.len() {
+    read_bytes(buf, offset, len)
+}
+
+/// Borrow the next `len` bytes of `buf`.
+pub(crate) fn read_bytes<'a>(buf: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    if len > buf.len() - (*offset).min(buf.len()) {
         return Err(String::from("short buffer"));
     }
-    let value = core::str::from_utf8(&buf[*offset..*offset + len])
-        .map_err(|_| String::from("invalid utf8"))?;
+    let value = &buf[*offset..*offset + len];
     *offset += len;
-    Ok(value.to_string())
+    Ok(value)
 }
 
 pub(crate) fn read_qid(buf: &[u8], offset: &mut usize) -> Result<Qid, String> {
@@ -121,8 +177,8 @@ pub(crate) fn read_qid(buf: &[u8], offset: &mut usize) -> Result<Qid, String> {
     let path = read_u64(buf, offset)?;
     Ok(Qid {
         type_,
-        _version: version,
-        _path: path,
+        version,
+        path,
     })
 }
 
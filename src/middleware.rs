@@ -0,0 +1,450 @@
+//! Transport wrappers that add retries, rate limiting and logging.
+//!
+//! Each layer is itself a [`Transport`] around any other transport, so they
+//! stack in whatever order the caller needs.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::time::Duration;
+use log::Level;
+use spin::Mutex;
+
+use crate::clock::Clock;
+use crate::codec::{Dialect, Rmessage, Tmessage};
+use crate::protocol::{message_name, TFSYNC, TGETATTR, TREAD, TREADDIR, TREADLINK};
+use crate::transport::Transport;
+
+/// Message type and tag of a 9P message, if the header is complete.
+fn header(msg: &[u8]) -> Option<(u8, u16)> {
+    if msg.len() < 7 {
+        return None;
+    }
+    Some((msg[4], u16::from_le_bytes([msg[5], msg[6]])))
+}
+
+/// Requests that can be resent without changing server state.
+fn is_idempotent(msg_type: u8) -> bool {
+    matches!(msg_type, TREAD | TREADDIR | TGETATTR | TREADLINK | TFSYNC)
+}
+
+fn is_idempotent_request(req: &[u8]) -> bool {
+    header(req).is_some_and(|(msg_type, _)| is_idempotent(msg_type))
+}
+
+/// Errors that a retry may resolve: timeouts.
+fn is_transient(err: &str) -> bool {
+    err.starts_with("transport timed out")
+}
+
+/// Retries idempotent requests that fail with a transient error.
+///
+/// Only reads, directory reads, getattr, readlink and fsync are retried;
+/// anything that creates, mutates or releases state is passed through once,
+/// as is a batch containing such a request.
+/// Stream transports drop their framing on a timeout, so this layer is most
+/// useful over message-oriented transports.
+pub struct RetryTransport<T> {
+    inner: T,
+    max_retries: u32,
+    backoff: Option<(Box<dyn Clock>, Duration)>,
+    is_transient: fn(&str) -> bool,
+}
+
+impl<T: Transport> RetryTransport<T> {
+    /// Retry each idempotent request up to `max_retries` more times.
+    pub fn new(inner: T, max_retries: u32) -> Self {
+        Self {
+            inner,
+            max_retries,
+            backoff: None,
+            is_transient,
+        }
+    }
+
+    /// Sleep on `clock` between attempts, doubling `initial` each time.
+    pub fn with_backoff(mut self, clock: Box<dyn Clock>, initial: Duration) -> Self {
+        self.backoff = Some((clock, initial));
+        self
+    }
+
+    /// Replace the test deciding which errors are worth retrying.
+    pub fn with_classifier(mut self, is_transient: fn(&str) -> bool) -> Self {
+        self.is_transient = is_transient;
+        self
+    }
+
+    /// Return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn retry<R>(&self, idempotent: bool, mut attempt: impl FnMut() -> Result<R, String>) -> Result<R, String> {
+        let retries = if idempotent { self.max_retries } else { 0 };
+        let mut delay = self.backoff.as_ref().map(|(_, initial)| *initial);
+        let mut tries = 0;
+        loop {
+            match attempt() {
+                Err(err) if tries < retries && (self.is_transient)(&err) => {
+                    tries += 1;
+                    if let (Some((clock, _)), Some(wait)) = (&self.backoff, delay) {
+                        clock.sleep(wait);
+                        delay = Some(wait.saturating_mul(2));
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T: Transport> Transport for RetryTransport<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.retry(is_idempotent_request(req), || self.inner.request(req, resp))
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        self.retry(is_idempotent_request(req_head), || {
+            self.inner
+                .request_vectored(req_head, req_data, resp_head, resp_data)
+        })
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        let idempotent = reqs.iter().all(|req| is_idempotent_request(req));
+        self.retry(idempotent, || self.inner.request_batch(reqs, resps))
+    }
+}
+
+/// Limits for [`RateLimitTransport`]; `None` leaves a dimension unlimited.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Request plus reply bytes per second.
+    pub bytes_per_sec: Option<u64>,
+    /// Requests per second.
+    pub ops_per_sec: Option<u64>,
+    /// How much unused allowance may accumulate, as time at the full rate.
+    pub burst: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            bytes_per_sec: None,
+            ops_per_sec: None,
+            burst: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Token bucket that may go into debt; debt is repaid by waiting.
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+}
+
+impl Bucket {
+    fn new(rate: Option<u64>, burst: Duration) -> Option<Self> {
+        let rate = rate?.max(1) as f64;
+        let capacity = (rate * burst.as_secs_f64()).max(1.0);
+        Some(Self {
+            rate,
+            capacity,
+            tokens: capacity,
+        })
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + self.rate * elapsed.as_secs_f64()).min(self.capacity);
+    }
+
+    /// Take `amount` tokens and return how long the caller must wait to cover any debt.
+    fn take(&mut self, amount: usize) -> Duration {
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+struct Buckets {
+    bytes: Option<Bucket>,
+    ops: Option<Bucket>,
+    updated: Duration,
+}
+
+/// Token-bucket limiter on request rate and bandwidth.
+///
+/// A request reserves one operation and its own size before it is sent and
+/// waits out any resulting debt; the reply size is charged afterwards, so
+/// large reads slow down the requests that follow them.
+pub struct RateLimitTransport<T> {
+    inner: T,
+    clock: Box<dyn Clock>,
+    buckets: Mutex<Buckets>,
+}
+
+impl<T: Transport> RateLimitTransport<T> {
+    /// Limit `inner` to `limit`, measuring time and waiting with `clock`.
+    pub fn new(inner: T, limit: RateLimit, clock: Box<dyn Clock>) -> Self {
+        let updated = clock.now();
+        Self {
+            inner,
+            clock,
+            buckets: Mutex::new(Buckets {
+                bytes: Bucket::new(limit.bytes_per_sec, limit.burst),
+                ops: Bucket::new(limit.ops_per_sec, limit.burst),
+                updated,
+            }),
+        }
+    }
+
+    /// Return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn charge(&self, ops: usize, bytes: usize) -> Duration {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock();
+        let elapsed = now.saturating_sub(buckets.updated);
+        buckets.updated = now;
+        let mut wait = Duration::ZERO;
+        if let Some(bucket) = &mut buckets.ops {
+            bucket.refill(elapsed);
+            wait = wait.max(bucket.take(ops));
+        }
+        if let Some(bucket) = &mut buckets.bytes {
+            bucket.refill(elapsed);
+            wait = wait.max(bucket.take(bytes));
+        }
+        wait
+    }
+
+    /// Charge requests up front and wait out any debt.
+    fn reserve(&self, ops: usize, req_len: usize) {
+        let wait = self.charge(ops, req_len);
+        if !wait.is_zero() {
+            self.clock.sleep(wait);
+        }
+    }
+
+    fn limited(&self, req_len: usize, send: impl FnOnce() -> Result<usize, String>) -> Result<usize, String> {
+        self.reserve(1, req_len);
+        let size = send()?;
+        self.charge(0, size);
+        Ok(size)
+    }
+}
+
+impl<T: Transport> Transport for RateLimitTransport<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.limited(req.len(), || self.inner.request(req, resp))
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let req_len = req_head.len() + req_data.iter().map(|data| data.len()).sum::<usize>();
+        self.limited(req_len, || {
+            self.inner
+                .request_vectored(req_head, req_data, resp_head, resp_data)
+        })
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.reserve(reqs.len(), reqs.iter().map(|req| req.len()).sum());
+        let sizes = self.inner.request_batch(reqs, resps)?;
+        self.charge(0, sizes.iter().sum());
+        Ok(sizes)
+    }
+}
+
+/// Logs a one-line summary of every request and reply through the `log` crate.
+///
+/// Messages are decoded in the dialect the last Rversion negotiated, plain
+/// 9P2000 until then. Payloads are summarised by their length.
+pub struct LoggingTransport<T> {
+    inner: T,
+    level: Level,
+    dialect: Mutex<Dialect>,
+}
+
+impl<T: Transport> LoggingTransport<T> {
+    /// Log traffic on `inner` at debug level.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            level: Level::Debug,
+            dialect: Mutex::new(Dialect::P2000),
+        }
+    }
+
+    /// Log at `level` instead.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn log_request(&self, req: &[u8]) {
+        if !log::log_enabled!(self.level) {
+            return;
+        }
+        let summary = match Tmessage::decode(req, *self.dialect.lock()) {
+            Ok((tag, msg)) => describe_request(tag, &msg),
+            Err(err) => format!("malformed size={} ({})", req.len(), err),
+        };
+        log::log!(self.level, "9p -> {}", summary);
+    }
+
+    fn log_reply(&self, result: &Result<usize, String>, reply: &[u8]) {
+        if !log::log_enabled!(self.level) {
+            return;
+        }
+        if let Err(err) = result {
+            return self.log_error(err);
+        }
+        let mut dialect = self.dialect.lock();
+        let summary = match Rmessage::decode(reply, *dialect) {
+            Ok((tag, msg)) => {
+                if let Rmessage::Version { version, .. } = msg {
+                    *dialect = Dialect::from_version(version).unwrap_or(Dialect::P2000);
+                }
+                describe_reply(tag, &msg)
+            }
+            Err(err) => format!("malformed size={} ({})", reply.len(), err),
+        };
+        drop(dialect);
+        log::log!(self.level, "9p <- {}", summary);
+    }
+
+    fn log_error(&self, err: &str) {
+        log::log!(self.level, "9p <- transport error: {}", err);
+    }
+}
+
+/// Message name and tag of `msg`, followed by its `Debug` fields.
+fn describe<M: Debug>(msg_type: u8, tag: u16, msg: &M) -> String {
+    // Debug prints the variant name first; the message name replaces it.
+    let debug = format!("{:?}", msg);
+    let fields = debug.trim_start_matches(char::is_alphanumeric).trim_start();
+    format!("{} tag={}{}{}", message_name(msg_type), tag, if fields.is_empty() { "" } else { " " }, fields)
+}
+
+fn describe_request(tag: u16, msg: &Tmessage) -> String {
+    let name = message_name(msg.msg_type());
+    match msg {
+        Tmessage::Walk { fid, newfid, wnames } => {
+            let wnames: Vec<_> = wnames.iter().map(|name| String::from_utf8_lossy(name)).collect();
+            format!("{} tag={} {{ fid: {}, newfid: {}, wnames: {:?} }}", name, tag, fid, newfid, wnames)
+        }
+        Tmessage::Write { fid, offset, data } => {
+            format!("{} tag={} {{ fid: {}, offset: {}, count: {} }}", name, tag, fid, offset, data.len())
+        }
+        Tmessage::Wstat { fid, stat } => format!("{} tag={} {{ fid: {}, size: {} }}", name, tag, fid, stat.len()),
+        _ => describe(msg.msg_type(), tag, msg),
+    }
+}
+
+fn describe_reply(tag: u16, msg: &Rmessage) -> String {
+    let name = message_name(msg.msg_type());
+    match msg {
+        Rmessage::Read { data } | Rmessage::Readdir { data } => {
+            format!("{} tag={} {{ count: {} }}", name, tag, data.len())
+        }
+        Rmessage::Stat { stat } => format!("{} tag={} {{ size: {} }}", name, tag, stat.len()),
+        Rmessage::Walk { wqids } => format!("{} tag={} {{ wqids: {:?} }}", name, tag, &wqids[..]),
+        _ => describe(msg.msg_type(), tag, msg),
+    }
+}
+
+/// The first `size` bytes of a message split across `head` and `data`.
+fn joined(head: &[u8], data: &[u8], size: usize) -> Vec<u8> {
+    head.iter().chain(data).take(size).copied().collect()
+}
+
+impl<T: Transport> Transport for LoggingTransport<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.log_request(req);
+        let result = self.inner.request(req, resp);
+        let size = result.as_ref().map_or(0, |size| (*size).min(resp.len()));
+        self.log_reply(&result, &resp[..size]);
+        result
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    /// Split messages are only joined for decoding while logging is enabled.
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let enabled = log::log_enabled!(self.level);
+        if enabled {
+            let req_len = req_head.len() + req_data.iter().map(|data| data.len()).sum::<usize>();
+            let mut req = Vec::with_capacity(req_len);
+            req.extend_from_slice(req_head);
+            for data in req_data {
+                req.extend_from_slice(data);
+            }
+            self.log_request(&req);
+        }
+        let result = self
+            .inner
+            .request_vectored(req_head, req_data, resp_head, resp_data);
+        if enabled {
+            let size = result.as_ref().map_or(0, |size| *size);
+            self.log_reply(&result, &joined(resp_head, resp_data, size));
+        }
+        result
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        for req in reqs {
+            self.log_request(req);
+        }
+        let result = self.inner.request_batch(reqs, resps);
+        match &result {
+            Ok(sizes) => {
+                for (size, resp) in sizes.iter().zip(resps.iter()) {
+                    self.log_reply(&Ok(*size), &resp[..(*size).min(resp.len())]);
+                }
+            }
+            Err(err) => self.log_error(err),
+        }
+        result
+    }
+}
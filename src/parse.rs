@@ -1,81 +1,149 @@
 //! Path handling and directory entry parsing.
 
+use alloc::borrow::Cow;
+use alloc::collections::VecDeque;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 
-use crate::message::{read_qid, read_str, read_u16, read_u32, read_u64, read_u8};
+use crate::message::{read_bstr, read_qid, read_u16, read_u32, read_u64, read_u8};
+use crate::protocol::Qid;
+use crate::session::P9DirEntry;
 
-/// Split a path into parent directory and leaf name.
-pub(crate) fn split_parent_name(path: &str) -> Result<(&str, &str), String> {
-    let trimmed = path.trim_end_matches('/');
-    if trimmed.is_empty() || trimmed == "/" {
-        return Err(String::from("invalid path"));
-    }
-    let mut parts = trimmed.rsplitn(2, '/');
-    let name = parts.next().unwrap_or("");
-    let parent = parts.next().unwrap_or("");
-    let parent = if parent.is_empty() { "/" } else { parent };
-    if name.is_empty() {
-        Err(String::from("invalid path"))
-    } else {
-        Ok((parent, name))
+/// How path resolution treats `..` components.
+///
+/// `..` is always resolved lexically against the components before it and is
+/// never sent to the server, so a path cannot climb above the attach point.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DotDotPolicy {
+    /// `..` at the root stays at the root, as in `/..` on Unix.
+    #[default]
+    Clamp,
+    /// A path whose `..` would climb above the root is an error.
+    RejectEscape,
+    /// Any `..` component is an error.
+    Reject,
+}
+
+/// Split a path into normalized components, resolving `.` and `..`.
+pub(crate) fn normalize_path(path: &[u8], policy: DotDotPolicy) -> Result<Vec<&[u8]>, String> {
+    let mut parts = Vec::new();
+    for part in path.split(|byte| *byte == b'/') {
+        match part {
+            b"" | b"." => {}
+            b".." => match policy {
+                DotDotPolicy::Reject => return Err(format!("path {:?} contains \"..\"", lossy(path))),
+                DotDotPolicy::RejectEscape if parts.is_empty() => {
+                    return Err(format!("path {:?} escapes the root", lossy(path)));
+                }
+                _ => {
+                    parts.pop();
+                }
+            },
+            _ if part.contains(&0) => return Err(format!("invalid path component {:?}", lossy(part))),
+            _ => parts.push(part),
+        }
     }
+    Ok(parts)
+}
+
+/// Components of `path` without allocating, when it has no `..` to resolve.
+///
+/// Returns `None` for paths that need [`normalize_path`], including ones
+/// with NUL bytes so that it reports the error.
+pub(crate) fn plain_components(path: &[u8]) -> Option<impl Iterator<Item = &[u8]> + Clone> {
+    let parts = path
+        .split(|byte| *byte == b'/')
+        .filter(|part| !matches!(*part, b"" | b"."));
+    parts
+        .clone()
+        .all(|part| part != b".." && !part.contains(&0))
+        .then_some(parts)
+}
+
+/// Split a path into its normalized parent directory and leaf name.
+pub(crate) fn split_parent_name(path: &[u8], policy: DotDotPolicy) -> Result<(Vec<u8>, &[u8]), String> {
+    let mut parts = normalize_path(path, policy)?;
+    let name = parts.pop().ok_or_else(|| String::from("invalid path"))?;
+    Ok((parts.join(&b'/'), name))
+}
+
+/// Join the components of `path` normalized under `policy` into a cache key.
+///
+/// A path the policy rejects maps to the root key, which covers every entry.
+pub(crate) fn path_key(path: &[u8], policy: DotDotPolicy) -> Vec<u8> {
+    normalize_path(path, policy)
+        .map(|parts| parts.join(&b'/'))
+        .unwrap_or_default()
 }
 
-/// Split a path into normalized components.
-pub(crate) fn path_parts(path: &str) -> Vec<&str> {
-    path.split('/').filter(|part| !part.is_empty() && *part != ".").collect()
+/// Render a byte-string name for messages, replacing invalid UTF-8.
+pub(crate) fn lossy(name: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(name)
 }
 
-/// Parse 9P2000 stat-based directory entries.
-pub(crate) fn parse_dir_entries(data: &[u8], names: &mut Vec<String>) -> Result<(), String> {
+/// Parse 9P2000 stat-based directory entries read at byte offset `base`.
+///
+/// Each entry's cookie is the stream offset just past it.
+pub(crate) fn parse_dir_entries(data: &[u8], base: u64, entries: &mut VecDeque<P9DirEntry>) -> Result<(), String> {
     let mut offset = 0usize;
-    while offset + 2 <= data.len() {
+    while offset < data.len() {
+        if offset + 2 > data.len() {
+            return Err(String::from("truncated directory entry"));
+        }
         let size = u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
         offset += 2;
         if offset + size > data.len() {
-            break;
+            return Err(String::from("directory entry overruns read"));
         }
         let entry = &data[offset..offset + size];
         offset += size;
-        let name = parse_stat_name(entry)?;
-        if name != "." && name != ".." {
-            names.push(name);
-        }
+        let (qid, name) = parse_stat_entry(entry)?;
+        entries.push_back(P9DirEntry {
+            name: name.to_vec(),
+            entry_type: dirent_type(qid),
+            qid,
+            cookie: base + offset as u64,
+        });
     }
     Ok(())
 }
 
-/// Parse 9P2000.L readdir entries and return the last offset.
-pub(crate) fn parse_dir_entries_l(data: &[u8]) -> Result<(Vec<String>, Option<u64>), String> {
+/// Parse 9P2000.L readdir entries.
+pub(crate) fn parse_dir_entries_l(data: &[u8], entries: &mut VecDeque<P9DirEntry>) -> Result<(), String> {
     let mut offset = 0usize;
-    let mut names = Vec::new();
-    let mut last_offset = None;
     while offset < data.len() {
-        let _qid = read_qid(data, &mut offset)?;
-        let entry_offset = read_u64(data, &mut offset)?;
-        let _entry_type = read_u8(data, &mut offset)?;
-        let name = read_str(data, &mut offset)?;
-        if name != "." && name != ".." {
-            names.push(name);
-        }
-        last_offset = Some(entry_offset);
+        let qid = read_qid(data, &mut offset)?;
+        let cookie = read_u64(data, &mut offset)?;
+        let entry_type = read_u8(data, &mut offset)?;
+        let name = read_bstr(data, &mut offset)?;
+        entries.push_back(P9DirEntry { name: name.to_vec(), entry_type, qid, cookie });
+    }
+    Ok(())
+}
+
+/// Derive a dirent d_type from qid type bits.
+pub(crate) fn dirent_type(qid: Qid) -> u8 {
+    if qid.type_ & 0x80 != 0 {
+        4
+    } else if qid.type_ & 0x02 != 0 {
+        10
+    } else {
+        8
     }
-    Ok((names, last_offset))
 }
 
-fn parse_stat_name(buf: &[u8]) -> Result<String, String> {
+fn parse_stat_entry(buf: &[u8]) -> Result<(Qid, &[u8]), String> {
     let mut offset = 0usize;
     if buf.len() < 39 {
         return Err(String::from("stat too short"));
     }
     let _type = read_u16(buf, &mut offset)?;
     let _dev = read_u32(buf, &mut offset)?;
-    let _qid = read_qid(buf, &mut offset)?;
+    let qid = read_qid(buf, &mut offset)?;
     let _mode = read_u32(buf, &mut offset)?;
     let _atime = read_u32(buf, &mut offset)?;
     let _mtime = read_u32(buf, &mut offset)?;
     let _length = read_u64(buf, &mut offset)?;
-    let name = read_str(buf, &mut offset)?;
-    Ok(name)
+    Ok((qid, read_bstr(buf, &mut offset)?))
 }
@@ -0,0 +1,390 @@
+//! Client-side page cache with read-ahead and write-back.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of a cached page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Page cache consistency mode, modelled on Linux v9fs `cache=loose`/`cache=mmap`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PageCacheMode {
+    /// Reads are cached with read-ahead and writes are buffered until flushed.
+    /// Clean pages are dropped when an open observes a new qid.version.
+    #[default]
+    Loose,
+    /// Reads are cached but writes go straight to the server and update any
+    /// cached pages. Clean pages are dropped on every open.
+    Mmap,
+}
+
+/// Configuration for the page cache.
+#[derive(Clone, Copy, Debug)]
+pub struct PageCacheConfig {
+    pub mode: PageCacheMode,
+    /// Pages kept before clean pages are evicted and dirty ones written back.
+    pub max_pages: usize,
+    /// Upper bound of the adaptive read-ahead window, in pages.
+    pub max_readahead: usize,
+}
+
+impl Default for PageCacheConfig {
+    fn default() -> Self {
+        Self {
+            mode: PageCacheMode::Loose,
+            max_pages: 1024,
+            max_readahead: 32,
+        }
+    }
+}
+
+/// Contiguous dirty bytes: starting file offset and (page, start, end) segments.
+pub(crate) type DirtyRun = (u64, Vec<(u64, usize, usize)>);
+
+/// A cached page. `data.len()` is the number of valid bytes; a short page
+/// marks end of file unless a later page of the same file is cached.
+pub(crate) struct Page {
+    pub(crate) data: Vec<u8>,
+    /// Byte range within the page that has not been written back.
+    pub(crate) dirty: Option<(usize, usize)>,
+    last_used: u64,
+}
+
+/// Cached pages and consistency state for one file (qid.path).
+#[derive(Default)]
+pub(crate) struct CachedFile {
+    pub(crate) pages: BTreeMap<u64, Page>,
+    /// qid.version observed at the last open.
+    version: Option<u32>,
+    /// Size and data_version from the last TGETATTR.
+    size: Option<u64>,
+    data_version: Option<u64>,
+    /// Fid used to write back dirty pages.
+    pub(crate) writeback_fid: Option<u32>,
+    /// Offset a sequential reader is expected to continue from.
+    next_offset: u64,
+    /// Current read-ahead window in pages.
+    window: usize,
+}
+
+impl CachedFile {
+    pub(crate) fn has_dirty(&self) -> bool {
+        self.pages.values().any(|page| page.dirty.is_some())
+    }
+
+    /// Group dirty pages into runs of contiguous bytes.
+    pub(crate) fn dirty_runs(&self) -> Vec<DirtyRun> {
+        let mut runs: Vec<DirtyRun> = Vec::new();
+        let mut prev: Option<(u64, usize)> = None;
+        for (&index, page) in &self.pages {
+            let Some((start, end)) = page.dirty else {
+                prev = None;
+                continue;
+            };
+            let continues = matches!(prev, Some((prev_index, prev_end))
+                if prev_index + 1 == index && prev_end == PAGE_SIZE && start == 0);
+            match runs.last_mut() {
+                Some((_, segments)) if continues => segments.push((index, start, end)),
+                _ => runs.push((index * PAGE_SIZE as u64 + start as u64, vec![(index, start, end)])),
+            }
+            prev = Some((index, end));
+        }
+        runs
+    }
+
+    pub(crate) fn clear_dirty(&mut self) {
+        for page in self.pages.values_mut() {
+            page.dirty = None;
+        }
+    }
+}
+
+/// Page-granular cache of file contents keyed by qid.path.
+pub(crate) struct PageCache {
+    config: PageCacheConfig,
+    files: BTreeMap<u64, CachedFile>,
+    pages: usize,
+    tick: u64,
+}
+
+impl PageCache {
+    pub(crate) fn new(config: PageCacheConfig) -> Self {
+        Self {
+            config,
+            files: BTreeMap::new(),
+            pages: 0,
+            tick: 0,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> PageCacheMode {
+        self.config.mode
+    }
+
+    pub(crate) fn max_pages(&self) -> usize {
+        self.config.max_pages
+    }
+
+    pub(crate) fn is_over_limit(&self) -> bool {
+        self.pages > self.config.max_pages
+    }
+
+    /// Copy cached bytes starting at `offset` into `buf`.
+    ///
+    /// Returns the bytes copied and whether end of file was reached; stops at
+    /// the first page that is not cached.
+    pub(crate) fn read(&mut self, qid_path: u64, offset: u64, buf: &mut [u8]) -> (usize, bool) {
+        self.tick += 1;
+        let tick = self.tick;
+        let Some(file) = self.files.get_mut(&qid_path) else {
+            return (0, false);
+        };
+        let last_index = file.pages.keys().next_back().copied();
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / PAGE_SIZE as u64;
+            let in_page = (pos % PAGE_SIZE as u64) as usize;
+            let Some(page) = file.pages.get_mut(&index) else {
+                return (done, false);
+            };
+            page.last_used = tick;
+            if in_page >= page.data.len() {
+                return (done, true);
+            }
+            let len = (page.data.len() - in_page).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&page.data[in_page..in_page + len]);
+            done += len;
+            if page.data.len() < PAGE_SIZE && in_page + len == page.data.len() && Some(index) == last_index {
+                return (done, true);
+            }
+        }
+        (done, false)
+    }
+
+    /// Record where a read ended so the next one can be recognised as sequential.
+    pub(crate) fn note_read(&mut self, qid_path: u64, end: u64) {
+        if let Some(file) = self.files.get_mut(&qid_path) {
+            file.next_offset = end;
+        }
+    }
+
+    /// Choose the pages to fetch for a miss at `offset`: (first page, page count).
+    ///
+    /// Sequential access doubles the read-ahead window up to the configured
+    /// maximum; random access resets it. The range stops before the next
+    /// page that is already cached.
+    pub(crate) fn plan_fetch(&mut self, qid_path: u64, offset: u64, want: usize) -> (u64, usize) {
+        let max_readahead = self.config.max_readahead;
+        let file = self.files.entry(qid_path).or_default();
+        file.window = if offset == file.next_offset && offset != 0 {
+            (file.window * 2).clamp(1, max_readahead.max(1))
+        } else if offset == 0 {
+            max_readahead.min(4)
+        } else {
+            0
+        };
+        let first = offset / PAGE_SIZE as u64;
+        let last = (offset + want.max(1) as u64 - 1) / PAGE_SIZE as u64;
+        let mut count = (last - first + 1) as usize + file.window;
+        if let Some((&next, _)) = file.pages.range(first + 1..).next() {
+            count = count.min((next - first) as usize);
+        }
+        (first, count)
+    }
+
+    /// Insert bytes fetched from the server starting at page `first`.
+    ///
+    /// Pages that are already cached are left untouched. An empty fetch
+    /// inserts nothing; the caller treats it as end of file.
+    pub(crate) fn insert_fetched(&mut self, qid_path: u64, first: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let file = self.files.entry(qid_path).or_default();
+        let count = data.len().div_ceil(PAGE_SIZE);
+        for i in 0..count {
+            let index = first + i as u64;
+            let start = (i * PAGE_SIZE).min(data.len());
+            let end = (start + PAGE_SIZE).min(data.len());
+            if file.pages.contains_key(&index) {
+                continue;
+            }
+            file.pages.insert(
+                index,
+                Page {
+                    data: data[start..end].to_vec(),
+                    dirty: None,
+                    last_used: tick,
+                },
+            );
+            self.pages += 1;
+        }
+        pad_short_pages(file);
+    }
+
+    /// Returns true if `index` is cached for `qid_path`.
+    pub(crate) fn has_page(&self, qid_path: u64, index: u64) -> bool {
+        self.files
+            .get(&qid_path)
+            .is_some_and(|file| file.pages.contains_key(&index))
+    }
+
+    /// Copy `data` into page `index` at `in_page`, creating the page if needed.
+    ///
+    /// With `dirty` the range is queued for write-back through `fid`; otherwise
+    /// only pages that are already cached are updated (write-through mode).
+    pub(crate) fn write(&mut self, qid_path: u64, fid: u32, index: u64, in_page: usize, data: &[u8], dirty: bool) {
+        self.tick += 1;
+        let tick = self.tick;
+        let file = self.files.entry(qid_path).or_default();
+        if !dirty && !file.pages.contains_key(&index) {
+            return;
+        }
+        let page = file.pages.entry(index).or_insert_with(|| {
+            self.pages += 1;
+            Page {
+                data: Vec::new(),
+                dirty: None,
+                last_used: tick,
+            }
+        });
+        page.last_used = tick;
+        let end = in_page + data.len();
+        if page.data.len() < end {
+            page.data.resize(end, 0);
+        }
+        page.data[in_page..end].copy_from_slice(data);
+        if dirty {
+            page.dirty = Some(match page.dirty {
+                Some((start, old_end)) => (start.min(in_page), old_end.max(end)),
+                None => (in_page, end),
+            });
+            file.writeback_fid = Some(fid);
+        }
+        pad_short_pages(file);
+    }
+
+    /// Take a file's state out of the cache so it can be written back.
+    pub(crate) fn take(&mut self, qid_path: u64) -> Option<CachedFile> {
+        let file = self.files.remove(&qid_path)?;
+        self.pages -= file.pages.len();
+        Some(file)
+    }
+
+    /// Return a file taken with [`Self::take`].
+    pub(crate) fn put(&mut self, qid_path: u64, file: CachedFile) {
+        self.pages += file.pages.len();
+        self.files.insert(qid_path, file);
+    }
+
+    /// Files with pages waiting for write-back.
+    pub(crate) fn dirty_files(&self) -> Vec<u64> {
+        self.files
+            .iter()
+            .filter(|(_, file)| file.has_dirty())
+            .map(|(&qid_path, _)| qid_path)
+            .collect()
+    }
+
+    /// Returns the file written back through `fid`, if any.
+    pub(crate) fn file_written_by(&self, fid: u32) -> Option<u64> {
+        self.files
+            .iter()
+            .find(|(_, file)| file.writeback_fid == Some(fid))
+            .map(|(&qid_path, _)| qid_path)
+    }
+
+    /// Forget `fid` as a write-back fid once it is clunked.
+    pub(crate) fn release_fid(&mut self, fid: u32) {
+        for file in self.files.values_mut() {
+            if file.writeback_fid == Some(fid) {
+                file.writeback_fid = None;
+            }
+        }
+    }
+
+    /// Revalidate against the qid seen by an open; drops clean pages when stale.
+    pub(crate) fn opened(&mut self, qid_path: u64, version: u32) {
+        let mmap = self.config.mode == PageCacheMode::Mmap;
+        let file = self.files.entry(qid_path).or_default();
+        let stale = mmap || file.version.is_some_and(|old| old != version);
+        file.version = Some(version);
+        if stale {
+            self.drop_clean(qid_path);
+        }
+    }
+
+    /// Revalidate against fresh attributes; drops clean pages if size or data_version moved.
+    pub(crate) fn attributes(&mut self, qid_path: u64, size: u64, data_version: Option<u64>) {
+        let file = self.files.entry(qid_path).or_default();
+        let size_changed = file.size.is_some_and(|old| old != size) && !file.has_dirty();
+        let version_changed = matches!((file.data_version, data_version), (Some(old), Some(new)) if old != new);
+        file.size = Some(size);
+        file.data_version = data_version;
+        if size_changed || version_changed {
+            self.drop_clean(qid_path);
+        }
+    }
+
+    /// Drop every page of `qid_path`, dirty or not.
+    pub(crate) fn invalidate(&mut self, qid_path: u64) {
+        if let Some(file) = self.files.remove(&qid_path) {
+            self.pages -= file.pages.len();
+        }
+    }
+
+    fn drop_clean(&mut self, qid_path: u64) {
+        if let Some(file) = self.files.get_mut(&qid_path) {
+            let before = file.pages.len();
+            file.pages.retain(|_, page| page.dirty.is_some());
+            self.pages -= before - file.pages.len();
+        }
+    }
+
+    /// Evict least recently used clean pages until at most `target` remain.
+    pub(crate) fn evict_clean(&mut self, target: usize) {
+        if self.pages <= target {
+            return;
+        }
+        let mut clean: Vec<(u64, u64, u64)> = self
+            .files
+            .iter()
+            .flat_map(|(&qid_path, file)| {
+                file.pages
+                    .iter()
+                    .filter(|(_, page)| page.dirty.is_none())
+                    .map(move |(&index, page)| (page.last_used, qid_path, index))
+            })
+            .collect();
+        clean.sort_unstable();
+        for (_, qid_path, index) in clean {
+            if self.pages <= target {
+                break;
+            }
+            if let Some(file) = self.files.get_mut(&qid_path)
+                && file.pages.remove(&index).is_some()
+            {
+                self.pages -= 1;
+            }
+        }
+        self.files
+            .retain(|_, file| !file.pages.is_empty() || file.writeback_fid.is_some());
+    }
+}
+
+/// Zero-fill short pages that are followed by later pages, since only the last
+/// cached page may mark end of file.
+fn pad_short_pages(file: &mut CachedFile) {
+    let Some(&last) = file.pages.keys().next_back() else {
+        return;
+    };
+    for page in file.pages.range_mut(..last).map(|(_, page)| page) {
+        if page.data.len() < PAGE_SIZE {
+            page.data.resize(PAGE_SIZE, 0);
+        }
+    }
+}
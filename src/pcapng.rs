@@ -0,0 +1,310 @@
+//! pcapng capture of 9P traffic as synthetic TCP for Wireshark.
+//!
+//! Each exchange is written as IPv4/TCP segments between a client at
+//! 10.0.0.1:40000 and a server at 10.0.0.2:564, so Wireshark's 9P dissector
+//! decodes them without further configuration. The capture opens with a TCP
+//! handshake so the conversation reassembles cleanly.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::clock::Clock;
+use crate::stream::ByteStream;
+use crate::transport::Transport;
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 1;
+const BLOCK_EPB: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Raw IPv4/IPv6 packets with no link-layer header.
+const LINKTYPE_RAW: u16 = 101;
+
+const CLIENT_ADDR: [u8; 4] = [10, 0, 0, 1];
+const SERVER_ADDR: [u8; 4] = [10, 0, 0, 2];
+const CLIENT_PORT: u16 = 40000;
+const SERVER_PORT: u16 = 564;
+
+const IP_HDR_LEN: usize = 20;
+const TCP_HDR_LEN: usize = 20;
+/// Largest payload that fits an IPv4 packet's 16-bit total length.
+const MAX_SEGMENT: usize = u16::MAX as usize - IP_HDR_LEN - TCP_HDR_LEN;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+struct CaptureState<S> {
+    sink: S,
+    client_seq: u32,
+    server_seq: u32,
+    ip_id: u16,
+}
+
+/// Transport wrapper that writes every request and reply to a pcapng capture.
+///
+/// Failing to write the capture fails the request. Timestamps come from the
+/// optional clock and are zero without one.
+pub struct CaptureTransport<T, S> {
+    inner: T,
+    clock: Option<Box<dyn Clock>>,
+    state: Mutex<CaptureState<S>>,
+}
+
+impl<T: Transport, S: ByteStream + Send> CaptureTransport<T, S> {
+    /// Capture traffic on `inner` to `sink`, writing the section header,
+    /// interface description and TCP handshake immediately.
+    pub fn new(inner: T, sink: S, clock: Option<Box<dyn Clock>>) -> Result<Self, String> {
+        let capture = Self {
+            inner,
+            clock,
+            state: Mutex::new(CaptureState {
+                sink,
+                client_seq: 0,
+                server_seq: 0,
+                ip_id: 0,
+            }),
+        };
+        {
+            let mut state = capture.state.lock();
+            write_block(&mut state.sink, BLOCK_SHB, &section_header())?;
+            write_block(&mut state.sink, BLOCK_IDB, &interface_description())?;
+            let ts = capture.timestamp();
+            capture.segment(&mut state, ts, true, TCP_SYN, &[])?;
+            state.client_seq = state.client_seq.wrapping_add(1);
+            capture.segment(&mut state, ts, false, TCP_SYN | TCP_ACK, &[])?;
+            state.server_seq = state.server_seq.wrapping_add(1);
+            capture.segment(&mut state, ts, true, TCP_ACK, &[])?;
+            state.sink.flush()?;
+        }
+        Ok(capture)
+    }
+
+    /// Return the wrapped transport and the capture sink.
+    pub fn into_inner(self) -> (T, S) {
+        (self.inner, self.state.into_inner().sink)
+    }
+
+    /// Current time in microseconds, the pcapng default resolution.
+    fn timestamp(&self) -> u64 {
+        self.clock
+            .as_ref()
+            .map(|clock| clock.now().as_micros() as u64)
+            .unwrap_or(0)
+    }
+
+    /// Write one TCP segment in the given direction and advance its sequence number.
+    fn segment(&self, state: &mut CaptureState<S>, ts: u64, from_client: bool, flags: u8, payload: &[u8]) -> Result<(), String> {
+        let (seq, ack) = if from_client {
+            (state.client_seq, state.server_seq)
+        } else {
+            (state.server_seq, state.client_seq)
+        };
+        let ack = if flags == TCP_SYN { 0 } else { ack };
+        let packet = tcp_packet(from_client, state.ip_id, seq, ack, flags, payload);
+        state.ip_id = state.ip_id.wrapping_add(1);
+        let seq = seq.wrapping_add(payload.len() as u32);
+        if from_client {
+            state.client_seq = seq;
+        } else {
+            state.server_seq = seq;
+        }
+        write_block(&mut state.sink, BLOCK_EPB, &enhanced_packet(ts, &packet))
+    }
+
+    /// Write a message as one or more PSH/ACK segments.
+    fn message(&self, state: &mut CaptureState<S>, ts: u64, from_client: bool, parts: &[&[u8]]) -> Result<(), String> {
+        let joined: Vec<u8>;
+        let msg = if let [part] = parts {
+            *part
+        } else {
+            joined = parts.concat();
+            &joined
+        };
+        for chunk in msg.chunks(MAX_SEGMENT) {
+            self.segment(state, ts, from_client, TCP_PSH | TCP_ACK, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn capture(&self, req: &[&[u8]], reply: &Result<usize, String>, resp: &[&[u8]], sent_at: u64) -> Result<(), String> {
+        let mut state = self.state.lock();
+        self.message(&mut state, sent_at, true, req)?;
+        if let Ok(size) = reply {
+            let mut left = *size;
+            let mut parts = Vec::with_capacity(resp.len());
+            for part in resp {
+                let take = left.min(part.len());
+                parts.push(&part[..take]);
+                left -= take;
+            }
+            self.message(&mut state, self.timestamp(), false, &parts)?;
+        }
+        state.sink.flush()
+    }
+
+    /// Capture every request of a batch, then every reply.
+    fn capture_batch(&self, reqs: &[&[u8]], reply: &Result<Vec<usize>, String>, resps: &[&mut [u8]], sent_at: u64) -> Result<(), String> {
+        let mut state = self.state.lock();
+        for req in reqs {
+            self.message(&mut state, sent_at, true, &[req])?;
+        }
+        if let Ok(sizes) = reply {
+            let received_at = self.timestamp();
+            for (resp, size) in resps.iter().zip(sizes) {
+                self.message(&mut state, received_at, false, &[&resp[..(*size).min(resp.len())]])?;
+            }
+        }
+        state.sink.flush()
+    }
+}
+
+impl<T: Transport, S: ByteStream + Send> Transport for CaptureTransport<T, S> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let sent_at = self.timestamp();
+        let reply = self.inner.request(req, resp);
+        self.capture(&[req], &reply, &[resp], sent_at)
+            .map_err(|err| format!("capture write failed: {}", err))?;
+        reply
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let sent_at = self.timestamp();
+        let reply = self
+            .inner
+            .request_vectored(req_head, req_data, resp_head, resp_data);
+        let mut req = Vec::with_capacity(req_data.len() + 1);
+        req.push(req_head);
+        req.extend_from_slice(req_data);
+        self.capture(&req, &reply, &[resp_head, resp_data], sent_at)
+            .map_err(|err| format!("capture write failed: {}", err))?;
+        reply
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        let sent_at = self.timestamp();
+        let reply = self.inner.request_batch(reqs, resps);
+        self.capture_batch(reqs, &reply, resps, sent_at)
+            .map_err(|err| format!("capture write failed: {}", err))?;
+        reply
+    }
+}
+
+/// Write a block: type, total length, body padded to 32 bits, total length.
+fn write_block<S: ByteStream>(sink: &mut S, block_type: u32, body: &[u8]) -> Result<(), String> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total = (12 + body.len() + padding) as u32;
+    sink.write_all(&block_type.to_le_bytes())?;
+    sink.write_all(&total.to_le_bytes())?;
+    sink.write_all(body)?;
+    sink.write_all(&[0u8; 3][..padding])?;
+    sink.write_all(&total.to_le_bytes())
+}
+
+fn section_header() -> Vec<u8> {
+    let mut body = Vec::with_capacity(16);
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // Section length is unknown while streaming.
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    body
+}
+
+fn interface_description() -> Vec<u8> {
+    let mut body = Vec::with_capacity(8);
+    body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // No snap length limit.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body
+}
+
+fn enhanced_packet(ts: u64, packet: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(20 + packet.len());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(ts as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    body
+}
+
+/// Build an IPv4 packet carrying one TCP segment, with valid checksums.
+fn tcp_packet(from_client: bool, ip_id: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let (src, dst, sport, dport) = if from_client {
+        (CLIENT_ADDR, SERVER_ADDR, CLIENT_PORT, SERVER_PORT)
+    } else {
+        (SERVER_ADDR, CLIENT_ADDR, SERVER_PORT, CLIENT_PORT)
+    };
+    let tcp_len = TCP_HDR_LEN + payload.len();
+    let total = IP_HDR_LEN + tcp_len;
+    let mut packet = Vec::with_capacity(total);
+
+    packet.push(0x45);
+    packet.push(0);
+    packet.extend_from_slice(&(total as u16).to_be_bytes());
+    packet.extend_from_slice(&ip_id.to_be_bytes());
+    // Don't fragment.
+    packet.extend_from_slice(&0x4000u16.to_be_bytes());
+    packet.push(64);
+    packet.push(6);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src);
+    packet.extend_from_slice(&dst);
+    let ip_sum = checksum(0, &packet[..IP_HDR_LEN]);
+    packet[10..12].copy_from_slice(&ip_sum.to_be_bytes());
+
+    packet.extend_from_slice(&sport.to_be_bytes());
+    packet.extend_from_slice(&dport.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(&ack.to_be_bytes());
+    packet.push((TCP_HDR_LEN as u8 / 4) << 4);
+    packet.push(flags);
+    packet.extend_from_slice(&u16::MAX.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    packet.extend_from_slice(payload);
+
+    let mut pseudo = [0u8; 12];
+    pseudo[..4].copy_from_slice(&src);
+    pseudo[4..8].copy_from_slice(&dst);
+    pseudo[9] = 6;
+    pseudo[10..12].copy_from_slice(&(tcp_len as u16).to_be_bytes());
+    let tcp_sum = checksum(sum_words(0, &pseudo), &packet[IP_HDR_LEN..]);
+    packet[IP_HDR_LEN + 16..IP_HDR_LEN + 18].copy_from_slice(&tcp_sum.to_be_bytes());
+    packet
+}
+
+/// Add `data` to a ones-complement sum as big-endian 16-bit words.
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum = sum.wrapping_add(u32::from(u16::from_be_bytes([word[0], word[1]])));
+    }
+    if let [last] = words.remainder() {
+        sum = sum.wrapping_add(u32::from(*last) << 8);
+    }
+    sum
+}
+
+/// Internet checksum of `data` continuing from a partial `sum`.
+fn checksum(sum: u32, data: &[u8]) -> u16 {
+    let mut sum = sum_words(sum, data);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,175 @@
+//! Transport speaking 9P over a child process's stdin/stdout.
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::stream::{io_error, StdStream, StreamTransport};
+use crate::transport::Transport;
+
+/// Bytes of the child's stderr kept for error reports.
+const STDERR_TAIL: usize = 4096;
+
+/// How long a closed pipe waits for the child to exit and finish its stderr.
+const EXIT_GRACE: Duration = Duration::from_millis(500);
+
+/// Recent stderr output, and whether the pipe has reached end of file.
+#[derive(Default)]
+struct StderrTail {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+/// Transport that spawns a server process and talks 9P on its stdin/stdout.
+///
+/// The child's stderr is drained in the background; its exit status and the
+/// tail of its stderr are appended to transport errors. The child is killed
+/// when the transport is dropped.
+pub struct ProcessTransport {
+    stream: StreamTransport<StdStream<ChildPipes>>,
+    child: Mutex<Child>,
+    stderr: Arc<Mutex<StderrTail>>,
+}
+
+impl ProcessTransport {
+    /// Spawn `command` with piped stdio, accepting replies up to `max_size` bytes.
+    pub fn spawn(command: &mut Command, max_size: u32) -> Result<Self, String> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("spawn failed: {}", err))?;
+        let pipes = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => ChildPipes { stdin, stdout },
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(String::from("spawn failed: missing stdio pipes"));
+            }
+        };
+
+        let stderr = Arc::new(Mutex::new(StderrTail::default()));
+        if let Some(mut pipe) = child.stderr.take() {
+            let tail = Arc::clone(&stderr);
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                while let Ok(len) = pipe.read(&mut buf) {
+                    if len == 0 {
+                        break;
+                    }
+                    let bytes = &mut tail.lock().bytes;
+                    bytes.extend(&buf[..len]);
+                    let excess = bytes.len().saturating_sub(STDERR_TAIL);
+                    bytes.drain(..excess);
+                }
+                tail.lock().closed = true;
+            });
+        } else {
+            stderr.lock().closed = true;
+        }
+
+        Ok(Self {
+            stream: StreamTransport::new(StdStream(pipes), max_size),
+            child: Mutex::new(child),
+            stderr,
+        })
+    }
+
+    /// Exit status of the child, if it has exited.
+    pub fn try_status(&self) -> Result<Option<ExitStatus>, String> {
+        self.child.lock().try_wait().map_err(io_error)
+    }
+
+    /// The most recent output the child wrote to stderr.
+    pub fn stderr_tail(&self) -> String {
+        let tail: Vec<u8> = self.stderr.lock().bytes.iter().copied().collect();
+        String::from_utf8_lossy(&tail).trim_end().into()
+    }
+
+    /// Append the child's exit status and stderr tail to a transport error.
+    ///
+    /// A closed pipe usually means the child is exiting, so give it a moment
+    /// to finish and flush its stderr before reporting.
+    fn annotate(&self, err: String) -> String {
+        if err.starts_with("transport closed") {
+            let deadline = Instant::now() + EXIT_GRACE;
+            while !(self.stderr.lock().closed && matches!(self.try_status(), Ok(Some(_)))) && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        let mut out = err;
+        if let Ok(Some(status)) = self.try_status() {
+            out.push_str(&format!(" (child exited: {})", status));
+        }
+        let tail = self.stderr_tail();
+        if !tail.is_empty() {
+            out.push_str(&format!(" (stderr: {})", tail));
+        }
+        out
+    }
+}
+
+impl Transport for ProcessTransport {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.stream.request(req, resp).map_err(|err| self.annotate(err))
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        self.stream
+            .request_vectored(req_head, req_data, resp_head, resp_data)
+            .map_err(|err| self.annotate(err))
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.stream
+            .request_batch(reqs, resps)
+            .map_err(|err| self.annotate(err))
+    }
+}
+
+impl Drop for ProcessTransport {
+    fn drop(&mut self) {
+        let child = self.child.get_mut();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// The child's stdin and stdout as one duplex stream.
+struct ChildPipes {
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl Read for ChildPipes {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for ChildPipes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.flush()
+    }
+}
@@ -46,6 +46,30 @@ pub const TREADDIR: u8 = 40;
 pub const RREADDIR: u8 = 41;
 pub const TFSYNC: u8 = 50;
 pub const RFSYNC: u8 = 51;
+pub const TSTATFS: u8 = 8;
+pub const RSTATFS: u8 = 9;
+pub const TMKNOD: u8 = 18;
+pub const RMKNOD: u8 = 19;
+pub const TXATTRWALK: u8 = 30;
+pub const RXATTRWALK: u8 = 31;
+pub const TXATTRCREATE: u8 = 32;
+pub const RXATTRCREATE: u8 = 33;
+pub const TLOCK: u8 = 52;
+pub const RLOCK: u8 = 53;
+pub const TGETLOCK: u8 = 54;
+pub const RGETLOCK: u8 = 55;
+pub const TRENAMEAT: u8 = 74;
+pub const RRENAMEAT: u8 = 75;
+pub const TUNLINKAT: u8 = 76;
+pub const RUNLINKAT: u8 = 77;
+pub const TAUTH: u8 = 102;
+pub const RAUTH: u8 = 103;
+pub const TFLUSH: u8 = 108;
+pub const RFLUSH: u8 = 109;
+pub const TSTAT: u8 = 124;
+pub const RSTAT: u8 = 125;
+pub const TWSTAT: u8 = 126;
+pub const RWSTAT: u8 = 127;
 
 pub const OREAD: u8 = 0;
 #[allow(dead_code)]
@@ -67,15 +91,116 @@ pub const P9_DOTL_APPEND: u32 = 0x2000;
 pub const P9_ATTR_SIZE: u32 = 1 << 3;
 pub const P9_SETATTR_MODE: u32 = 1;
 pub const P9_STATS_BASIC: u64 = 0x000007ff;
+pub const P9_STATS_DATA_VERSION: u64 = 0x00002000;
+
+pub const ENOENT: u32 = 2;
+pub const ENOTDIR: u32 = 20;
+pub const ELOOP: u32 = 40;
+
+/// Most symlinks expanded while resolving one path, as Linux's MAXSYMLINKS.
+pub const SYMLOOP_MAX: u32 = 40;
 
 pub const DMDIR: u32 = 0x8000_0000;
+/// Directory file type bit of a 9P2000.L mode.
+pub const S_IFDIR: u32 = 0o040000;
+
+/// Most names a single Twalk may carry.
+pub const MAXWELEM: usize = 16;
 
 pub const DEFAULT_MSIZE: u32 = 16384;
+/// Smallest msize a server may negotiate.
+pub const MIN_MSIZE: u32 = 256;
+/// Size of the TREAD/TWRITE header that precedes the payload.
+pub const P9_IOHDRSZ: u32 = 24;
+/// Length of an RREAD reply before its payload: size, type, tag, count.
+pub const RREAD_HDR: usize = 11;
+/// Length of a complete RWRITE reply.
+pub const RWRITE_LEN: usize = 11;
+/// Reads below this many bytes use the session's reply buffer, where an error reply always fits.
+pub const SMALL_READ: usize = 256;
+/// Reply buffer for batched TWALK/TGETATTR/TCLUNK; RGETATTR is the largest at 160.
+pub const BATCH_REPLY_MAX: usize = 256;
+/// Directory entries whose attributes are fetched per batch.
+pub const READDIR_PLUS_BATCH: usize = 32;
 
 /// Qid identifies a file within a 9P server.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct Qid {
-    pub(crate) type_: u8,
-    pub(crate) _version: u32,
-    pub(crate) _path: u64,
+    /// Type bits: 0x80 = directory, 0x02 = symlink, 0x00 = regular file.
+    pub type_: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+/// Name of a message type for diagnostics.
+pub(crate) fn message_name(msg_type: u8) -> &'static str {
+    match msg_type {
+        TVERSION => "Tversion",
+        RVERSION => "Rversion",
+        TATTACH => "Tattach",
+        RATTACH => "Rattach",
+        RERROR => "Rerror",
+        RLERROR => "Rlerror",
+        TREMOVE => "Tremove",
+        RREMOVE => "Rremove",
+        TWALK => "Twalk",
+        RWALK => "Rwalk",
+        TOPEN => "Topen",
+        ROPEN => "Ropen",
+        TCREATE => "Tcreate",
+        RCREATE => "Rcreate",
+        TREAD => "Tread",
+        RREAD => "Rread",
+        TWRITE => "Twrite",
+        RWRITE => "Rwrite",
+        TCLUNK => "Tclunk",
+        RCLUNK => "Rclunk",
+        TREADLINK => "Treadlink",
+        RREADLINK => "Rreadlink",
+        TLINK => "Tlink",
+        RLINK => "Rlink",
+        TMKDIR => "Tmkdir",
+        RMKDIR => "Rmkdir",
+        TSYMLINK => "Tsymlink",
+        RSYMLINK => "Rsymlink",
+        TSETATTR => "Tsetattr",
+        RSETATTR => "Rsetattr",
+        TLOPEN => "Tlopen",
+        RLOPEN => "Rlopen",
+        TLCREATE => "Tlcreate",
+        RLCREATE => "Rlcreate",
+        TGETATTR => "Tgetattr",
+        RGETATTR => "Rgetattr",
+        TRENAME => "Trename",
+        RRENAME => "Rrename",
+        TREADDIR => "Treaddir",
+        RREADDIR => "Rreaddir",
+        TFSYNC => "Tfsync",
+        RFSYNC => "Rfsync",
+        TSTATFS => "Tstatfs",
+        RSTATFS => "Rstatfs",
+        TMKNOD => "Tmknod",
+        RMKNOD => "Rmknod",
+        TXATTRWALK => "Txattrwalk",
+        RXATTRWALK => "Rxattrwalk",
+        TXATTRCREATE => "Txattrcreate",
+        RXATTRCREATE => "Rxattrcreate",
+        TLOCK => "Tlock",
+        RLOCK => "Rlock",
+        TGETLOCK => "Tgetlock",
+        RGETLOCK => "Rgetlock",
+        TRENAMEAT => "Trenameat",
+        RRENAMEAT => "Rrenameat",
+        TUNLINKAT => "Tunlinkat",
+        RUNLINKAT => "Runlinkat",
+        TAUTH => "Tauth",
+        RAUTH => "Rauth",
+        TFLUSH => "Tflush",
+        RFLUSH => "Rflush",
+        TSTAT => "Tstat",
+        RSTAT => "Rstat",
+        TWSTAT => "Twstat",
+        RWSTAT => "Rwstat",
+        _ => "unknown",
+    }
 }
@@ -0,0 +1,99 @@
+//! Lazy, resumable directory cursor.
+
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+
+use crate::session::{P9DirEntry, P9Session};
+
+/// Iterator over the entries of an open directory fid.
+///
+/// Entries are fetched one `Treaddir` (or `Tread` on 9P2000/.u) at a time and
+/// include `.` and `..` as the server sends them. Iteration ends only when the
+/// server returns an empty reply; cookies are treated as opaque, so servers
+/// with hash-ordered offsets are listed completely. A chunk ending on a cookie
+/// already requested would start a cycle, so iteration fails there instead.
+///
+/// On 9P2000 and 9P2000.u the cookie is a byte offset into the directory
+/// stream, and servers usually accept only 0 or the end of the previous read.
+pub struct ReadDir<'a> {
+    session: &'a mut P9Session,
+    fid: u32,
+    owned: bool,
+    cookie: u64,
+    requested: BTreeSet<u64>,
+    buf: VecDeque<P9DirEntry>,
+    done: bool,
+}
+
+impl<'a> ReadDir<'a> {
+    pub(crate) fn new(session: &'a mut P9Session, fid: u32, owned: bool) -> Self {
+        Self {
+            session,
+            fid,
+            owned,
+            cookie: 0,
+            requested: BTreeSet::new(),
+            buf: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// The directory fid being read.
+    pub fn fid(&self) -> u32 {
+        self.fid
+    }
+
+    /// Cookie after the last entry yielded; pass it to [`ReadDir::seek`] to resume.
+    pub fn tell(&self) -> u64 {
+        self.cookie
+    }
+
+    /// Restart iteration at `cookie`, as returned by [`ReadDir::tell`] or
+    /// [`P9DirEntry::cookie`]. A cookie of 0 rewinds to the start.
+    pub fn seek(&mut self, cookie: u64) {
+        self.buf.clear();
+        self.requested.clear();
+        self.cookie = cookie;
+        self.done = false;
+    }
+}
+
+impl Iterator for ReadDir<'_> {
+    type Item = Result<P9DirEntry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.buf.pop_front() {
+                self.cookie = entry.cookie;
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+            self.requested.insert(self.cookie);
+            if let Err(err) = self.session.read_dir_chunk(self.fid, self.cookie, &mut self.buf) {
+                self.done = true;
+                return Some(Err(err));
+            }
+            if self.buf.is_empty() {
+                self.done = true;
+            } else if let Some(next) = self.buf.back().map(|entry| entry.cookie)
+                && self.requested.contains(&next)
+            {
+                // The next request would repeat an earlier one and never finish.
+                self.buf.clear();
+                self.done = true;
+                return Some(Err(format!("readdir cookie {} repeated", next)));
+            }
+        }
+    }
+}
+
+impl Drop for ReadDir<'_> {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = self.session.clunk(self.fid);
+        }
+    }
+}
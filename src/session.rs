@@ -1,18 +1,36 @@
 //! 9P session state and high-level operations.
 
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::format;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
-use log::warn;
-
-use crate::message::{dump_hex, read_qid, read_str, read_u8, read_u16, read_u32, read_u64, Message};
-use crate::parse::{parse_dir_entries, parse_dir_entries_l, path_parts, split_parent_name};
+use core::mem;
+use core::time::Duration;
+use log::{debug, warn};
+
+use crate::acache::{AttrCache, AttrCacheConfig, CacheMode};
+use crate::clock::Clock;
+use crate::dcache::{DentryCache, DentryCacheConfig};
+use crate::codec::{decode_header, Attr, Dialect, Rmessage, SetAttr, Tmessage, WalkList, HEADER_LEN};
+use crate::message::{dump_hex, read_u32};
+use crate::pcache::{PageCache, PageCacheConfig, PageCacheMode, PAGE_SIZE};
+use crate::parse::{
+    lossy, normalize_path, parse_dir_entries, parse_dir_entries_l, path_key, plain_components,
+    split_parent_name, DotDotPolicy,
+};
 use crate::protocol::*;
+use crate::readdir::ReadDir;
 use crate::transport::Transport;
+use crate::walktree::{WalkTree, WalkTreeOptions};
 
 /// A single 9P connection/session.
+///
+/// Paths and names are byte strings, since servers may export names that are
+/// not UTF-8: path arguments accept `&str`, `&[u8]` or anything else that is
+/// `AsRef<[u8]>`, and names come back as `Vec<u8>`.
 pub struct P9Session {
     msize: u32,
     next_tag: u16,
@@ -21,9 +39,36 @@ pub struct P9Session {
     mount_tag: String,
     /// Negotiated 9P protocol version from TVERSION/RVERSION.
     p9_version: P9Version,
+    /// Qid and iounit reported by TOPEN/TCREATE for each open fid.
+    open_fids: BTreeMap<u32, OpenFid>,
+    /// Reusable request and response buffers shared by every operation.
+    req_buf: Vec<u8>,
+    resp_buf: Vec<u8>,
+    /// Optional cache of walked directory fids.
+    dcache: Option<DentryCache>,
+    /// Optional cache of TGETATTR results.
+    acache: Option<AttrCache>,
+    /// Optional page cache for regular files.
+    pcache: Option<PageCache>,
+    clock: Option<Box<dyn Clock>>,
+    /// How `..` in paths is resolved.
+    dotdot: DotDotPolicy,
+    /// Whether path lookups expand symlinks on the client.
+    follow_symlinks: bool,
     transport: Box<dyn Transport>,
 }
 
+/// A fid from symlink resolution, the qid it names and its symlink-free
+/// components below the attach root.
+type Resolved = (u32, Option<Qid>, Vec<Vec<u8>>);
+
+/// State kept for fids opened through the session.
+#[derive(Clone, Copy, Debug)]
+struct OpenFid {
+    qid: Qid,
+    iounit: u32,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum P9Version {
     Unknown,
@@ -46,6 +91,15 @@ impl P9Version {
     fn is_dotl(self) -> bool {
         matches!(self, P9Version::P2000L)
     }
+
+    /// Codec dialect for this version; plain 9P2000 until negotiated.
+    fn dialect(self) -> Dialect {
+        match self {
+            P9Version::P2000L => Dialect::P2000L,
+            P9Version::P2000U => Dialect::P2000U,
+            P9Version::Unknown | P9Version::P2000 => Dialect::P2000,
+        }
+    }
 }
 
 /// File attributes returned by TGETATTR.
@@ -61,21 +115,61 @@ pub struct FileAttr {
     pub atime_sec: u64,
     pub mtime_sec: u64,
     pub ctime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_nsec: u64,
+    pub ctime_nsec: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    /// Server-unique file identity (qid.path).
+    pub qid_path: u64,
+    /// qid.version at the time the attributes were fetched.
+    pub qid_version: u32,
+    /// Data version counter, if the server reports one.
+    pub data_version: Option<u64>,
 }
 
 /// A directory entry with type information from structured readdir.
 #[derive(Clone, Debug)]
 pub struct P9DirEntry {
-    pub name: String,
+    /// Name as sent by the server, which need not be UTF-8.
+    pub name: Vec<u8>,
     /// d_type from dirent: 4=dir, 8=file, 10=symlink, 0=unknown.
     pub entry_type: u8,
+    /// Server identity of the entry.
+    pub qid: Qid,
+    /// Opaque position just past this entry, for [`ReadDir::seek`].
+    pub cookie: u64,
+}
+
+impl P9DirEntry {
+    /// The name for display, with invalid UTF-8 replaced by U+FFFD.
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        lossy(&self.name)
+    }
 }
 
 impl P9Session {
+    pub(crate) fn is_dotl(&self) -> bool {
+        self.p9_version.is_dotl()
+    }
+
     fn max_read_count(&self) -> u32 {
         // Leave headroom for 9P headers and directory entry parsing.
         self.msize.saturating_sub(64)
     }
+
+    fn max_write_count(&self) -> u32 {
+        self.msize.saturating_sub(P9_IOHDRSZ)
+    }
+
+    /// Clamp a transfer size to the iounit of `fid`, if the server reported one.
+    fn io_count(&self, fid: u32, max_count: u32) -> u32 {
+        match self.open_fids.get(&fid) {
+            Some(open) if open.iounit != 0 => open.iounit.min(max_count),
+            _ => max_count,
+        }
+    }
+
     /// Create a new session with the given transport and mount tag.
     pub fn new(transport: Box<dyn Transport>, mount_tag: String) -> Self {
         Self {
@@ -85,10 +179,119 @@ impl P9Session {
             root_fid: 1,
             mount_tag,
             p9_version: P9Version::Unknown,
+            open_fids: BTreeMap::new(),
+            req_buf: Vec::with_capacity(DEFAULT_MSIZE as usize),
+            resp_buf: vec![0u8; DEFAULT_MSIZE as usize],
+            dcache: None,
+            acache: None,
+            pcache: None,
+            clock: None,
+            dotdot: DotDotPolicy::default(),
+            follow_symlinks: false,
             transport,
         }
     }
 
+    /// Install the clock used to expire cache entries.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    /// Choose how `..` components in paths are resolved.
+    ///
+    /// The policy applies to paths passed to the session. `..` in symlink
+    /// targets read from the server is clamped at the attach root instead.
+    pub fn set_dotdot_policy(&mut self, policy: DotDotPolicy) {
+        self.dotdot = policy;
+    }
+
+    /// Expand symlinks on the client when looking up paths (9P2000.L).
+    ///
+    /// Links anywhere before the last component are always followed. A final
+    /// link is followed by metadata, open and directory calls, while
+    /// [`Self::read_link`], [`Self::remove_path`], [`Self::rename_path`] and
+    /// the target of [`Self::link`] act on the link itself.
+    pub fn set_follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Keep fids for recently walked directories so lookups start from the deepest cached ancestor.
+    ///
+    /// Negative entries additionally require a clock (see [`Self::set_clock`]).
+    /// A capacity of zero disables the cache.
+    pub fn enable_dentry_cache(&mut self, config: DentryCacheConfig) {
+        self.disable_dentry_cache();
+        if config.capacity > 0 {
+            self.dcache = Some(DentryCache::new(config));
+        }
+    }
+
+    /// Cache TGETATTR results according to `config.mode`; [`CacheMode::None`] disables the cache.
+    ///
+    /// TTL expiry requires a clock (see [`Self::set_clock`]); without one,
+    /// entries live until qid.version changes or they are invalidated.
+    pub fn enable_attr_cache(&mut self, config: AttrCacheConfig) {
+        self.acache = match config.mode {
+            CacheMode::None => None,
+            _ if config.capacity == 0 => None,
+            _ => Some(AttrCache::new(config)),
+        };
+    }
+
+    /// Drop all cached attributes and stop caching.
+    pub fn disable_attr_cache(&mut self) {
+        self.acache = None;
+    }
+
+    /// Cache file pages for regular files opened through the session.
+    ///
+    /// Dirty pages are written back on [`Self::fsync_fid`], [`Self::close_fid`],
+    /// [`Self::flush_page_cache`] and when the cache grows past `max_pages`.
+    pub fn enable_page_cache(&mut self, config: PageCacheConfig) -> Result<(), String> {
+        self.disable_page_cache()?;
+        if config.max_pages > 0 {
+            self.pcache = Some(PageCache::new(config));
+        }
+        Ok(())
+    }
+
+    /// Write back dirty pages and drop the page cache.
+    pub fn disable_page_cache(&mut self) -> Result<(), String> {
+        self.flush_page_cache()?;
+        self.pcache = None;
+        Ok(())
+    }
+
+    /// Write back every dirty page.
+    pub fn flush_page_cache(&mut self) -> Result<(), String> {
+        let files = match self.pcache.as_ref() {
+            Some(cache) => cache.dirty_files(),
+            None => return Ok(()),
+        };
+        for qid_path in files {
+            self.flush_file(qid_path)?;
+        }
+        Ok(())
+    }
+
+    /// Write back dirty pages and evict clean ones until at most `max_pages` remain.
+    ///
+    /// Intended for memory-pressure callbacks of the embedding kernel.
+    pub fn shrink_page_cache(&mut self, max_pages: usize) -> Result<(), String> {
+        self.flush_page_cache()?;
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.evict_clean(max_pages);
+        }
+        Ok(())
+    }
+
+    /// Drop the walk fid cache, clunking every fid it holds.
+    pub fn disable_dentry_cache(&mut self) {
+        if let Some(mut cache) = self.dcache.take() {
+            self.clunk_all(cache.drain());
+        }
+    }
+
     /// Negotiate protocol version and attach to the server root.
     pub fn negotiate(&mut self) -> Result<(), String> {
         let mut last_version = String::from("unknown");
@@ -114,44 +317,44 @@ impl P9Session {
     }
 
     /// List directory entries at the provided path.
-    pub fn list_dir(&mut self, path: &str) -> Result<Vec<String>, String> {
+    pub fn list_dir(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<Vec<u8>>, String> {
+        let mut names = Vec::new();
+        for entry in self.read_dir(path)? {
+            let entry = entry?;
+            if entry.name != b"." && entry.name != b".." {
+                names.push(entry.name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Open the directory at `path` and iterate its entries lazily.
+    ///
+    /// The directory fid is clunked when the iterator is dropped.
+    pub fn read_dir(&mut self, path: impl AsRef<[u8]>) -> Result<ReadDir<'_>, String> {
+        let path = path.as_ref();
         let (fid, is_dir) = self.walk_path(path)?;
         if !is_dir {
             self.clunk(fid)?;
             return Err(String::from("not a directory"));
         }
-
-        self.open_with_flags(fid, OREAD, P9_DOTL_RDONLY)?;
-
-        let mut offset = 0u64;
-        let mut names = Vec::new();
-        loop {
-            if self.p9_version.is_dotl() {
-                let (chunk, next_offset) = self.readdir(fid, offset, self.msize - 64)?;
-                if chunk.is_empty() {
-                    break;
-                }
-                names.extend(chunk);
-                match next_offset {
-                    Some(next) if next > offset => offset = next,
-                    _ => break,
-                }
-            } else {
-                let data = self.read(fid, offset, self.msize - 64)?;
-                if data.is_empty() {
-                    break;
-                }
-                offset += data.len() as u64;
-                parse_dir_entries(&data, &mut names)?;
-            }
+        if let Err(err) = self.open_with_flags(fid, OREAD, P9_DOTL_RDONLY) {
+            let _ = self.clunk(fid);
+            return Err(err);
         }
+        Ok(ReadDir::new(self, fid, true))
+    }
 
-        self.clunk(fid)?;
-        Ok(names)
+    /// Iterate the entries of a directory fid the caller already opened.
+    ///
+    /// The fid stays open when the iterator is dropped.
+    pub fn read_dir_fid(&mut self, fid: u32) -> ReadDir<'_> {
+        ReadDir::new(self, fid, false)
     }
 
     /// Ensure the path points to a directory.
-    pub fn ensure_dir(&mut self, path: &str) -> Result<(), String> {
+    pub fn ensure_dir(&mut self, path: impl AsRef<[u8]>) -> Result<(), String> {
+        let path = path.as_ref();
         let (fid, is_dir) = self.walk_path(path)?;
         self.clunk(fid)?;
         if is_dir {
@@ -162,24 +365,30 @@ impl P9Session {
     }
 
     /// Create a directory at `path`.
-    pub fn create_dir(&mut self, path: &str) -> Result<(), String> {
-        let (parent, name) = split_parent_name(path)?;
-        let (fid, is_dir) = self.walk_path(parent)?;
+    pub fn create_dir(&mut self, path: impl AsRef<[u8]>) -> Result<(), String> {
+        let path = path.as_ref();
+        let (parent, name) = split_parent_name(path, self.dotdot)?;
+        let (fid, is_dir) = self.walk_path(&parent)?;
         if !is_dir {
             self.clunk(fid)?;
             return Err(String::from("parent is not a directory"));
         }
 
-        if self.p9_version.is_dotl() {
-            self.mkdir(fid, name, DMDIR | 0o755, 0)?;
+        let result = if self.p9_version.is_dotl() {
+            self.mkdir(fid, name, S_IFDIR | 0o755, 0)
         } else {
-            self.create(fid, name, OREAD, DMDIR | 0o755)?;
+            self.create(fid, name, OREAD, DMDIR | 0o755)
+        };
+        if result.is_ok() {
+            self.dcache_created(path);
         }
-        self.clunk(fid)?;
-        Ok(())
+        let clunked = self.clunk(fid);
+        result?;
+        clunked
     }
 
-    pub fn open_path_with_flags(&mut self, path: &str, mode_9p: u8, mode_dotl: u32) -> Result<u32, String> {
+    pub fn open_path_with_flags(&mut self, path: impl AsRef<[u8]>, mode_9p: u8, mode_dotl: u32) -> Result<u32, String> {
+        let path = path.as_ref();
         let (fid, _is_dir) = self.walk_path(path)?;
         match self.open_with_flags(fid, mode_9p, mode_dotl) {
             Ok(()) => Ok(fid),
@@ -190,37 +399,193 @@ impl P9Session {
         }
     }
 
+    /// Clunk `fid`, first writing back any pages it dirtied.
     pub fn close_fid(&mut self, fid: u32) -> Result<(), String> {
         self.clunk(fid)
     }
 
     pub fn read_fid(&mut self, fid: u32, offset: u64, count: u32) -> Result<Vec<u8>, String> {
-        let max_count = self.max_read_count();
+        let max_count = self.io_count(fid, self.max_read_count());
         let count = if count == 0 || count > max_count {
             max_count
         } else {
             count
         };
+        if let Some((qid_path, _)) = self.cached_file(fid) {
+            let mut data = vec![0u8; count as usize];
+            let len = self.cached_read(fid, qid_path, offset, &mut data)?;
+            data.truncate(len);
+            return Ok(data);
+        }
         self.read(fid, offset, count)
     }
 
+    /// Read into `buf` from `offset` with a single TREAD, returning the byte count.
+    ///
+    /// The reply payload is placed directly in `buf` by the transport, so no
+    /// intermediate buffers are allocated. Reads are clamped to iounit/msize.
+    pub fn read_into(&mut self, fid: u32, offset: u64, buf: &mut [u8]) -> Result<usize, String> {
+        let max_count = self.io_count(fid, self.max_read_count()) as usize;
+        let len = buf.len().min(max_count);
+        if let Some((qid_path, _)) = self.cached_file(fid) {
+            return self.cached_read(fid, qid_path, offset, &mut buf[..len]);
+        }
+        self.read_into_raw(fid, offset, &mut buf[..len])
+    }
+
+    /// Write the concatenation of `bufs` with a single TWRITE, returning the count written.
+    ///
+    /// The slices are handed to the transport as-is, so page-sized buffers are
+    /// sent without assembling a contiguous message. Data beyond iounit/msize
+    /// is not sent; callers loop on the returned count like [`Self::write_fid`].
+    pub fn write_vectored_at(&mut self, fid: u32, offset: u64, bufs: &[&[u8]]) -> Result<usize, String> {
+        if let Some((qid_path, PageCacheMode::Loose)) = self.cached_file(fid) {
+            let mut pos = offset;
+            for buf in bufs {
+                self.cached_write(fid, qid_path, pos, buf)?;
+                pos += buf.len() as u64;
+            }
+            let wrote = (pos - offset) as usize;
+            self.acache_written(fid, offset, wrote);
+            return Ok(wrote);
+        }
+        let mut remaining = self.io_count(fid, self.max_write_count()) as usize;
+        let mut clamped: Vec<&[u8]> = Vec::with_capacity(bufs.len());
+        for buf in bufs {
+            if remaining == 0 {
+                break;
+            }
+            let take = buf.len().min(remaining);
+            clamped.push(&buf[..take]);
+            remaining -= take;
+        }
+        let count: usize = clamped.iter().map(|buf| buf.len()).sum();
+        let wrote = self.write_vectored(fid, offset, &clamped)?;
+        if wrote > count {
+            return Err(format!("invalid write count: {} > {}", wrote, count));
+        }
+        self.acache_written(fid, offset, wrote);
+        if let Some((qid_path, _)) = self.cached_file(fid) {
+            let mut pos = offset;
+            for buf in &clamped {
+                let len = buf.len().min((offset + wrote as u64 - pos) as usize);
+                self.pcache_write_through(qid_path, fid, pos, &buf[..len]);
+                pos += len as u64;
+            }
+        }
+        Ok(wrote)
+    }
+
+    /// Write as much of `data` as fits in a single TWRITE and return the count written.
+    ///
+    /// With the page cache in loose mode the data is buffered instead and
+    /// fully accepted.
     pub fn write_fid(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<usize, String> {
-        self.write(fid, offset, data)
+        let cached = self.cached_file(fid);
+        if let Some((qid_path, PageCacheMode::Loose)) = cached {
+            let wrote = self.cached_write(fid, qid_path, offset, data)?;
+            self.acache_written(fid, offset, wrote);
+            return Ok(wrote);
+        }
+        let max_count = self.io_count(fid, self.max_write_count()) as usize;
+        let data = &data[..data.len().min(max_count)];
+        let wrote = self.write(fid, offset, data)?;
+        if wrote > data.len() {
+            return Err(format!("invalid write count: {} > {}", wrote, data.len()));
+        }
+        self.acache_written(fid, offset, wrote);
+        if let Some((qid_path, _)) = cached {
+            self.pcache_write_through(qid_path, fid, offset, &data[..wrote]);
+        }
+        Ok(wrote)
     }
 
-    pub fn create_file(&mut self, path: &str) -> Result<u32, String> {
+    /// Write all of `data` at `offset`, splitting by iounit/msize and retrying short writes.
+    pub fn write_all_at(&mut self, fid: u32, mut offset: u64, mut data: &[u8]) -> Result<(), String> {
+        while !data.is_empty() {
+            let wrote = self.write_fid(fid, offset, data)?;
+            if wrote == 0 {
+                return Err(String::from("write returned zero bytes"));
+            }
+            data = &data[wrote..];
+            offset += wrote as u64;
+        }
+        Ok(())
+    }
+
+    /// Fill `buf` from `offset`, failing if the file ends first.
+    pub fn read_exact_at(&mut self, fid: u32, mut offset: u64, mut buf: &mut [u8]) -> Result<(), String> {
+        while !buf.is_empty() {
+            let len = self.read_into(fid, offset, buf)?;
+            if len == 0 {
+                return Err(String::from("unexpected end of file"));
+            }
+            buf = &mut buf[len..];
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Append everything from `offset` to end of file into `buf`, returning the byte count.
+    pub fn read_to_end(&mut self, fid: u32, mut offset: u64, buf: &mut Vec<u8>) -> Result<usize, String> {
+        let start = buf.len();
+        loop {
+            let data = self.read_fid(fid, offset, 0)?;
+            if data.is_empty() {
+                return Ok(buf.len() - start);
+            }
+            offset += data.len() as u64;
+            buf.extend_from_slice(&data);
+        }
+    }
+
+    /// Read the whole file at `path`.
+    pub fn read_file(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<u8>, String> {
+        let path = path.as_ref();
+        let fid = self.open_path_with_flags(path, OREAD, P9_DOTL_RDONLY)?;
+        let mut data = Vec::new();
+        let result = self.read_to_end(fid, 0, &mut data);
+        let clunked = self.clunk(fid);
+        result?;
+        clunked?;
+        Ok(data)
+    }
+
+    /// Replace the contents of the file at `path`, creating it if it does not exist.
+    pub fn write_file(&mut self, path: impl AsRef<[u8]>, data: &[u8]) -> Result<(), String> {
+        let path = path.as_ref();
+        let fid = match self.walk_path(path) {
+            Ok((fid, _is_dir)) => {
+                if let Err(err) = self.open_with_flags(fid, OWRITE | OTRUNC, P9_DOTL_WRONLY | P9_DOTL_TRUNC) {
+                    let _ = self.clunk(fid);
+                    return Err(err);
+                }
+                fid
+            }
+            Err(err) if is_not_found(&err) => self.create_file_with_flags(path, OWRITE, P9_DOTL_WRONLY, 0o644)?,
+            Err(err) => return Err(err),
+        };
+        let result = self.write_all_at(fid, 0, data);
+        let clunked = self.clunk(fid);
+        result?;
+        clunked
+    }
+
+    pub fn create_file(&mut self, path: impl AsRef<[u8]>) -> Result<u32, String> {
+        let path = path.as_ref();
         self.create_file_with_flags(path, ORDWR, P9_DOTL_RDWR | P9_DOTL_CREATE, 0o644)
     }
 
     pub fn create_file_with_flags(
         &mut self,
-        path: &str,
+        path: impl AsRef<[u8]>,
         mode_9p: u8,
         mode_dotl: u32,
         perm: u32,
     ) -> Result<u32, String> {
-        let (parent, name) = split_parent_name(path)?;
-        let (fid, is_dir) = self.walk_path(parent)?;
+        let path = path.as_ref();
+        let (parent, name) = split_parent_name(path, self.dotdot)?;
+        let (fid, is_dir) = self.walk_path(&parent)?;
         if !is_dir {
             self.clunk(fid)?;
             return Err(String::from("parent is not a directory"));
@@ -233,7 +598,10 @@ impl P9Session {
         };
 
         match result {
-            Ok(()) => Ok(fid),
+            Ok(()) => {
+                self.dcache_created(path);
+                Ok(fid)
+            }
             Err(err) => {
                 let _ = self.clunk(fid);
                 Err(err)
@@ -241,74 +609,106 @@ impl P9Session {
         }
     }
 
-    pub fn read_link(&mut self, path: &str) -> Result<String, String> {
-        let (fid, _is_dir) = self.walk_path(path)?;
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TREADLINK, tag);
-        msg.push_u32(fid);
-        let resp = self.send_recv(msg.finish(), RREADLINK, tag);
-        let target = match resp {
-            Ok(resp) => {
-                let mut offset = 0;
-                read_str(&resp, &mut offset)
-            }
-            Err(err) => Err(err),
-        };
+    pub fn read_link(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<u8>, String> {
+        let path = path.as_ref();
+        let (fid, _qid) = self.walk_path_nofollow(path)?;
+        let target = self.readlink_fid(fid);
         let _ = self.clunk(fid);
         target
     }
 
-    pub fn link(&mut self, target: &str, link_path: &str) -> Result<(), String> {
-        let (parent, name) = split_parent_name(link_path)?;
-        let (dfid, is_dir) = self.walk_path(parent)?;
+    /// Absolute path of `path` with every symlink expanded (9P2000.L).
+    ///
+    /// Targets are resolved inside the attach root: absolute targets start
+    /// at the root and their `..` components stop there, whatever
+    /// [`Self::set_dotdot_policy`] says about `..` in `path` itself. More
+    /// than 40 expansions fail with ELOOP.
+    pub fn canonicalize(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<u8>, String> {
+        let path = path.as_ref();
+        let (fid, _qid, parts) = self.resolve_components(path, true)?;
+        let _ = self.clunk(fid);
+        Ok(absolute_path(&parts))
+    }
+
+    /// Like [`Self::canonicalize`], but a symlink in the last component is kept.
+    pub fn resolve(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<u8>, String> {
+        let path = path.as_ref();
+        let (fid, _qid, parts) = self.resolve_components(path, false)?;
+        let _ = self.clunk(fid);
+        Ok(absolute_path(&parts))
+    }
+
+    pub fn link(&mut self, target: impl AsRef<[u8]>, link_path: impl AsRef<[u8]>) -> Result<(), String> {
+        let target = target.as_ref();
+        let link_path = link_path.as_ref();
+        let (parent, name) = split_parent_name(link_path, self.dotdot)?;
+        let (dfid, is_dir) = self.walk_path(&parent)?;
         if !is_dir {
             self.clunk(dfid)?;
             return Err(String::from("parent is not a directory"));
         }
-        let (fid, _is_dir) = self.walk_path(target)?;
+        let (fid, _qid) = match self.walk_path_nofollow(target) {
+            Ok(walked) => walked,
+            Err(err) => {
+                let _ = self.clunk(dfid);
+                return Err(err);
+            }
+        };
 
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TLINK, tag);
-        msg.push_u32(fid);
-        msg.push_u32(dfid);
-        msg.push_str(name);
-        let result = self.send_recv(msg.finish(), RLINK, tag).map(|_| ());
+        let result = self
+            .call(&Tmessage::Link { dfid, fid, name })
+            .and_then(|reply| expect(&reply, RLINK));
+        if result.is_ok() {
+            self.dcache_created(link_path);
+        }
 
         let _ = self.clunk(fid);
         let _ = self.clunk(dfid);
         result
     }
 
-    pub fn symlink(&mut self, target: &str, link_path: &str) -> Result<(), String> {
+    pub fn symlink(&mut self, target: impl AsRef<[u8]>, link_path: impl AsRef<[u8]>) -> Result<(), String> {
+        let target = target.as_ref();
+        let link_path = link_path.as_ref();
         if !self.p9_version.is_dotl() {
             return Err(String::from("symlink requires 9P2000.L"));
         }
-        let (parent, name) = split_parent_name(link_path)?;
-        let (dfid, is_dir) = self.walk_path(parent)?;
+        let (parent, name) = split_parent_name(link_path, self.dotdot)?;
+        let (dfid, is_dir) = self.walk_path(&parent)?;
         if !is_dir {
             self.clunk(dfid)?;
             return Err(String::from("parent is not a directory"));
         }
 
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TSYMLINK, tag);
-        msg.push_u32(dfid);
-        msg.push_str(name);
-        msg.push_str(target);
-        msg.push_u32(0);
-        let result = self.send_recv(msg.finish(), RSYMLINK, tag).map(|_| ());
+        let result = self
+            .call(&Tmessage::Symlink { fid: dfid, name, symtgt: target, gid: 0 })
+            .and_then(|reply| expect(&reply, RSYMLINK));
+        if result.is_ok() {
+            self.dcache_created(link_path);
+        }
 
         let _ = self.clunk(dfid);
         result
     }
 
-    pub fn remove_path(&mut self, path: &str) -> Result<(), String> {
-        let (fid, _is_dir) = self.walk_path(path)?;
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TREMOVE, tag);
-        msg.push_u32(fid);
-        match self.send_recv(msg.finish(), RREMOVE, tag) {
-            Ok(_) => Ok(()),
+    pub fn remove_path(&mut self, path: impl AsRef<[u8]>) -> Result<(), String> {
+        let path = path.as_ref();
+        let (fid, qid) = self.walk_path_nofollow(path)?;
+        if let Some(qid) = qid {
+            self.acache_invalidate(qid.path);
+            if let Some(cache) = self.pcache.as_mut() {
+                cache.invalidate(qid.path);
+            }
+        }
+        match self
+            .call(&Tmessage::Remove { fid })
+            .and_then(|reply| expect(&reply, RREMOVE))
+        {
+            Ok(()) => {
+                self.open_fids.remove(&fid);
+                self.dcache_invalidate(path);
+                Ok(())
+            }
             Err(err) => {
                 let _ = self.clunk(fid);
                 Err(err)
@@ -318,429 +718,1201 @@ impl P9Session {
 
     pub fn truncate_fid(&mut self, fid: u32, size: u64) -> Result<(), String> {
         if self.p9_version.is_dotl() {
-            self.setattr_size(fid, size)
+            if let Some((qid_path, _)) = self.cached_file(fid) {
+                self.flush_file(qid_path)?;
+                if let Some(cache) = self.pcache.as_mut() {
+                    cache.invalidate(qid_path);
+                }
+            }
+            self.setattr_size(fid, size)?;
+            self.acache_update_fid(fid, |attr| attr.size = size);
+            Ok(())
         } else {
             Err(String::from("truncate requires 9P2000.L"))
         }
     }
 
     /// Get file attributes via TGETATTR (9P2000.L).
-    pub fn getattr(&mut self, path: &str) -> Result<FileAttr, String> {
+    ///
+    /// With an attribute cache enabled, a cached entry whose qid.version still
+    /// matches the walk result is returned without a TGETATTR round trip.
+    pub fn getattr(&mut self, path: impl AsRef<[u8]>) -> Result<FileAttr, String> {
+        let path = path.as_ref();
         if !self.p9_version.is_dotl() {
             return Err(String::from("getattr requires 9P2000.L"));
         }
-        let (fid, _) = self.walk_path(path)?;
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TGETATTR, tag);
-        msg.push_u32(fid);
-        msg.push_u64(P9_STATS_BASIC);
-        let result = self.send_recv(msg.finish(), RGETATTR, tag);
-        let attr = match result {
-            Ok(resp) => {
-                let mut off = 0;
-                let _valid = read_u64(&resp, &mut off)?;
-                let qid = read_qid(&resp, &mut off)?;
-                let mode = read_u32(&resp, &mut off)?;
-                let uid = read_u32(&resp, &mut off)?;
-                let gid = read_u32(&resp, &mut off)?;
-                let nlink = read_u64(&resp, &mut off)?;
-                let _rdev = read_u64(&resp, &mut off)?;
-                let size = read_u64(&resp, &mut off)?;
-                let _blksize = read_u64(&resp, &mut off)?;
-                let _blocks = read_u64(&resp, &mut off)?;
-                let atime_sec = read_u64(&resp, &mut off)?;
-                let _atime_nsec = read_u64(&resp, &mut off)?;
-                let mtime_sec = read_u64(&resp, &mut off)?;
-                let _mtime_nsec = read_u64(&resp, &mut off)?;
-                let ctime_sec = read_u64(&resp, &mut off)?;
-                // remaining fields (ctime_nsec, btime, gen, data_version) skipped
-                Ok(FileAttr {
-                    qid_type: qid.type_,
-                    mode,
-                    uid,
-                    gid,
-                    nlink,
-                    size,
-                    atime_sec,
-                    mtime_sec,
-                    ctime_sec,
-                })
-            }
-            Err(err) => Err(err),
-        };
+        let (fid, qid) = self.walk_path_qid(path)?;
+        let now = self.now();
+        if let (Some(cache), Some(qid)) = (self.acache.as_mut(), qid)
+            && let Some(attr) = cache.get(qid.path, qid.version, None, now)
+        {
+            let _ = self.clunk(fid);
+            return Ok(attr);
+        }
+        let attr = self.getattr_fid(fid);
         let _ = self.clunk(fid);
         attr
     }
 
+    /// Fetch attributes for `fid` from the server and refresh the attribute cache.
+    fn getattr_fid(&mut self, fid: u32) -> Result<FileAttr, String> {
+        let request_mask = P9_STATS_BASIC | P9_STATS_DATA_VERSION;
+        let stat = match self.call(&Tmessage::Getattr { fid, request_mask })? {
+            Rmessage::Getattr(stat) => stat,
+            other => return Err(unexpected(&other)),
+        };
+        Ok(self.cache_attr(&stat))
+    }
+
+    /// Convert an RGETATTR body and record it in the attribute and page caches.
+    fn cache_attr(&mut self, stat: &Attr) -> FileAttr {
+        let attr = FileAttr {
+            qid_type: stat.qid.type_,
+            mode: stat.mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            nlink: stat.nlink,
+            size: stat.size,
+            atime_sec: stat.atime_sec,
+            mtime_sec: stat.mtime_sec,
+            ctime_sec: stat.ctime_sec,
+            atime_nsec: stat.atime_nsec,
+            mtime_nsec: stat.mtime_nsec,
+            ctime_nsec: stat.ctime_nsec,
+            blksize: stat.blksize,
+            blocks: stat.blocks,
+            qid_path: stat.qid.path,
+            qid_version: stat.qid.version,
+            data_version: (stat.valid & P9_STATS_DATA_VERSION != 0).then_some(stat.data_version),
+        };
+        let now = self.now();
+        if let Some(cache) = self.acache.as_mut() {
+            cache.insert(attr.clone(), now);
+        }
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.attributes(attr.qid_path, attr.size, attr.data_version);
+        }
+        attr
+    }
+
     /// Rename a file or directory via TRENAME (9P2000.L).
-    pub fn rename_path(&mut self, old_path: &str, new_path: &str) -> Result<(), String> {
+    pub fn rename_path(&mut self, old_path: impl AsRef<[u8]>, new_path: impl AsRef<[u8]>) -> Result<(), String> {
+        let old_path = old_path.as_ref();
+        let new_path = new_path.as_ref();
         if !self.p9_version.is_dotl() {
             return Err(String::from("rename requires 9P2000.L"));
         }
-        let (fid, _) = self.walk_path(old_path)?;
-        let (parent, name) = split_parent_name(new_path)?;
-        let (dfid, is_dir) = self.walk_path(parent)?;
+        let (parent, name) = split_parent_name(new_path, self.dotdot)?;
+        let (fid, qid) = self.walk_path_nofollow(old_path)?;
+        let (dfid, is_dir) = match self.walk_path(&parent) {
+            Ok(walked) => walked,
+            Err(err) => {
+                let _ = self.clunk(fid);
+                return Err(err);
+            }
+        };
         if !is_dir {
             let _ = self.clunk(fid);
             let _ = self.clunk(dfid);
             return Err(String::from("target parent is not a directory"));
         }
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TRENAME, tag);
-        msg.push_u32(fid);
-        msg.push_u32(dfid);
-        msg.push_str(name);
-        let result = self.send_recv(msg.finish(), RRENAME, tag).map(|_| ());
+        let result = self
+            .call(&Tmessage::Rename { fid, dfid, name })
+            .and_then(|reply| expect(&reply, RRENAME));
+        if let Some(qid) = qid {
+            self.acache_invalidate(qid.path);
+        }
+        if result.is_ok() {
+            self.dcache_invalidate(old_path);
+            self.dcache_invalidate(new_path);
+            self.dcache_created(new_path);
+        }
         let _ = self.clunk(fid);
         let _ = self.clunk(dfid);
         result
     }
 
     /// Change file mode via TSETATTR (9P2000.L).
-    pub fn setattr_mode(&mut self, path: &str, mode: u32) -> Result<(), String> {
+    pub fn setattr_mode(&mut self, path: impl AsRef<[u8]>, mode: u32) -> Result<(), String> {
+        let path = path.as_ref();
         if !self.p9_version.is_dotl() {
             return Err(String::from("setattr requires 9P2000.L"));
         }
-        let (fid, _) = self.walk_path(path)?;
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TSETATTR, tag);
-        msg.push_u32(fid);
-        msg.push_u32(P9_SETATTR_MODE); // valid: mode only
-        msg.push_u32(mode);            // mode
-        msg.push_u32(0);               // uid
-        msg.push_u32(0);               // gid
-        msg.push_u64(0);               // size
-        msg.push_u64(0);               // atime_sec
-        msg.push_u64(0);               // atime_nsec
-        msg.push_u64(0);               // mtime_sec
-        msg.push_u64(0);               // mtime_nsec
-        let result = self.send_recv(msg.finish(), RSETATTR, tag).map(|_| ());
+        let (fid, qid) = self.walk_path_qid(path)?;
+        let attr = SetAttr {
+            valid: P9_SETATTR_MODE,
+            mode,
+            ..SetAttr::default()
+        };
+        let result = self
+            .call(&Tmessage::Setattr { fid, attr })
+            .and_then(|reply| expect(&reply, RSETATTR));
+        if let (Some(cache), Some(qid)) = (self.acache.as_mut(), qid) {
+            match result {
+                Ok(()) => cache.update(qid.path, |attr| attr.mode = (attr.mode & !0o7777) | (mode & 0o7777)),
+                Err(_) => cache.invalidate(qid.path),
+            }
+        }
         let _ = self.clunk(fid);
         result
     }
 
     /// List directory entries with type information.
-    pub fn list_dir_entries(&mut self, path: &str) -> Result<Vec<P9DirEntry>, String> {
-        let (fid, is_dir) = self.walk_path(path)?;
+    pub fn list_dir_entries(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<P9DirEntry>, String> {
+        let mut entries = Vec::new();
+        for entry in self.read_dir(path)? {
+            let entry = entry?;
+            if entry.name != b"." && entry.name != b".." {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// List a directory together with the attributes of every entry (9P2000.L).
+    ///
+    /// Entries are walked relative to the directory fid, and the TWALK, TGETATTR
+    /// and TCLUNK requests for up to 32 entries go out as one transport batch
+    /// per phase. Entries whose cached attributes still match the readdir qid
+    /// skip the round trips; entries removed before they could be walked are
+    /// left out.
+    pub fn read_dir_plus(&mut self, path: impl AsRef<[u8]>) -> Result<Vec<(P9DirEntry, FileAttr)>, String> {
+        let path = path.as_ref();
+        if !self.p9_version.is_dotl() {
+            return Err(String::from("read_dir_plus requires 9P2000.L"));
+        }
+        let (dir_fid, is_dir) = self.walk_path(path)?;
         if !is_dir {
-            self.clunk(fid)?;
+            self.clunk(dir_fid)?;
             return Err(String::from("not a directory"));
         }
-        self.open_with_flags(fid, OREAD, P9_DOTL_RDONLY)?;
+        let result = self.read_dir_plus_fid(dir_fid);
+        let _ = self.clunk(dir_fid);
+        result
+    }
 
-        let mut dir_offset = 0u64;
-        let mut entries = Vec::new();
-        loop {
-            if self.p9_version.is_dotl() {
-                let (chunk, next_offset) =
-                    self.readdir_entries(fid, dir_offset, self.msize - 64)?;
-                if chunk.is_empty() {
-                    break;
-                }
-                entries.extend(chunk);
-                match next_offset {
-                    Some(next) if next > dir_offset => dir_offset = next,
-                    _ => break,
-                }
+    /// Walk the tree below `root` depth-first with default options.
+    pub fn walk_tree(&mut self, root: impl AsRef<[u8]>) -> WalkTree<'_> {
+        self.walk_tree_with(root, WalkTreeOptions::default())
+    }
+
+    /// Walk the tree below `root` as configured by `options`.
+    pub fn walk_tree_with(&mut self, root: impl AsRef<[u8]>, options: WalkTreeOptions) -> WalkTree<'_> {
+        WalkTree::new(self, root.as_ref().to_vec(), options)
+    }
+
+    fn read_dir_plus_fid(&mut self, dir_fid: u32) -> Result<Vec<(P9DirEntry, FileAttr)>, String> {
+        let entries = self.list_dir_fid(dir_fid)?;
+        self.attach_attrs(dir_fid, entries)
+    }
+
+    /// List the directory at `path` for a tree walk, with attributes when
+    /// `metadata` is set and the server speaks 9P2000.L.
+    pub(crate) fn read_tree_dir(
+        &mut self,
+        path: &[u8],
+        metadata: bool,
+    ) -> Result<Vec<(P9DirEntry, Option<FileAttr>)>, String> {
+        let (dir_fid, is_dir) = self.walk_path(path)?;
+        if !is_dir {
+            self.clunk(dir_fid)?;
+            return Err(format!("{}: not a directory", lossy(path)));
+        }
+        let result = self.list_dir_fid(dir_fid).and_then(|entries| {
+            if metadata && self.p9_version.is_dotl() {
+                let entries = self.attach_attrs(dir_fid, entries)?;
+                Ok(entries.into_iter().map(|(entry, attr)| (entry, Some(attr))).collect())
             } else {
-                let data = self.read(fid, dir_offset, self.msize - 64)?;
-                if data.is_empty() {
-                    break;
-                }
-                dir_offset += data.len() as u64;
-                let mut names = Vec::new();
-                parse_dir_entries(&data, &mut names)?;
-                for name in names {
-                    entries.push(P9DirEntry { name, entry_type: 0 });
-                }
+                Ok(entries.into_iter().map(|entry| (entry, None)).collect())
+            }
+        });
+        let _ = self.clunk(dir_fid);
+        result
+    }
+
+    /// Look up `path`, returning its absolute path and qid. With `follow`,
+    /// every symlink including the last one is expanded.
+    pub(crate) fn lookup_qid(&mut self, path: &[u8], follow: bool) -> Result<(Vec<u8>, Qid), String> {
+        let (fid, qid, path) = if follow {
+            let (fid, qid, parts) = self.resolve_components(path, true)?;
+            (fid, qid, absolute_path(&parts))
+        } else {
+            let (fid, qid) = self.walk_path_nofollow(path)?;
+            (fid, qid, path.to_vec())
+        };
+        let qid = match qid {
+            Some(qid) => Ok(qid),
+            // An empty walk reports no qid, but opening the directory does.
+            None => self
+                .open_with_flags(fid, OREAD, P9_DOTL_RDONLY)
+                .and_then(|()| self.open_fids.get(&fid).map(|open| open.qid).ok_or_else(|| String::from("no qid"))),
+        };
+        let _ = self.clunk(fid);
+        Ok((path, qid?))
+    }
+
+    /// Read every entry of the directory `dir_fid` except `.` and `..`.
+    fn list_dir_fid(&mut self, dir_fid: u32) -> Result<Vec<P9DirEntry>, String> {
+        // An opened fid cannot be walked from, so list through a clone.
+        let list_fid = self.alloc_fid();
+        self.walk(dir_fid, list_fid, [])?;
+        if let Err(err) = self.open_with_flags(list_fid, OREAD, P9_DOTL_RDONLY) {
+            let _ = self.clunk(list_fid);
+            return Err(err);
+        }
+        let mut entries = Vec::new();
+        for entry in ReadDir::new(self, list_fid, true) {
+            let entry = entry?;
+            if entry.name != b"." && entry.name != b".." {
+                entries.push(entry);
             }
         }
-        self.clunk(fid)?;
         Ok(entries)
     }
 
+    /// Pair the children `entries` of `dir_fid` with their attributes,
+    /// dropping entries that vanished.
+    fn attach_attrs(
+        &mut self,
+        dir_fid: u32,
+        entries: Vec<P9DirEntry>,
+    ) -> Result<Vec<(P9DirEntry, FileAttr)>, String> {
+        let now = self.now();
+        let mut attrs: Vec<Option<FileAttr>> = match self.acache.as_mut() {
+            Some(cache) => entries
+                .iter()
+                .map(|entry| cache.get(entry.qid.path, entry.qid.version, None, now))
+                .collect(),
+            None => vec![None; entries.len()],
+        };
+        let missing: Vec<usize> = (0..entries.len()).filter(|&i| attrs[i].is_none()).collect();
+        for batch in missing.chunks(READDIR_PLUS_BATCH) {
+            let names: Vec<&[u8]> = batch.iter().map(|&i| entries[i].name.as_slice()).collect();
+            for (&i, attr) in batch.iter().zip(self.getattr_batch(dir_fid, &names)?) {
+                attrs[i] = attr;
+            }
+        }
+        Ok(entries
+            .into_iter()
+            .zip(attrs)
+            .filter_map(|(entry, attr)| Some((entry, attr?)))
+            .collect())
+    }
+
+    /// Fetch the attributes of the children `names` of `dir_fid`, batching each
+    /// phase. `None` marks a child that no longer exists.
+    fn getattr_batch(&mut self, dir_fid: u32, names: &[&[u8]]) -> Result<Vec<Option<FileAttr>>, String> {
+        let fids: Vec<u32> = names.iter().map(|_| self.alloc_fid()).collect();
+        let walks = names
+            .iter()
+            .zip(&fids)
+            .map(|(name, &newfid)| {
+                let wnames = WalkList::try_from(&[*name][..])?;
+                Ok(Tmessage::Walk { fid: dir_fid, newfid, wnames })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let walked = self.call_batch(&walks, |reply| match reply {
+            Ok(Rmessage::Walk { wqids }) if wqids.len() == 1 => Ok(true),
+            Ok(other) => Err(unexpected(&other)),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(err),
+        })?;
+        let live: Vec<u32> = fids
+            .iter()
+            .zip(&walked)
+            .filter(|(_, walked)| matches!(walked, Ok(true)))
+            .map(|(&fid, _)| fid)
+            .collect();
+
+        let request_mask = P9_STATS_BASIC | P9_STATS_DATA_VERSION;
+        let getattrs: Vec<Tmessage> = live.iter().map(|&fid| Tmessage::Getattr { fid, request_mask }).collect();
+        let stats = self.call_batch(&getattrs, |reply| match reply {
+            Ok(Rmessage::Getattr(stat)) => Ok(Some(stat)),
+            Ok(other) => Err(unexpected(&other)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        });
+        let clunks: Vec<Tmessage> = live.iter().map(|&fid| Tmessage::Clunk { fid }).collect();
+        let clunked = self.call_batch(&clunks, |_| ());
+        let stats = stats?;
+        clunked?;
+
+        let mut stats = stats.into_iter();
+        let mut attrs = Vec::with_capacity(names.len());
+        for walked in walked {
+            let attr = match walked? {
+                true => stats.next().transpose()?.flatten().map(|stat| self.cache_attr(&stat)),
+                false => None,
+            };
+            attrs.push(attr);
+        }
+        Ok(attrs)
+    }
+
     /// Flush file data to storage via TFSYNC (9P2000.L).
+    ///
+    /// Dirty cached pages of the file are written back first.
     pub fn fsync_fid(&mut self, fid: u32) -> Result<(), String> {
+        self.fsync(fid, false)
+    }
+
+    /// Like [`Self::fsync_fid`], but lets the server skip metadata that
+    /// is not needed to read the data back.
+    pub fn fdatasync_fid(&mut self, fid: u32) -> Result<(), String> {
+        self.fsync(fid, true)
+    }
+
+    fn fsync(&mut self, fid: u32, datasync: bool) -> Result<(), String> {
         if !self.p9_version.is_dotl() {
             return Err(String::from("fsync requires 9P2000.L"));
         }
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TFSYNC, tag);
-        msg.push_u32(fid);
-        self.send_recv(msg.finish(), RFSYNC, tag).map(|_| ())
+        if let Some((qid_path, _)) = self.cached_file(fid) {
+            self.flush_file(qid_path)?;
+        }
+        let reply = self.call(&Tmessage::Fsync { fid, datasync: u32::from(datasync) })?;
+        expect(&reply, RFSYNC)
+    }
+
+    fn walk_path(&mut self, path: &[u8]) -> Result<(u32, bool), String> {
+        let (fid, last) = self.walk_path_qid(path)?;
+        Ok((fid, is_dir_qid(last)))
+    }
+
+    /// Walk to `path`, returning the new fid and the qid of its last component.
+    fn walk_path_qid(&mut self, path: &[u8]) -> Result<(u32, Option<Qid>), String> {
+        self.walk_path_with(path, true)
     }
 
-    fn walk_path(&mut self, path: &str) -> Result<(u32, bool), String> {
+    /// Like [`Self::walk_path_qid`], but a final symlink is not followed.
+    fn walk_path_nofollow(&mut self, path: &[u8]) -> Result<(u32, Option<Qid>), String> {
+        self.walk_path_with(path, false)
+    }
+
+    fn walk_path_with(&mut self, path: &[u8], follow_last: bool) -> Result<(u32, Option<Qid>), String> {
+        if !self.follow_symlinks {
+            return self.walk_path_lexical(path);
+        }
+        // A path without `..` that walks cleanly to a non-link crosses no
+        // symlinks, since Twalk stops at a link that is not last.
+        if !path.split(|byte| *byte == b'/').any(|part| part == b"..") {
+            match self.walk_path_lexical(path) {
+                Ok((fid, qid)) if !(follow_last && qid.is_some_and(is_symlink_qid)) => return Ok((fid, qid)),
+                Ok((fid, _)) => {
+                    let _ = self.clunk(fid);
+                }
+                Err(err) if errno_of(&err) == Some(ENOTDIR) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let (fid, qid, _parts) = self.resolve_components(path, follow_last)?;
+        Ok((fid, qid))
+    }
+
+    /// Walk to the lexically normalized `path` without expanding symlinks.
+    fn walk_path_lexical(&mut self, path: &[u8]) -> Result<(u32, Option<Qid>), String> {
+        if self.dcache.is_none()
+            && let Some(parts) = plain_components(path)
+        {
+            let fid = self.alloc_fid();
+            let last = self.walk(self.root_fid, fid, parts)?;
+            return Ok((fid, last));
+        }
+        let parts = normalize_path(path, self.dotdot)?;
+        if self.dcache.is_some() {
+            return self.walk_path_cached(&parts);
+        }
         let fid = self.alloc_fid();
-        let names = path_parts(path);
-        let qids = self.walk(self.root_fid, fid, &names)?;
-        let is_dir = qids
-            .last()
-            .map(|q| q.type_ & 0x80 != 0)
-            .unwrap_or(true);
-        Ok((fid, is_dir))
+        let last = self.walk(self.root_fid, fid, parts.iter().copied())?;
+        Ok((fid, last))
     }
 
-    fn send_tversion(&mut self, version: &str) -> Result<String, String> {
-        let tag = NO_TAG;
-        let mut msg = Message::new(TVERSION, tag);
-        msg.push_u32(self.msize);
-        msg.push_str(version);
-        let resp = self.send_recv(msg.finish(), RVERSION, tag)?;
-
-        let mut offset = 0;
-        let msize = read_u32(&resp, &mut offset)?;
-        let version = match read_str(&resp, &mut offset) {
-            Ok(value) => value,
+    /// Walk to `path` starting from the deepest cached ancestor directory.
+    ///
+    /// The parent directory fid is cached on a miss, so siblings cost a single
+    /// one-element TWALK. Failed lookups are remembered as negative entries.
+    fn walk_path_cached(&mut self, parts: &[&[u8]]) -> Result<(u32, Option<Qid>), String> {
+        let Some((leaf, parent)) = parts.split_last() else {
+            let fid = self.alloc_fid();
+            self.walk(self.root_fid, fid, [])?;
+            return Ok((fid, None));
+        };
+        let now = self.now();
+        let cache = self.dcache.as_mut().ok_or("dentry cache disabled")?;
+        if cache.is_negative(&parts.join(&b'/'), now) {
+            return Err(component_error(leaf, ENOENT));
+        }
+
+        let dir_fid = match cache.lookup(parent) {
+            Some((fid, depth)) if depth == parent.len() => fid,
+            _ if parent.is_empty() => self.root_fid,
+            found => {
+                let (start, depth) = found.unwrap_or((self.root_fid, 0));
+                let fid = self.alloc_fid();
+                match self.walk(start, fid, parent[depth..].iter().copied()) {
+                    Ok(last) if is_dir_qid(last) => {}
+                    Ok(_) => {
+                        let _ = self.clunk(fid);
+                        return Err(component_error(parent[parent.len() - 1], ENOTDIR));
+                    }
+                    Err(err) => {
+                        if is_not_found(&err) {
+                            self.dcache_negative(parent.join(&b'/'), now);
+                        }
+                        return Err(err);
+                    }
+                }
+                if let Some(cache) = self.dcache.as_mut() {
+                    let evicted = cache.insert(parent.join(&b'/'), fid);
+                    self.clunk_all(evicted);
+                }
+                fid
+            }
+        };
+
+        let fid = self.alloc_fid();
+        match self.walk(dir_fid, fid, [*leaf]) {
+            Ok(last) => Ok((fid, last)),
             Err(err) => {
-                warn!(
-                    "RVERSION parse error: {} (msize={}, remaining={})",
-                    err,
-                    msize,
-                    dump_hex(&resp[offset..])
-                );
-                return Err(err);
+                if is_not_found(&err) {
+                    self.dcache_negative(parts.join(&b'/'), now);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Walk `path` one component at a time, expanding symlinks.
+    ///
+    /// Returns a new fid for the result, its qid and its symlink-free
+    /// components below the attach root.
+    fn resolve_components(&mut self, path: &[u8], follow_last: bool) -> Result<Resolved, String> {
+        let mut fid = self.alloc_fid();
+        self.walk(self.root_fid, fid, [])?;
+        let mut qid = None;
+        match self.resolve_from(&mut fid, &mut qid, path, follow_last) {
+            Ok(parts) => Ok((fid, qid, parts)),
+            Err(err) => {
+                let _ = self.clunk(fid);
+                Err(err)
+            }
+        }
+    }
+
+    fn resolve_from(
+        &mut self,
+        fid: &mut u32,
+        qid: &mut Option<Qid>,
+        path: &[u8],
+        follow_last: bool,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        // Components still to walk, last first.
+        let mut pending: Vec<Vec<u8>> = Vec::new();
+        push_components(&mut pending, path);
+        let mut resolved: Vec<Vec<u8>> = Vec::new();
+        let mut links = 0;
+        // The caller's components sit below any link targets pushed on top.
+        let mut from_caller = pending.len();
+        while let Some(name) = pending.pop() {
+            let is_caller = pending.len() < from_caller;
+            from_caller = from_caller.min(pending.len());
+            if name == b".." {
+                // Link targets are the server's to choose, so the policy only
+                // covers the caller's path; their `..` stops at the root.
+                match self.dotdot {
+                    DotDotPolicy::Reject if is_caller => {
+                        return Err(format!("path {:?} contains \"..\"", lossy(path)));
+                    }
+                    DotDotPolicy::RejectEscape if is_caller && resolved.is_empty() => {
+                        return Err(format!("path {:?} escapes the root", lossy(path)));
+                    }
+                    _ => {}
+                }
+                resolved.pop();
+                // `..` is never sent to the server; walk the shorter path again.
+                let parts: Vec<&[u8]> = resolved.iter().map(Vec::as_slice).collect();
+                let next = self.alloc_fid();
+                *qid = self.walk(self.root_fid, next, parts.iter().copied())?;
+                self.replace_fid(fid, next);
+                continue;
+            }
+
+            let next = self.alloc_fid();
+            let walked = self.walk(*fid, next, [name.as_slice()])?;
+            if walked.is_some_and(is_symlink_qid) && (follow_last || !pending.is_empty()) {
+                let target = self.readlink_fid(next);
+                let _ = self.clunk(next);
+                let target = target?;
+                links += 1;
+                if links > SYMLOOP_MAX {
+                    return Err(component_error(&name, ELOOP));
+                }
+                if target.is_empty() {
+                    return Err(component_error(&name, ENOENT));
+                }
+                if target.starts_with(b"/") {
+                    resolved.clear();
+                    let root = self.alloc_fid();
+                    self.walk(self.root_fid, root, [])?;
+                    self.replace_fid(fid, root);
+                    *qid = None;
+                }
+                push_components(&mut pending, &target);
+                continue;
+            }
+            self.replace_fid(fid, next);
+            *qid = walked;
+            resolved.push(name);
+        }
+        Ok(resolved)
+    }
+
+    /// Clunk `*fid` and replace it with `next`.
+    fn replace_fid(&mut self, fid: &mut u32, next: u32) {
+        let _ = self.clunk(mem::replace(fid, next));
+    }
+
+    fn readlink_fid(&mut self, fid: u32) -> Result<Vec<u8>, String> {
+        if !self.p9_version.is_dotl() {
+            return Err(String::from("readlink requires 9P2000.L"));
+        }
+        match self.call(&Tmessage::Readlink { fid })? {
+            Rmessage::Readlink { target } => Ok(target.to_vec()),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    fn dcache_negative(&mut self, key: Vec<u8>, now: Option<Duration>) {
+        if let Some(cache) = self.dcache.as_mut() {
+            cache.insert_negative(key, now);
+        }
+    }
+
+    /// Drop cached fids for `path` and its descendants after a remove or rename.
+    fn dcache_invalidate(&mut self, path: &[u8]) {
+        if let Some(cache) = self.dcache.as_mut() {
+            let fids = cache.invalidate(&path_key(path, self.dotdot));
+            self.clunk_all(fids);
+        }
+    }
+
+    /// Forget negative lookups covering `path` after it was created.
+    fn dcache_created(&mut self, path: &[u8]) {
+        if let Some(cache) = self.dcache.as_mut() {
+            cache.forget_negative(&path_key(path, self.dotdot));
+        }
+    }
+
+    /// Track an opened fid.
+    fn record_open(&mut self, fid: u32, open: OpenFid) {
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.opened(open.qid.path, open.qid.version);
+        }
+        self.open_fids.insert(fid, open);
+    }
+
+    /// Returns the page-cached file behind `fid`, if it is an open regular file.
+    fn cached_file(&self, fid: u32) -> Option<(u64, PageCacheMode)> {
+        let cache = self.pcache.as_ref()?;
+        let open = self.open_fids.get(&fid)?;
+        // Plain files only (QTFILE, optionally QTTMP).
+        (open.qid.type_ & !0x04 == 0).then_some((open.qid.path, cache.mode()))
+    }
+
+    /// Serve a read from the page cache, fetching missing pages with read-ahead.
+    fn cached_read(&mut self, fid: u32, qid_path: u64, offset: u64, buf: &mut [u8]) -> Result<usize, String> {
+        let mut done = 0;
+        while let Some(cache) = self.pcache.as_mut() {
+            let (len, eof) = cache.read(qid_path, offset + done as u64, &mut buf[done..]);
+            done += len;
+            if eof || done == buf.len() {
+                break;
+            }
+            let (first, count) = cache.plan_fetch(qid_path, offset + done as u64, buf.len() - done);
+            if self.fetch_pages(fid, qid_path, first, count)? == 0 {
+                break;
+            }
+        }
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.note_read(qid_path, offset + done as u64);
+        }
+        self.pcache_reclaim()?;
+        Ok(done)
+    }
+
+    /// Read `count` pages starting at page `first` into the cache, returning the bytes fetched.
+    fn fetch_pages(&mut self, fid: u32, qid_path: u64, first: u64, count: usize) -> Result<usize, String> {
+        let mut data = vec![0u8; count * PAGE_SIZE];
+        let max_count = self.io_count(fid, self.max_read_count()) as usize;
+        let start = first * PAGE_SIZE as u64;
+        let mut filled = 0;
+        while filled < data.len() {
+            let len = (data.len() - filled).min(max_count);
+            let got = self.read_into_raw(fid, start + filled as u64, &mut data[filled..filled + len])?;
+            filled += got;
+            if got < len {
+                break;
+            }
+        }
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.insert_fetched(qid_path, first, &data[..filled]);
+        }
+        Ok(filled)
+    }
+
+    /// Buffer a write in the page cache, reading partially overwritten pages first.
+    ///
+    /// If the fid cannot be read (e.g. it was opened write-only) the write goes
+    /// straight to the server instead.
+    fn cached_write(&mut self, fid: u32, qid_path: u64, offset: u64, data: &[u8]) -> Result<usize, String> {
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let index = pos / PAGE_SIZE as u64;
+            let in_page = (pos % PAGE_SIZE as u64) as usize;
+            let len = (PAGE_SIZE - in_page).min(data.len() - done);
+            let cached = self.pcache.as_ref().is_some_and(|cache| cache.has_page(qid_path, index));
+            if !cached && len < PAGE_SIZE && self.fetch_pages(fid, qid_path, index, 1).is_err() {
+                self.write_all_raw(fid, pos, &data[done..])?;
+                self.pcache_write_through(qid_path, fid, pos, &data[done..]);
+                return Ok(data.len());
+            }
+            if let Some(cache) = self.pcache.as_mut() {
+                cache.write(qid_path, fid, index, in_page, &data[done..done + len], true);
             }
+            done += len;
+        }
+        self.pcache_reclaim()?;
+        Ok(done)
+    }
+
+    /// Mirror data already written to the server into any cached pages it covers.
+    fn pcache_write_through(&mut self, qid_path: u64, fid: u32, offset: u64, data: &[u8]) {
+        let Some(cache) = self.pcache.as_mut() else {
+            return;
         };
-        self.msize = msize.max(256);
-        Ok(version)
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let in_page = (pos % PAGE_SIZE as u64) as usize;
+            let len = (PAGE_SIZE - in_page).min(data.len() - done);
+            cache.write(qid_path, fid, pos / PAGE_SIZE as u64, in_page, &data[done..done + len], false);
+            done += len;
+        }
     }
 
-    fn send_tattach(&mut self) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TATTACH, tag);
-        msg.push_u32(self.root_fid);
-        msg.push_u32(NO_FID);
-        msg.push_str("root");
-        msg.push_str(&self.mount_tag);
-        if self.p9_version.is_dotl() {
-            msg.push_u32(0);
+    /// Write back the dirty pages of one file, coalescing contiguous pages into large TWRITEs.
+    fn flush_file(&mut self, qid_path: u64) -> Result<(), String> {
+        let Some(mut file) = self.pcache.as_mut().and_then(|cache| cache.take(qid_path)) else {
+            return Ok(());
+        };
+        let result = match file.writeback_fid {
+            _ if !file.has_dirty() => Ok(()),
+            Some(fid) => file.dirty_runs().into_iter().try_for_each(|(offset, segments)| {
+                let bufs: Vec<&[u8]> = segments
+                    .iter()
+                    .map(|&(index, start, end)| &file.pages[&index].data[start..end])
+                    .collect();
+                self.write_all_vectored(fid, offset, &bufs)
+            }),
+            None => Err(String::from("dirty pages lost: write-back fid was clunked")),
+        };
+        match result {
+            Ok(()) => {
+                file.clear_dirty();
+                if let Some(cache) = self.pcache.as_mut() {
+                    cache.put(qid_path, file);
+                }
+            }
+            Err(_) if file.writeback_fid.is_some() => {
+                if let Some(cache) = self.pcache.as_mut() {
+                    cache.put(qid_path, file);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Evict clean pages, then write back dirty ones, once the cache is over its limit.
+    fn pcache_reclaim(&mut self) -> Result<(), String> {
+        let Some(cache) = self.pcache.as_mut() else {
+            return Ok(());
+        };
+        if !cache.is_over_limit() {
+            return Ok(());
+        }
+        let target = cache.max_pages() - cache.max_pages() / 8;
+        cache.evict_clean(target);
+        if cache.is_over_limit() {
+            self.shrink_page_cache(target)?;
         }
-        let _ = self.send_recv(msg.finish(), RATTACH, tag)?;
         Ok(())
     }
 
-    fn walk(&mut self, fid: u32, new_fid: u32, names: &[&str]) -> Result<Vec<Qid>, String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TWALK, tag);
-        msg.push_u32(fid);
-        msg.push_u32(new_fid);
-        msg.push_u16(names.len() as u16);
-        for name in names {
-            msg.push_str(name);
+    fn acache_invalidate(&mut self, qid_path: u64) {
+        if let Some(cache) = self.acache.as_mut() {
+            cache.invalidate(qid_path);
         }
-        let resp = self.send_recv(msg.finish(), RWALK, tag)?;
+    }
 
-        let mut offset = 0;
-        let nwqid = read_u16(&resp, &mut offset)? as usize;
-        if nwqid < names.len() {
-            return Err(String::from("walk failed"));
+    /// Apply a local change to the cached attributes of an open fid.
+    fn acache_update_fid(&mut self, fid: u32, f: impl FnOnce(&mut FileAttr)) {
+        if let (Some(cache), Some(open)) = (self.acache.as_mut(), self.open_fids.get(&fid)) {
+            cache.update(open.qid.path, f);
         }
+    }
+
+    /// Extend the cached size of an open fid after a successful write.
+    fn acache_written(&mut self, fid: u32, offset: u64, wrote: usize) {
+        let end = offset + wrote as u64;
+        self.acache_update_fid(fid, |attr| attr.size = attr.size.max(end));
+    }
 
-        let mut qids = Vec::with_capacity(nwqid);
-        for _ in 0..nwqid {
-            qids.push(read_qid(&resp, &mut offset)?);
+    fn now(&self) -> Option<Duration> {
+        self.clock.as_ref().map(|clock| clock.now())
+    }
+
+    fn clunk_all(&mut self, fids: Vec<u32>) {
+        for fid in fids {
+            let _ = self.clunk(fid);
         }
-        Ok(qids)
+    }
+
+    fn send_tversion(&mut self, version: &str) -> Result<String, String> {
+        let (msize, version) = match self.call(&Tmessage::Version { msize: self.msize, version })? {
+            Rmessage::Version { msize, version } => (msize, String::from(version)),
+            other => return Err(unexpected(&other)),
+        };
+        if msize < MIN_MSIZE {
+            return Err(format!("server msize {} is too small", msize));
+        }
+        // The server may lower the msize we offered but never raise it.
+        self.msize = self.msize.min(msize);
+        self.resp_buf.resize(self.msize as usize, 0);
+        Ok(version)
+    }
+
+    fn send_tattach(&mut self) -> Result<(), String> {
+        let aname = mem::take(&mut self.mount_tag);
+        let result = self.call(&Tmessage::Attach {
+            fid: self.root_fid,
+            afid: NO_FID,
+            uname: "root",
+            aname: &aname,
+            n_uname: self.p9_version.dialect().has_n_uname().then_some(0),
+        });
+        let result = result.and_then(|reply| expect(&reply, RATTACH));
+        self.mount_tag = aname;
+        result
+    }
+
+    /// Walk `names` from `fid` to `new_fid`, returning the qid of the last element.
+    ///
+    /// Long paths are split into walks of at most [`MAXWELEM`] names; if a
+    /// later walk fails, the partly walked `new_fid` is clunked. A failure
+    /// names the component that could not be walked.
+    fn walk<'n>(
+        &mut self,
+        fid: u32,
+        new_fid: u32,
+        names: impl IntoIterator<Item = &'n [u8]>,
+    ) -> Result<Option<Qid>, String> {
+        let mut names = names.into_iter().peekable();
+        let mut from = fid;
+        loop {
+            let mut chunk = WalkList::new();
+            while chunk.len() < MAXWELEM
+                && let Some(name) = names.next()
+            {
+                chunk.push(name)?;
+            }
+            let last = match self.walk_once(from, new_fid, &chunk) {
+                Ok(qid) => qid,
+                Err(err) => {
+                    if from == new_fid {
+                        let _ = self.clunk(new_fid);
+                    }
+                    return Err(err);
+                }
+            };
+            if names.peek().is_none() {
+                return Ok(last);
+            }
+            from = new_fid;
+        }
+    }
+
+    /// Issue a single Twalk of at most [`MAXWELEM`] names.
+    fn walk_once(&mut self, fid: u32, new_fid: u32, names: &WalkList<&[u8]>) -> Result<Option<Qid>, String> {
+        let nwname = names.len();
+        let reply = self.call(&Tmessage::Walk { fid, newfid: new_fid, wnames: *names });
+        let wqids = match reply {
+            Ok(Rmessage::Walk { wqids }) => wqids,
+            Ok(other) => return Err(unexpected(&other)),
+            // Only the first name can fail with an error reply; an empty
+            // clone walk has no name to blame.
+            Err(err) if errno_of(&err).is_some() => match names.first() {
+                Some(name) => return Err(format!("{}: {}", lossy(name), err)),
+                None => return Err(err),
+            },
+            Err(err) => return Err(err),
+        };
+        if wqids.len() > nwname {
+            return Err(format!("Rwalk has {} qids for {} names", wqids.len(), nwname));
+        }
+        if wqids.len() < nwname {
+            // Either the next name is missing or the last one walked is not a directory.
+            return Err(match wqids.len().checked_sub(1) {
+                Some(i) if !is_dir_qid(Some(wqids[i])) => component_error(names[i], ENOTDIR),
+                _ => component_error(names[wqids.len()], ENOENT),
+            });
+        }
+        Ok(wqids.last().copied())
     }
 
     fn open_with_flags(&mut self, fid: u32, mode_9p: u8, mode_dotl: u32) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        if self.p9_version.is_dotl() {
-            let mut msg = Message::new(TLOPEN, tag);
-            msg.push_u32(fid);
-            msg.push_u32(mode_dotl);
-            let _ = self.send_recv(msg.finish(), RLOPEN, tag)?;
+        let request = if self.p9_version.is_dotl() {
+            Tmessage::Lopen { fid, flags: mode_dotl }
         } else {
-            let mut msg = Message::new(TOPEN, tag);
-            msg.push_u32(fid);
-            msg.push_u8(mode_9p);
-            let _ = self.send_recv(msg.finish(), ROPEN, tag)?;
+            Tmessage::Open { fid, mode: mode_9p }
+        };
+        let open = open_reply(self.call(&request)?)?;
+        let qid_path = open.qid.path;
+        self.record_open(fid, open);
+        let close_to_open = self.acache.as_ref().is_some_and(|cache| cache.mode() == CacheMode::CloseToOpen);
+        if close_to_open && self.p9_version.is_dotl() {
+            self.revalidate_attr(fid, qid_path);
         }
         Ok(())
     }
 
-    fn create(&mut self, fid: u32, name: &str, mode: u8, perm: u32) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TCREATE, tag);
-        msg.push_u32(fid);
-        msg.push_str(name);
-        msg.push_u32(perm);
-        msg.push_u8(mode);
-        let _ = self.send_recv(msg.finish(), RCREATE, tag)?;
+    /// Check the cached attributes of `qid_path` against the server.
+    ///
+    /// The cached entry, including changes applied locally, is kept when both
+    /// qid.version and data_version match; otherwise it is replaced.
+    fn revalidate_attr(&mut self, fid: u32, qid_path: u64) {
+        let request_mask = P9_STATS_BASIC | P9_STATS_DATA_VERSION;
+        let stat = match self.call(&Tmessage::Getattr { fid, request_mask }) {
+            Ok(Rmessage::Getattr(stat)) => stat,
+            _ => return self.acache_invalidate(qid_path),
+        };
+        let data_version = (stat.valid & P9_STATS_DATA_VERSION != 0).then_some(stat.data_version);
+        let now = self.now();
+        let current = data_version.is_some()
+            && self
+                .acache
+                .as_mut()
+                .and_then(|cache| cache.get(qid_path, stat.qid.version, data_version, now))
+                .is_some();
+        if !current {
+            self.cache_attr(&stat);
+        }
+    }
+
+    fn create(&mut self, fid: u32, name: &[u8], mode: u8, perm: u32) -> Result<(), String> {
+        // 9P2000.u always carries an extension string, empty for regular files.
+        let extension = (self.p9_version.dialect() == Dialect::P2000U).then_some(&b""[..]);
+        let open = open_reply(self.call(&Tmessage::Create { fid, name, perm, mode, extension })?)?;
+        self.record_open(fid, open);
         Ok(())
     }
 
     fn lcreate(
         &mut self,
         fid: u32,
-        name: &str,
+        name: &[u8],
         flags: u32,
         mode: u32,
         gid: u32,
     ) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TLCREATE, tag);
-        msg.push_u32(fid);
-        msg.push_str(name);
-        msg.push_u32(flags);
-        msg.push_u32(mode);
-        msg.push_u32(gid);
-        let _ = self.send_recv(msg.finish(), RLCREATE, tag)?;
+        let open = open_reply(self.call(&Tmessage::Lcreate { fid, name, flags, mode, gid })?)?;
+        self.record_open(fid, open);
         Ok(())
     }
 
-    fn mkdir(&mut self, fid: u32, name: &str, perm: u32, gid: u32) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TMKDIR, tag);
-        msg.push_u32(fid);
-        msg.push_str(name);
-        msg.push_u32(perm);
-        msg.push_u32(gid);
-        let _ = self.send_recv(msg.finish(), RMKDIR, tag)?;
-        Ok(())
+    fn mkdir(&mut self, fid: u32, name: &[u8], perm: u32, gid: u32) -> Result<(), String> {
+        let reply = self.call(&Tmessage::Mkdir { dfid: fid, name, mode: perm, gid })?;
+        expect(&reply, RMKDIR)
     }
 
     fn read(&mut self, fid: u32, offset: u64, count: u32) -> Result<Vec<u8>, String> {
+        let mut data = vec![0u8; count as usize];
+        let len = self.read_into_raw(fid, offset, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Issue one TREAD whose payload the transport scatters directly into `buf`.
+    fn read_into_raw(&mut self, fid: u32, offset: u64, buf: &mut [u8]) -> Result<usize, String> {
+        let request = Tmessage::Read { fid, offset, count: buf.len() as u32 };
+        if !self.transport.is_vectored() || buf.len() < SMALL_READ {
+            let data = match self.call(&request)? {
+                Rmessage::Read { data } => data,
+                other => return Err(unexpected(&other)),
+            };
+            if data.len() > buf.len() {
+                return Err(format!("Rread count {} exceeds requested {}", data.len(), buf.len()));
+            }
+            buf[..data.len()].copy_from_slice(data);
+            return Ok(data.len());
+        }
+
         let tag = self.alloc_tag();
-        let mut msg = Message::new(TREAD, tag);
-        msg.push_u32(fid);
-        msg.push_u64(offset);
-        msg.push_u32(count);
-        let resp = self.send_recv(msg.finish(), RREAD, tag)?;
-
-        let mut offset = 0;
-        let data_len = read_u32(&resp, &mut offset)? as usize;
-        if offset + data_len > resp.len() {
+        let dialect = self.p9_version.dialect();
+        let req = self.encode_request(|req| request.encode(tag, dialect, req))?;
+        let mut head = [0u8; RREAD_HDR];
+        let result = self.transport.request_vectored(&req, &[], &mut head, buf);
+        self.req_buf = req;
+        let size = result?;
+        if size > RREAD_HDR + buf.len() {
+            return Err(String::from("reply exceeds msize"));
+        }
+        let header = decode_header(&head[..size.min(RREAD_HDR)])?;
+        if header.size as usize != size {
+            return Err(format!(
+                "9p size field {} does not match message length {}",
+                header.size, size
+            ));
+        }
+        if header.msg_type != RREAD {
+            // An error reply that fit is reassembled from the scattered halves.
+            let mut resp = head[..size.min(RREAD_HDR)].to_vec();
+            resp.extend_from_slice(&buf[..size.saturating_sub(RREAD_HDR)]);
+            let reply = decode_reply(&resp, self.p9_version.dialect(), tag)?;
+            return Err(unexpected(&reply));
+        }
+        if header.tag != tag {
+            return Err(String::from("tag mismatch"));
+        }
+        let mut off = HEADER_LEN;
+        let data_len = read_u32(&head[..size.min(RREAD_HDR)], &mut off)? as usize;
+        if data_len > buf.len() {
+            return Err(format!("Rread count {} exceeds requested {}", data_len, buf.len()));
+        }
+        if size != RREAD_HDR + data_len {
             return Err(String::from("short read response"));
         }
-        Ok(resp[offset..offset + data_len].to_vec())
+        Ok(data_len)
     }
 
-    fn readdir(
+    /// Fetch the directory entries of `fid` starting at `cookie` into `entries`.
+    pub(crate) fn read_dir_chunk(
         &mut self,
         fid: u32,
-        offset: u64,
-        count: u32,
-    ) -> Result<(Vec<String>, Option<u64>), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TREADDIR, tag);
-        msg.push_u32(fid);
-        msg.push_u64(offset);
-        msg.push_u32(count);
-        let resp = self.send_recv(msg.finish(), RREADDIR, tag)?;
-
-        let mut offset = 0;
-        let data_len = read_u32(&resp, &mut offset)? as usize;
-        if offset + data_len > resp.len() {
-            return Err(String::from("short readdir response"));
+        cookie: u64,
+        entries: &mut VecDeque<P9DirEntry>,
+    ) -> Result<(), String> {
+        let count = self.io_count(fid, self.max_read_count());
+        if self.p9_version.is_dotl() {
+            match self.call(&Tmessage::Readdir { fid, offset: cookie, count })? {
+                Rmessage::Readdir { data } => parse_dir_entries_l(readdir_data(data, count)?, entries),
+                other => Err(unexpected(&other)),
+            }
+        } else {
+            let data = self.read(fid, cookie, count)?;
+            parse_dir_entries(&data, cookie, entries)
         }
-        parse_dir_entries_l(&resp[offset..offset + data_len])
     }
 
-    fn readdir_entries(
-        &mut self,
-        fid: u32,
-        offset: u64,
-        count: u32,
-    ) -> Result<(Vec<P9DirEntry>, Option<u64>), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TREADDIR, tag);
-        msg.push_u32(fid);
-        msg.push_u64(offset);
-        msg.push_u32(count);
-        let resp = self.send_recv(msg.finish(), RREADDIR, tag)?;
+    fn write(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<usize, String> {
+        self.write_vectored(fid, offset, &[data])
+    }
 
-        let mut off = 0;
-        let data_len = read_u32(&resp, &mut off)? as usize;
-        if off + data_len > resp.len() {
-            return Err(String::from("short readdir response"));
+    /// Issue one TWRITE whose payload is gathered from `bufs` by the transport.
+    fn write_vectored(&mut self, fid: u32, offset: u64, bufs: &[&[u8]]) -> Result<usize, String> {
+        let count: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let count32 = u32::try_from(count).map_err(|_| format!("write of {} bytes exceeds 4 GiB", count))?;
+        let tag = self.alloc_tag();
+        let mut req = self.encode_request(|req| Tmessage::encode_write_header(tag, fid, offset, count32, req))?;
+        if !self.transport.is_vectored() {
+            for buf in bufs {
+                req.extend_from_slice(buf);
+            }
+            return write_reply(self.exchange(req, tag)?, count);
         }
-        let data = &resp[off..off + data_len];
 
-        let mut entries = Vec::new();
-        let mut last_offset = None;
-        let mut parse_off = 0usize;
-        while parse_off < data.len() {
-            let _qid = read_qid(data, &mut parse_off)?;
-            let entry_offset = read_u64(data, &mut parse_off)?;
-            let entry_type = read_u8(data, &mut parse_off)?;
-            let name = read_str(data, &mut parse_off)?;
-            if name != "." && name != ".." {
-                entries.push(P9DirEntry { name, entry_type });
-            }
-            last_offset = Some(entry_offset);
+        // Anything longer than an RWRITE, such as an error, spills into the reply buffer.
+        let mut head = [0u8; RWRITE_LEN];
+        let result = self.transport.request_vectored(&req, bufs, &mut head, &mut self.resp_buf);
+        self.req_buf = req;
+        let size = result?;
+        let dialect = self.p9_version.dialect();
+        if size <= RWRITE_LEN {
+            return write_reply(decode_reply(&head[..size], dialect, tag)?, count);
         }
-        Ok((entries, last_offset))
+        let rest = self.resp_buf.get(..size - RWRITE_LEN).ok_or("reply exceeds msize")?;
+        let mut resp = head.to_vec();
+        resp.extend_from_slice(rest);
+        write_reply(decode_reply(&resp, dialect, tag)?, count)
     }
 
-    #[allow(dead_code)]
-    fn write(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<usize, String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TWRITE, tag);
-        msg.push_u32(fid);
-        msg.push_u64(offset);
-        msg.push_u32(data.len() as u32);
-        msg.push_bytes(data);
-        let resp = self.send_recv(msg.finish(), RWRITE, tag)?;
-
-        let mut offset = 0;
-        let wrote = read_u32(&resp, &mut offset)? as usize;
-        Ok(wrote)
+    /// Write all of `data` with uncached TWRITEs.
+    fn write_all_raw(&mut self, fid: u32, offset: u64, data: &[u8]) -> Result<(), String> {
+        self.write_all_vectored(fid, offset, &[data])
     }
 
-    fn clunk(&mut self, fid: u32) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TCLUNK, tag);
-        msg.push_u32(fid);
-        let _ = self.send_recv(msg.finish(), RCLUNK, tag)?;
-        Ok(())
+    /// Write the concatenation of `bufs`, grouping slices into TWRITEs of at most iounit/msize bytes.
+    fn write_all_vectored(&mut self, fid: u32, mut offset: u64, bufs: &[&[u8]]) -> Result<(), String> {
+        let max_count = self.io_count(fid, self.max_write_count()) as usize;
+        let (mut index, mut skip) = (0usize, 0usize);
+        let mut group: Vec<&[u8]> = Vec::new();
+        loop {
+            while index < bufs.len() && bufs[index].len() == skip {
+                index += 1;
+                skip = 0;
+            }
+            if index == bufs.len() {
+                return Ok(());
+            }
+            group.clear();
+            let (mut i, mut s, mut total) = (index, skip, 0usize);
+            while i < bufs.len() && total < max_count {
+                let avail = &bufs[i][s..];
+                let take = avail.len().min(max_count - total);
+                group.push(&avail[..take]);
+                total += take;
+                if take == avail.len() {
+                    i += 1;
+                    s = 0;
+                } else {
+                    s += take;
+                }
+            }
+            let wrote = self.write_vectored(fid, offset, &group)?;
+            if wrote == 0 || wrote > total {
+                return Err(format!("invalid write count: {} of {}", wrote, total));
+            }
+            offset += wrote as u64;
+            let mut advance = wrote;
+            while advance > 0 {
+                let avail = bufs[index].len() - skip;
+                if advance >= avail {
+                    advance -= avail;
+                    index += 1;
+                    skip = 0;
+                } else {
+                    skip += advance;
+                    advance = 0;
+                }
+            }
+        }
+    }
+
+    /// Clunk `fid`, first writing back pages it dirtied, which are lost once
+    /// their write-back fid is gone.
+    pub(crate) fn clunk(&mut self, fid: u32) -> Result<(), String> {
+        let flushed = match self.pcache.as_ref().and_then(|cache| cache.file_written_by(fid)) {
+            Some(qid_path) => self.flush_file(qid_path),
+            None => Ok(()),
+        };
+        self.open_fids.remove(&fid);
+        if let Some(cache) = self.pcache.as_mut() {
+            cache.release_fid(fid);
+        }
+        let clunked = self.call(&Tmessage::Clunk { fid }).and_then(|reply| expect(&reply, RCLUNK));
+        flushed?;
+        clunked
     }
 
     fn setattr_size(&mut self, fid: u32, size: u64) -> Result<(), String> {
-        let tag = self.alloc_tag();
-        let mut msg = Message::new(TSETATTR, tag);
-        msg.push_u32(fid);
-        msg.push_u32(P9_ATTR_SIZE);
-        msg.push_u32(0);
-        msg.push_u32(0);
-        msg.push_u32(0);
-        msg.push_u64(size);
-        msg.push_u64(0);
-        msg.push_u64(0);
-        msg.push_u64(0);
-        msg.push_u64(0);
-        let _ = self.send_recv(msg.finish(), RSETATTR, tag)?;
-        Ok(())
+        let attr = SetAttr {
+            valid: P9_ATTR_SIZE,
+            size,
+            ..SetAttr::default()
+        };
+        let reply = self.call(&Tmessage::Setattr { fid, attr })?;
+        expect(&reply, RSETATTR)
     }
 
+    /// Send `request` and decode the reply, turning RERROR/RLERROR into an error.
+    ///
+    /// The request is encoded into the session's reusable request buffer and the
+    /// reply borrows its response buffer, so steady-state traffic does not allocate.
+    fn call(&mut self, request: &Tmessage) -> Result<Rmessage<'_>, String> {
+        let tag = match request {
+            Tmessage::Version { .. } => NO_TAG,
+            _ => self.alloc_tag(),
+        };
+        let dialect = self.p9_version.dialect();
+        let req = self.encode_request(|req| request.encode(tag, dialect, req))?;
+        self.exchange(req, tag)
+    }
 
-    fn send_recv(&mut self, req: Vec<u8>, expect: u8, tag: u16) -> Result<Vec<u8>, String> {
-        let mut resp = vec![0u8; self.msize as usize];
-        let size = self.transport.request(&req, &mut resp)?;
-        if size < 7 {
-            return Err(String::from("short 9p response"));
+    /// Send independent requests as one transport batch, handing each decoded
+    /// reply to `reply` in request order.
+    fn call_batch<T>(
+        &mut self,
+        requests: &[Tmessage],
+        mut reply: impl FnMut(Result<Rmessage<'_>, String>) -> T,
+    ) -> Result<Vec<T>, String> {
+        if requests.is_empty() {
+            return Ok(Vec::new());
         }
-        let resp = &resp[..size];
-        let resp_type = resp[4];
-        let resp_tag = u16::from_le_bytes([resp[5], resp[6]]);
-        if resp_type == RERROR {
-            let mut offset = 7;
-            let msg = read_str(resp, &mut offset).unwrap_or_else(|_| String::from("unknown"));
-            return Err(msg);
+        let mut tags = Vec::with_capacity(requests.len());
+        let mut reqs = Vec::with_capacity(requests.len());
+        let dialect = self.p9_version.dialect();
+        for request in requests {
+            let tag = self.alloc_tag();
+            reqs.push(self.encode_request(|req| request.encode(tag, dialect, req))?);
+            tags.push(tag);
         }
-        if resp_type == RLERROR {
-            let mut offset = 7;
-            let errno = read_u32(resp, &mut offset).unwrap_or(0);
-            return Err(format!("rlerror errno={}", errno));
+        let mut resps = vec![vec![0u8; BATCH_REPLY_MAX]; requests.len()];
+        let req_refs: Vec<&[u8]> = reqs.iter().map(Vec::as_slice).collect();
+        let mut resp_refs: Vec<&mut [u8]> = resps.iter_mut().map(Vec::as_mut_slice).collect();
+        let sizes = self.transport.request_batch(&req_refs, &mut resp_refs)?;
+        if sizes.len() != requests.len() {
+            return Err(format!("batch of {} requests got {} replies", requests.len(), sizes.len()));
         }
-        if resp_type != expect {
-            return Err(format!("unexpected response type: {}", resp_type));
+        let dialect = self.p9_version.dialect();
+        Ok(sizes
+            .iter()
+            .zip(&resps)
+            .zip(&tags)
+            .map(|((&size, resp), &tag)| match resp.get(..size) {
+                Some(resp) => reply(decode_reply(resp, dialect, tag)),
+                None => reply(Err(String::from("reply exceeds buffer"))),
+            })
+            .collect())
+    }
+
+    /// Encode a request into the reusable request buffer, rejecting it before
+    /// it reaches the transport if it does not fit the negotiated msize.
+    fn encode_request(
+        &mut self,
+        encode: impl FnOnce(&mut Vec<u8>) -> Result<(), String>,
+    ) -> Result<Vec<u8>, String> {
+        let mut req = mem::take(&mut self.req_buf);
+        let result = encode(&mut req).and_then(|()| {
+            let header = decode_header(&req)?;
+            if header.size > self.msize {
+                return Err(format!(
+                    "{} of {} bytes exceeds msize {}",
+                    message_name(header.msg_type),
+                    header.size,
+                    self.msize
+                ));
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(req),
+            Err(err) => {
+                req.clear();
+                self.req_buf = req;
+                Err(err)
+            }
         }
-        if resp_tag != tag {
-            return Err(String::from("tag mismatch"));
+    }
+
+    /// Send the encoded request `req` and decode the reply to `tag`.
+    fn exchange(&mut self, req: Vec<u8>, tag: u16) -> Result<Rmessage<'_>, String> {
+        let result = self.transport.request(&req, &mut self.resp_buf);
+        self.req_buf = req;
+        let size = result?;
+        if size > self.resp_buf.len() {
+            return Err(String::from("reply exceeds msize"));
         }
-        Ok(resp[7..].to_vec())
+        decode_reply(&self.resp_buf[..size], self.p9_version.dialect(), tag)
     }
 
     fn alloc_tag(&mut self) -> u16 {
@@ -759,3 +1931,107 @@ impl P9Session {
         fid
     }
 }
+
+/// Decode a reply to `tag`, turning RERROR/RLERROR into an error string.
+fn decode_reply(resp: &[u8], dialect: Dialect, tag: u16) -> Result<Rmessage<'_>, String> {
+    let (resp_tag, reply) = Rmessage::decode(resp, dialect).inspect_err(|err| {
+        debug!("undecodable 9p reply: {} [{}]", err, dump_hex(resp));
+    })?;
+    match reply {
+        Rmessage::Error { ename, .. } => Err(String::from(ename)),
+        Rmessage::Lerror { ecode } => Err(format!("rlerror errno={}", ecode)),
+        _ if resp_tag != tag => Err(String::from("tag mismatch")),
+        reply => Ok(reply),
+    }
+}
+
+/// Error for a reply of the wrong type.
+fn unexpected(reply: &Rmessage) -> String {
+    format!("unexpected response type: {}", reply.msg_type())
+}
+
+/// Check that a reply without a body has the expected type.
+fn expect(reply: &Rmessage, msg_type: u8) -> Result<(), String> {
+    if reply.msg_type() == msg_type {
+        Ok(())
+    } else {
+        Err(unexpected(reply))
+    }
+}
+
+/// Returns true if the final qid of a walk names a directory (an empty walk stays on one).
+fn is_dir_qid(last: Option<Qid>) -> bool {
+    last.map(|q| q.type_ & 0x80 != 0).unwrap_or(true)
+}
+
+/// Returns true if `qid` names a symlink.
+fn is_symlink_qid(qid: Qid) -> bool {
+    qid.type_ & 0x02 != 0
+}
+
+/// Push the components of `path` onto a stack of names to walk, so the first
+/// component is popped first.
+fn push_components(pending: &mut Vec<Vec<u8>>, path: &[u8]) {
+    let start = pending.len();
+    pending.extend(
+        path.split(|byte| *byte == b'/')
+            .filter(|part| !part.is_empty() && *part != b".")
+            .map(<[u8]>::to_vec),
+    );
+    pending[start..].reverse();
+}
+
+/// Join symlink-free components into an absolute path.
+fn absolute_path(parts: &[Vec<u8>]) -> Vec<u8> {
+    let mut path = vec![b'/'];
+    path.extend(parts.join(&b'/'));
+    path
+}
+
+/// Returns the errno carried by an RLERROR-derived error string.
+fn errno_of(err: &str) -> Option<u32> {
+    err.rsplit_once("rlerror errno=")?.1.parse().ok()
+}
+
+/// Error for a path component that failed with `errno`.
+fn component_error(name: &[u8], errno: u32) -> String {
+    format!("{}: rlerror errno={}", lossy(name), errno)
+}
+
+/// Rerror strings that 9P2000 and 9P2000.u servers send for a missing file.
+const NOT_FOUND_ENAMES: [&str; 3] = ["No such file or directory", "file does not exist", "file not found"];
+
+/// Returns true if `err` means a path component does not exist.
+fn is_not_found(err: &str) -> bool {
+    errno_of(err) == Some(ENOENT) || NOT_FOUND_ENAMES.iter().any(|ename| err.ends_with(ename))
+}
+
+/// Check that an RREADDIR payload fits the `count` requested.
+fn readdir_data(data: &[u8], count: u32) -> Result<&[u8], String> {
+    if data.len() > count as usize {
+        return Err(format!("Rreaddir count {} exceeds requested {}", data.len(), count));
+    }
+    Ok(data)
+}
+
+/// Extract the count from an RWRITE reply to a write of `requested` bytes.
+fn write_reply(reply: Rmessage, requested: usize) -> Result<usize, String> {
+    match reply {
+        Rmessage::Write { count } if count as usize > requested => {
+            Err(format!("Rwrite count {} exceeds requested {}", count, requested))
+        }
+        Rmessage::Write { count } => Ok(count as usize),
+        other => Err(unexpected(&other)),
+    }
+}
+
+/// Extract the qid and iounit from an ROPEN/RLOPEN/RCREATE/RLCREATE reply.
+fn open_reply(reply: Rmessage) -> Result<OpenFid, String> {
+    match reply {
+        Rmessage::Open { qid, iounit }
+        | Rmessage::Create { qid, iounit }
+        | Rmessage::Lopen { qid, iounit }
+        | Rmessage::Lcreate { qid, iounit } => Ok(OpenFid { qid, iounit }),
+        other => Err(unexpected(&other)),
+    }
+}
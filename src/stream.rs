@@ -0,0 +1,287 @@
+//! Size-prefixed 9P framing over byte streams.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::transport::Transport;
+
+/// A reliable, ordered byte stream such as a socket, pipe or serial link.
+pub trait ByteStream {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), String>;
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String>;
+    fn flush(&mut self) -> Result<(), String>;
+}
+
+/// Adapter for `std::io::Read + Write` streams.
+#[cfg(feature = "std")]
+pub struct StdStream<S>(pub S);
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read + std::io::Write> ByteStream for StdStream<S> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), String> {
+        std::io::Write::write_all(&mut self.0, buf).map_err(io_error)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        std::io::Read::read_exact(&mut self.0, buf).map_err(io_error)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        std::io::Write::flush(&mut self.0).map_err(io_error)
+    }
+}
+
+/// Map an I/O error to a transport error string, naming EOF and resets explicitly.
+#[cfg(feature = "std")]
+pub(crate) fn io_error(err: std::io::Error) -> String {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::UnexpectedEof => String::from("transport closed: unexpected eof"),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+            format!("transport closed: {}", err)
+        }
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => format!("transport timed out: {}", err),
+        _ => format!("transport error: {}", err),
+    }
+}
+
+/// Adapter for `embedded_io::Read + Write` streams.
+#[cfg(feature = "embedded-io")]
+pub struct EmbeddedStream<S>(pub S);
+
+#[cfg(feature = "embedded-io")]
+impl<S: embedded_io::Read + embedded_io::Write> ByteStream for EmbeddedStream<S> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), String> {
+        self.0
+            .write_all(buf)
+            .map_err(|err| format!("transport error: {:?}", err))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        self.0.read_exact(buf).map_err(|err| match err {
+            embedded_io::ReadExactError::UnexpectedEof => String::from("transport closed: unexpected eof"),
+            embedded_io::ReadExactError::Other(err) => format!("transport error: {:?}", err),
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.0.flush().map_err(|err| format!("transport error: {:?}", err))
+    }
+}
+
+struct StreamState<S> {
+    stream: S,
+    /// Set once framing is lost; the stream cannot be resynchronised.
+    broken: bool,
+}
+
+/// Transport that writes a request and reads exactly one size-prefixed reply.
+pub struct StreamTransport<S> {
+    state: Mutex<StreamState<S>>,
+    max_size: u32,
+}
+
+impl<S: ByteStream> StreamTransport<S> {
+    /// Wrap `stream`, rejecting replies larger than `max_size` (the msize offered to the server).
+    pub fn new(stream: S, max_size: u32) -> Self {
+        Self {
+            state: Mutex::new(StreamState {
+                stream,
+                broken: false,
+            }),
+            max_size,
+        }
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.state.into_inner().stream
+    }
+
+    fn exchange(&self, req_head: &[u8], req_data: &[&[u8]], resp_head: &mut [u8], resp_data: &mut [u8]) -> Result<usize, String> {
+        let mut state = self.state.lock();
+        if state.broken {
+            return Err(String::from("transport closed: framing lost"));
+        }
+        let result = exchange(&mut state.stream, self.max_size, req_head, req_data, resp_head, resp_data);
+        state.settle(result)
+    }
+
+    fn exchange_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        let mut state = self.state.lock();
+        if state.broken {
+            return Err(String::from("transport closed: framing lost"));
+        }
+        let result = exchange_batch(&mut state.stream, self.max_size, reqs, resps);
+        state.settle(result)
+    }
+}
+
+/// A failed exchange.
+struct Failure {
+    err: String,
+    /// The stream is still at a frame boundary and can carry the next request.
+    in_sync: bool,
+}
+
+impl From<String> for Failure {
+    fn from(err: String) -> Self {
+        Self { err, in_sync: false }
+    }
+}
+
+impl<S> StreamState<S> {
+    /// Mark the stream broken if `result` lost framing.
+    fn settle<T>(&mut self, result: Result<T, Failure>) -> Result<T, String> {
+        result.map_err(|failure| {
+            self.broken |= !failure.in_sync;
+            failure.err
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read + std::io::Write> StreamTransport<StdStream<S>> {
+    /// Frame 9P over a `std::io` stream.
+    pub fn from_std(stream: S, max_size: u32) -> Self {
+        Self::new(StdStream(stream), max_size)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<S: embedded_io::Read + embedded_io::Write> StreamTransport<EmbeddedStream<S>> {
+    /// Frame 9P over an `embedded_io` stream.
+    pub fn from_embedded(stream: S, max_size: u32) -> Self {
+        Self::new(EmbeddedStream(stream), max_size)
+    }
+}
+
+fn exchange<S: ByteStream>(
+    stream: &mut S,
+    max_size: u32,
+    req_head: &[u8],
+    req_data: &[&[u8]],
+    resp_head: &mut [u8],
+    resp_data: &mut [u8],
+) -> Result<usize, Failure> {
+    stream.write_all(req_head)?;
+    for data in req_data {
+        stream.write_all(data)?;
+    }
+    stream.flush()?;
+
+    let mut size = [0u8; 4];
+    stream.read_exact(&mut size)?;
+    let size = u32::from_le_bytes(size);
+    if size < 7 || size > max_size {
+        return Err(format!("invalid 9p frame size: {}", size).into());
+    }
+    let size = size as usize;
+    let head = size.min(resp_head.len());
+    if size > resp_head.len() + resp_data.len() || head < 4 {
+        return Err(too_large(stream, size, 4));
+    }
+    resp_head[..4].copy_from_slice(&(size as u32).to_le_bytes());
+    stream.read_exact(&mut resp_head[4..head])?;
+    stream.read_exact(&mut resp_data[..size - head])?;
+    Ok(size)
+}
+
+/// Write every request, then read one reply per request and file it by tag.
+fn exchange_batch<S: ByteStream>(
+    stream: &mut S,
+    max_size: u32,
+    reqs: &[&[u8]],
+    resps: &mut [&mut [u8]],
+) -> Result<Vec<usize>, Failure> {
+    if resps.len() < reqs.len() {
+        let err = String::from("fewer reply buffers than requests");
+        return Err(Failure { err, in_sync: true });
+    }
+    let tags: Vec<Option<u16>> = reqs
+        .iter()
+        .map(|req| req.get(5..7).map(|tag| u16::from_le_bytes([tag[0], tag[1]])))
+        .collect();
+    for req in reqs {
+        stream.write_all(req)?;
+    }
+    stream.flush()?;
+
+    // A reply too large for its buffer is skipped so the rest can still be read.
+    let mut skipped = None;
+    let mut sizes = vec![0usize; reqs.len()];
+    for _ in 0..reqs.len() {
+        let mut head = [0u8; 7];
+        stream.read_exact(&mut head)?;
+        let size = u32::from_le_bytes([head[0], head[1], head[2], head[3]]);
+        if size < 7 || size > max_size {
+            return Err(format!("invalid 9p frame size: {}", size).into());
+        }
+        let tag = u16::from_le_bytes([head[5], head[6]]);
+        let index = (0..reqs.len())
+            .find(|&index| tags[index] == Some(tag) && sizes[index] == 0)
+            .ok_or_else(|| format!("9p reply for unexpected tag {}", tag))?;
+        let size = size as usize;
+        sizes[index] = size;
+        let resp = &mut resps[index];
+        if size > resp.len() {
+            let failure = too_large(stream, size, 7);
+            if !failure.in_sync {
+                return Err(failure);
+            }
+            skipped.get_or_insert(failure);
+            continue;
+        }
+        resp[..7].copy_from_slice(&head);
+        stream.read_exact(&mut resp[7..size])?;
+    }
+    match skipped {
+        Some(failure) => Err(failure),
+        None => Ok(sizes),
+    }
+}
+
+/// Discard the rest of a `size`-byte frame whose first `read` bytes were
+/// consumed, and report it as too large for the reply buffer.
+fn too_large<S: ByteStream>(stream: &mut S, size: usize, read: usize) -> Failure {
+    let mut scratch = [0u8; 64];
+    let mut left = size - read;
+    while left > 0 {
+        let len = left.min(scratch.len());
+        if let Err(err) = stream.read_exact(&mut scratch[..len]) {
+            return err.into();
+        }
+        left -= len;
+    }
+    Failure {
+        err: format!("9p frame larger than buffer: {}", size),
+        in_sync: true,
+    }
+}
+
+impl<S: ByteStream + Send> Transport for StreamTransport<S> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.exchange(req, &[], resp, &mut [])
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        self.exchange(req_head, req_data, resp_head, resp_data)
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.exchange_batch(reqs, resps)
+    }
+}
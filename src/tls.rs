@@ -0,0 +1,151 @@
+//! TLS-wrapped stream transport for host tooling.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme, StreamOwned,
+};
+
+use crate::dial::{dial, DialOptions};
+use crate::stream::{io_error, StreamTransport};
+use crate::transport::Transport;
+
+/// How the server certificate is authenticated.
+pub enum TlsVerify {
+    /// Verify the chain against these trust anchors and check the server name.
+    Roots(RootCertStore),
+    /// Accept only servers presenting one of these end-entity certificates.
+    ///
+    /// The chain and name are not checked, which suits self-signed test hosts.
+    Pinned(Vec<CertificateDer<'static>>),
+}
+
+/// Options for [`connect_tls`].
+pub struct TlsOptions {
+    /// Name sent for SNI and matched against the certificate with [`TlsVerify::Roots`].
+    pub server_name: String,
+    /// Server certificate policy.
+    pub verify: TlsVerify,
+    /// Certificate chain and key presented when the server asks for client auth.
+    pub client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+impl TlsOptions {
+    /// Verify `server_name` against the given trust anchors, without client auth.
+    pub fn new(server_name: &str, roots: RootCertStore) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            verify: TlsVerify::Roots(roots),
+            client_auth: None,
+        }
+    }
+}
+
+/// Connect to a 9P server over TLS using a dial string; see [`crate::connect`].
+///
+/// The handshake completes before this returns, so certificate problems are
+/// reported here rather than on the first request.
+pub fn connect_tls(
+    addr: &str,
+    options: DialOptions,
+    tls: TlsOptions,
+) -> Result<Box<dyn Transport>, String> {
+    let config = client_config(tls.verify, tls.client_auth)?;
+    let name = ServerName::try_from(tls.server_name.clone())
+        .map_err(|_| format!("invalid tls server name: {}", tls.server_name))?;
+    let conn = ClientConnection::new(Arc::new(config), name).map_err(tls_error)?;
+    let mut stream = StreamOwned::new(conn, dial(addr, &options)?);
+    while stream.conn.is_handshaking() {
+        stream
+            .conn
+            .complete_io(&mut stream.sock)
+            .map_err(|err| format!("tls handshake with {}: {}", addr, io_error(err)))?;
+    }
+    Ok(Box::new(StreamTransport::from_std(stream, options.max_size)))
+}
+
+fn client_config(
+    verify: TlsVerify,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> Result<ClientConfig, String> {
+    let provider = Arc::new(ring::default_provider());
+    let algorithms = provider.signature_verification_algorithms;
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let builder = match verify {
+        TlsVerify::Roots(roots) => builder.with_root_certificates(roots),
+        TlsVerify::Pinned(pinned) => {
+            if pinned.is_empty() {
+                return Err(String::from("no pinned tls certificates"));
+            }
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier { pinned, algorithms }))
+        }
+    };
+    match client_auth {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key).map_err(tls_error),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn tls_error(err: rustls::Error) -> String {
+    format!("tls error: {}", err)
+}
+
+/// Accepts exactly the pinned end-entity certificates.
+#[derive(Debug)]
+struct PinnedVerifier {
+    pinned: Vec<CertificateDer<'static>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pinned.iter().any(|cert| cert.as_ref() == end_entity.as_ref()) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
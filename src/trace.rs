@@ -0,0 +1,258 @@
+//! Recording and replay of 9P traffic for reproducible bug reports.
+//!
+//! A trace is the magic `9PTRACE\0` and a little-endian `u32` format version,
+//! followed by one record per exchange:
+//!
+//! ```text
+//! req_len[4] req[req_len] status[1] reply_len[4] reply[reply_len]
+//! ```
+//!
+//! `status` is 0 when `reply` holds the server's reply and 1 when it holds the
+//! text of a transport error.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::protocol::message_name;
+use crate::stream::ByteStream;
+use crate::transport::Transport;
+
+const TRACE_MAGIC: &[u8; 8] = b"9PTRACE\0";
+const TRACE_VERSION: u32 = 1;
+const STATUS_REPLY: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+/// Transport wrapper that appends every exchange to a trace.
+///
+/// Failing to write the trace fails the request, so a trace that was written
+/// without errors covers the whole session.
+pub struct RecordingTransport<T, S> {
+    inner: T,
+    sink: Mutex<S>,
+}
+
+impl<T: Transport, S: ByteStream + Send> RecordingTransport<T, S> {
+    /// Record traffic on `inner` to `sink`, writing the trace header immediately.
+    pub fn new(inner: T, mut sink: S) -> Result<Self, String> {
+        sink.write_all(TRACE_MAGIC)?;
+        sink.write_all(&TRACE_VERSION.to_le_bytes())?;
+        sink.flush()?;
+        Ok(Self {
+            inner,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Return the wrapped transport and the trace sink.
+    pub fn into_inner(self) -> (T, S) {
+        (self.inner, self.sink.into_inner())
+    }
+
+    fn record(&self, req: &[&[u8]], reply: &Result<usize, String>, resp: &[&[u8]]) -> Result<(), String> {
+        let req_len: usize = req.iter().map(|part| part.len()).sum();
+        let mut sink = self.sink.lock();
+        sink.write_all(&(req_len as u32).to_le_bytes())?;
+        for part in req {
+            sink.write_all(part)?;
+        }
+        match reply {
+            Ok(size) => {
+                sink.write_all(&[STATUS_REPLY])?;
+                sink.write_all(&(*size as u32).to_le_bytes())?;
+                let mut left = *size;
+                for part in resp {
+                    let take = left.min(part.len());
+                    sink.write_all(&part[..take])?;
+                    left -= take;
+                }
+            }
+            Err(err) => {
+                sink.write_all(&[STATUS_ERROR])?;
+                sink.write_all(&(err.len() as u32).to_le_bytes())?;
+                sink.write_all(err.as_bytes())?;
+            }
+        }
+        sink.flush()
+    }
+}
+
+impl<T: Transport, S: ByteStream + Send> Transport for RecordingTransport<T, S> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let reply = self.inner.request(req, resp);
+        self.record(&[req], &reply, &[resp])
+            .map_err(|err| format!("trace write failed: {}", err))?;
+        reply
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.inner.is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let reply = self
+            .inner
+            .request_vectored(req_head, req_data, resp_head, resp_data);
+        let mut req = Vec::with_capacity(req_data.len() + 1);
+        req.push(req_head);
+        req.extend_from_slice(req_data);
+        self.record(&req, &reply, &[resp_head, resp_data])
+            .map_err(|err| format!("trace write failed: {}", err))?;
+        reply
+    }
+
+    /// Records one exchange per request, so a replay can answer them one at a time.
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        let reply = self.inner.request_batch(reqs, resps);
+        let recorded = match &reply {
+            Ok(sizes) => reqs
+                .iter()
+                .zip(resps.iter())
+                .zip(sizes)
+                .try_for_each(|((req, resp), size)| self.record(&[req], &Ok(*size), &[resp])),
+            // A replay stops a batch at its first failure.
+            Err(err) => match reqs.first() {
+                Some(req) => self.record(&[req], &Err(err.clone()), &[]),
+                None => Ok(()),
+            },
+        };
+        recorded.map_err(|err| format!("trace write failed: {}", err))?;
+        reply
+    }
+}
+
+struct TraceRecord {
+    req: Vec<u8>,
+    reply: Result<Vec<u8>, String>,
+}
+
+struct ReplayState {
+    next: usize,
+    divergence: Option<String>,
+}
+
+/// Transport that serves a session from a recorded trace.
+///
+/// Each request must match the next recorded request byte for byte. The first
+/// mismatch is reported as an error naming the exchange and offset, and every
+/// later request fails with the same error.
+pub struct ReplayTransport {
+    records: Vec<TraceRecord>,
+    state: Mutex<ReplayState>,
+}
+
+impl ReplayTransport {
+    /// Parse `trace` as written by [`RecordingTransport`].
+    pub fn new(trace: &[u8]) -> Result<Self, String> {
+        if trace.len() < 12 || &trace[..8] != TRACE_MAGIC {
+            return Err(String::from("not a 9p trace"));
+        }
+        let version = u32::from_le_bytes([trace[8], trace[9], trace[10], trace[11]]);
+        if version != TRACE_VERSION {
+            return Err(format!("unsupported trace version: {}", version));
+        }
+        let mut offset = 12;
+        let mut records = Vec::new();
+        while offset < trace.len() {
+            let req = take_chunk(trace, &mut offset)?.to_vec();
+            let status = *trace
+                .get(offset)
+                .ok_or_else(|| format!("trace truncated in record {}", records.len()))?;
+            offset += 1;
+            let body = take_chunk(trace, &mut offset)?;
+            let reply = match status {
+                STATUS_REPLY => Ok(body.to_vec()),
+                STATUS_ERROR => Err(String::from_utf8_lossy(body).into_owned()),
+                _ => return Err(format!("invalid status in trace record {}", records.len())),
+            };
+            records.push(TraceRecord { req, reply });
+        }
+        Ok(Self {
+            records,
+            state: Mutex::new(ReplayState {
+                next: 0,
+                divergence: None,
+            }),
+        })
+    }
+
+    /// Number of recorded exchanges not yet replayed.
+    pub fn remaining(&self) -> usize {
+        self.records.len() - self.state.lock().next
+    }
+
+    /// Description of the first request that differed from the recording.
+    pub fn divergence(&self) -> Option<String> {
+        self.state.lock().divergence.clone()
+    }
+}
+
+fn take_chunk<'a>(trace: &'a [u8], offset: &mut usize) -> Result<&'a [u8], String> {
+    let len_bytes = trace
+        .get(*offset..*offset + 4)
+        .ok_or_else(|| String::from("trace truncated"))?;
+    let len = u32::from_le_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+    *offset += 4;
+    let chunk = trace
+        .get(*offset..*offset + len)
+        .ok_or_else(|| String::from("trace truncated"))?;
+    *offset += len;
+    Ok(chunk)
+}
+
+/// Explain how `got` differs from the recorded request `want`.
+fn describe_mismatch(index: usize, want: &[u8], got: &[u8]) -> String {
+    let name = |msg: &[u8]| msg.get(4).map(|t| message_name(*t)).unwrap_or("malformed");
+    let at = want
+        .iter()
+        .zip(got)
+        .position(|(a, b)| a != b)
+        .unwrap_or(want.len().min(got.len()));
+    format!(
+        "replay diverged at request {}: expected {} ({} bytes), got {} ({} bytes), first difference at byte {}",
+        index,
+        name(want),
+        want.len(),
+        name(got),
+        got.len(),
+        at
+    )
+}
+
+impl Transport for ReplayTransport {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let mut state = self.state.lock();
+        if let Some(divergence) = &state.divergence {
+            return Err(divergence.clone());
+        }
+        let index = state.next;
+        let Some(record) = self.records.get(index) else {
+            let err = format!("replay diverged at request {}: trace has no more requests", index);
+            state.divergence = Some(err.clone());
+            return Err(err);
+        };
+        if record.req != req {
+            let err = describe_mismatch(index, &record.req, req);
+            state.divergence = Some(err.clone());
+            return Err(err);
+        }
+        state.next += 1;
+        match &record.reply {
+            Ok(reply) => {
+                if reply.len() > resp.len() {
+                    return Err(String::from("transport reply exceeds buffer"));
+                }
+                resp[..reply.len()].copy_from_slice(reply);
+                Ok(reply.len())
+            }
+            Err(err) => Err(err.clone()),
+        }
+    }
+}
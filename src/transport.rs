@@ -1,9 +1,96 @@
 //! Transport abstraction for 9P request/response traffic.
 
+use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
 /// Transport for sending raw 9P requests and receiving replies.
 pub trait Transport: Send + Sync {
     /// Send `req` and write the response into `resp`, returning the used length.
     fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String>;
+
+    /// Returns true if [`Transport::request_vectored`] scatters/gathers natively.
+    ///
+    /// When false the session assembles requests and receives replies in its
+    /// own reusable buffers instead of going through the bounce-buffer default.
+    fn is_vectored(&self) -> bool {
+        false
+    }
+
+    /// Send `req_head` followed by `req_data` and scatter the reply, returning its length.
+    ///
+    /// The first `resp_head.len()` bytes of the reply land in `resp_head` and the
+    /// remainder in `resp_data`. The default gathers through a bounce buffer;
+    /// transports that can scatter/gather (e.g. virtqueue descriptors) should
+    /// override it so payloads move straight between caller buffers and the wire.
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let joined: Vec<u8>;
+        let req = if req_data.is_empty() {
+            req_head
+        } else {
+            let len = req_head.len() + req_data.iter().map(|data| data.len()).sum::<usize>();
+            let mut buf = Vec::with_capacity(len);
+            buf.extend_from_slice(req_head);
+            for data in req_data {
+                buf.extend_from_slice(data);
+            }
+            joined = buf;
+            &joined
+        };
+        let mut resp = vec![0u8; resp_head.len() + resp_data.len()];
+        let size = self.request(req, &mut resp)?;
+        if size > resp.len() {
+            return Err(String::from("transport reply exceeds buffer"));
+        }
+        let head = size.min(resp_head.len());
+        resp_head[..head].copy_from_slice(&resp[..head]);
+        resp_data[..size - head].copy_from_slice(&resp[head..size]);
+        Ok(size)
+    }
+
+    /// Send independent requests back to back, writing each reply into the
+    /// matching buffer and returning the reply lengths.
+    ///
+    /// Every request must carry a distinct tag; replies are matched by tag, not
+    /// arrival order. The default issues one request at a time; transports that
+    /// can keep several requests in flight override it to save round trips.
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        reqs.iter()
+            .zip(resps.iter_mut())
+            .map(|(req, resp)| self.request(req, resp))
+            .collect()
+    }
+}
+
+/// Lets wrappers such as [`RetryTransport`](crate::RetryTransport) take a
+/// boxed transport, like the one `connect` returns.
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        (**self).request(req, resp)
+    }
+
+    fn is_vectored(&self) -> bool {
+        (**self).is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        (**self).request_vectored(req_head, req_data, resp_head, resp_data)
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        (**self).request_batch(reqs, resps)
+    }
 }
@@ -0,0 +1,160 @@
+//! virtio-9p transport backed by the `virtio-drivers` crate.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use virtio_drivers::device::common::Feature;
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::Transport as VirtioDeviceTransport;
+use virtio_drivers::{Error, Hal};
+
+use crate::session::P9Session;
+use crate::transport::Transport;
+
+const QUEUE: u16 = 0;
+/// Descriptors in the request queue, and so the longest buffer chain.
+const QUEUE_SIZE: usize = 16;
+const SUPPORTED_FEATURES: Feature = Feature::RING_INDIRECT_DESC
+    .union(Feature::RING_EVENT_IDX)
+    .union(Feature::VERSION_1);
+
+/// The device transport and its single request queue.
+struct Device<H: Hal, T: VirtioDeviceTransport> {
+    transport: T,
+    queue: VirtQueue<H, QUEUE_SIZE>,
+}
+
+/// Transport over a virtio-9p device (QEMU `-virtfs` / `-device virtio-9p-*`).
+///
+/// Vectored requests become one descriptor per buffer, so TREAD and TWRITE
+/// payloads move between the device and the caller's buffers directly.
+pub struct VirtioTransport<H: Hal, T: VirtioDeviceTransport> {
+    device: Mutex<Device<H, T>>,
+    mount_tag: String,
+}
+
+impl<H: Hal, T: VirtioDeviceTransport> VirtioTransport<H, T> {
+    /// Initialise the device behind `transport` and read its mount tag from config space.
+    pub fn new(mut transport: T) -> Result<Self, String> {
+        let init_error = |err: Error| format!("virtio-9p init failed: {}", err);
+        let features = transport.begin_init(SUPPORTED_FEATURES);
+        let queue = VirtQueue::new(
+            &mut transport,
+            QUEUE,
+            features.contains(Feature::RING_INDIRECT_DESC),
+            features.contains(Feature::RING_EVENT_IDX),
+        )
+        .map_err(init_error)?;
+        transport.finish_init();
+        let mount_tag = read_mount_tag(&transport).map_err(init_error)?;
+        Ok(Self {
+            device: Mutex::new(Device { transport, queue }),
+            mount_tag,
+        })
+    }
+
+    /// Mount tag advertised by the device.
+    pub fn mount_tag(&self) -> &str {
+        &self.mount_tag
+    }
+}
+
+impl<H, T> VirtioTransport<H, T>
+where
+    H: Hal + 'static,
+    T: VirtioDeviceTransport + Send + 'static,
+{
+    /// Build a session attached to the export named by the device's mount tag.
+    pub fn into_session(self) -> P9Session {
+        let mount_tag = self.mount_tag.clone();
+        P9Session::new(Box::new(self), mount_tag)
+    }
+}
+
+/// Read the length-prefixed mount tag from the device config space.
+fn read_mount_tag<T: VirtioDeviceTransport>(transport: &T) -> Result<String, Error> {
+    transport.read_consistent(|| {
+        let len: u16 = transport.read_config_space(0)?;
+        if len == 0 {
+            return Err(Error::InvalidParam);
+        }
+        let bytes = (0..usize::from(len))
+            .map(|index| transport.read_config_space::<u8>(2 + index))
+            .collect::<Result<Vec<u8>, Error>>()?;
+        Ok(String::from_utf8(bytes)?)
+    })
+}
+
+impl<H: Hal, T: VirtioDeviceTransport + Send> Transport for VirtioTransport<H, T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.request_vectored(req, &[], resp, &mut [])
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+
+    /// Chain `req_head`, each slice of `req_data`, `resp_head` and `resp_data`
+    /// as separate descriptors. Request data is gathered into one buffer only
+    /// when the chain would not fit the queue.
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let joined: Vec<u8>;
+        let mut inputs: [&[u8]; QUEUE_SIZE] = [&[]; QUEUE_SIZE];
+        let mut ninputs = 0;
+        if 1 + req_data.len() + 2 <= QUEUE_SIZE {
+            for data in core::iter::once(&req_head).chain(req_data) {
+                if !data.is_empty() {
+                    inputs[ninputs] = data;
+                    ninputs += 1;
+                }
+            }
+        } else {
+            let mut buf = Vec::with_capacity(req_head.len() + req_data.iter().map(|data| data.len()).sum::<usize>());
+            buf.extend_from_slice(req_head);
+            for data in req_data {
+                buf.extend_from_slice(data);
+            }
+            joined = buf;
+            inputs[0] = &joined;
+            ninputs = 1;
+        }
+        if ninputs == 0 || resp_head.len() + resp_data.len() < 7 {
+            return Err(String::from("virtio-9p request failed: empty request or reply buffer"));
+        }
+        // Descriptors may not be empty.
+        let used = {
+            let mut outputs = [&mut *resp_head, &mut *resp_data];
+            if outputs[0].is_empty() {
+                outputs.swap(0, 1);
+            }
+            let noutputs = if outputs[1].is_empty() { 1 } else { 2 };
+            let mut device = self.device.lock();
+            let Device { transport, queue } = &mut *device;
+            queue
+                .add_notify_wait_pop(&inputs[..ninputs], &mut outputs[..noutputs], transport)
+                .map_err(|err| format!("virtio-9p request failed: {}", err))? as usize
+        };
+
+        // The size field may straddle the two reply buffers.
+        let mut size = [0u8; 4];
+        for (byte, value) in size.iter_mut().zip(resp_head.iter().chain(resp_data.iter())) {
+            *byte = *value;
+        }
+        let size = u32::from_le_bytes(size) as usize;
+        if size != used {
+            return Err(format!(
+                "virtio-9p request failed: size field {} does not match used length {}",
+                size, used
+            ));
+        }
+        Ok(used)
+    }
+}
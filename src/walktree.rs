@@ -0,0 +1,284 @@
+//! Recursive directory tree walker.
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::parse::{dirent_type, lossy};
+use crate::protocol::Qid;
+use crate::session::{FileAttr, P9Session};
+
+/// Order in which [`WalkTree`] visits entries.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WalkOrder {
+    /// Each directory's contents follow it before its next sibling.
+    #[default]
+    DepthFirst,
+    /// All entries at one depth come before any entry deeper down.
+    BreadthFirst,
+}
+
+/// Options for [`Session::walk_tree_with`](crate::Session::walk_tree_with).
+#[derive(Clone, Copy, Debug)]
+pub struct WalkTreeOptions {
+    pub order: WalkOrder,
+    /// Entries shallower than this are traversed but not yielded; the root is depth 0.
+    pub min_depth: usize,
+    /// Directories at this depth are yielded but not descended into.
+    pub max_depth: usize,
+    /// Yield [`WalkEvent::Post`] after a directory's contents (depth-first only).
+    pub post_order: bool,
+    /// Descend through symlinks to directories, failing on loops.
+    pub follow_symlinks: bool,
+    /// Fetch [`FileAttr`]s for every entry (9P2000.L only).
+    pub metadata: bool,
+    /// Fill [`TreeEntry::hard_link_of`] for files seen earlier under another path.
+    pub hard_links: bool,
+}
+
+impl Default for WalkTreeOptions {
+    fn default() -> Self {
+        Self {
+            order: WalkOrder::DepthFirst,
+            min_depth: 0,
+            max_depth: usize::MAX,
+            post_order: false,
+            follow_symlinks: false,
+            metadata: false,
+            hard_links: false,
+        }
+    }
+}
+
+/// One file or directory found by [`WalkTree`].
+#[derive(Clone, Debug)]
+pub struct TreeEntry {
+    /// The walk root joined with the names below it.
+    pub path: Vec<u8>,
+    /// Distance from the walk root, which is depth 0.
+    pub depth: usize,
+    /// d_type as in [`P9DirEntry`](crate::P9DirEntry); of the target when a symlink was followed.
+    pub entry_type: u8,
+    /// Server identity of the entry, or of the target when a symlink was followed.
+    pub qid: Qid,
+    /// Attributes, when [`WalkTreeOptions::metadata`] is set.
+    pub attr: Option<FileAttr>,
+    /// Path of the first entry seen with the same qid, when [`WalkTreeOptions::hard_links`] is set.
+    pub hard_link_of: Option<Vec<u8>>,
+}
+
+impl TreeEntry {
+    /// The last component of [`TreeEntry::path`].
+    pub fn file_name(&self) -> &[u8] {
+        let path = self.path.strip_suffix(b"/").unwrap_or(&self.path);
+        path.rsplit(|byte| *byte == b'/').next().unwrap_or(path)
+    }
+
+    /// Returns true if the entry is (or resolves to) a directory.
+    pub fn is_dir(&self) -> bool {
+        self.qid.type_ & 0x80 != 0
+    }
+}
+
+/// Item yielded by [`WalkTree`].
+#[derive(Clone, Debug)]
+pub enum WalkEvent {
+    /// An entry, yielded before the contents of a directory.
+    Pre(TreeEntry),
+    /// A directory again, after its contents, when [`WalkTreeOptions::post_order`] is set.
+    Post(TreeEntry),
+}
+
+type Filter<'a> = Box<dyn FnMut(&TreeEntry) -> bool + 'a>;
+
+struct Node {
+    entry: TreeEntry,
+    /// Path to list on the server; differs from `entry.path` below a followed symlink.
+    real: Vec<u8>,
+    /// qid.path of every directory above this one, for loop detection.
+    ancestors: Rc<Vec<u64>>,
+}
+
+enum Pending {
+    Visit(Node),
+    List(Node),
+    Leave(TreeEntry),
+}
+
+/// Iterator over a directory tree, in the spirit of the `walkdir` crate.
+///
+/// Each directory is listed in full and closed before its entries are
+/// yielded, so no fids stay open between calls to `next` regardless of tree
+/// depth. The root is followed if it is a symlink. An error for one entry or
+/// directory is yielded in its place and the walk continues.
+pub struct WalkTree<'a> {
+    session: &'a mut P9Session,
+    options: WalkTreeOptions,
+    root: Option<Vec<u8>>,
+    filter: Option<Filter<'a>>,
+    pending: VecDeque<Pending>,
+    links: BTreeMap<u64, Vec<u8>>,
+}
+
+impl<'a> WalkTree<'a> {
+    pub(crate) fn new(session: &'a mut P9Session, root: Vec<u8>, options: WalkTreeOptions) -> Self {
+        Self {
+            session,
+            options,
+            root: Some(root),
+            filter: None,
+            pending: VecDeque::new(),
+            links: BTreeMap::new(),
+        }
+    }
+
+    /// Skip entries for which `predicate` returns false, and everything below them.
+    pub fn filter_entry(mut self, predicate: impl FnMut(&TreeEntry) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+
+    fn start(&mut self, root: Vec<u8>) -> Result<(), String> {
+        let (real, qid) = self.session.lookup_qid(&root, true)?;
+        let attr = self.attr(&real)?;
+        let entry = TreeEntry {
+            path: root,
+            depth: 0,
+            entry_type: dirent_type(qid),
+            qid,
+            attr,
+            hard_link_of: None,
+        };
+        self.pending.push_back(Pending::Visit(Node {
+            entry,
+            real,
+            ancestors: Rc::new(Vec::new()),
+        }));
+        Ok(())
+    }
+
+    fn attr(&mut self, path: &[u8]) -> Result<Option<FileAttr>, String> {
+        if self.options.metadata && self.session.is_dotl() {
+            self.session.getattr(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn pop(&mut self) -> Option<Pending> {
+        match self.options.order {
+            WalkOrder::DepthFirst => self.pending.pop_back(),
+            WalkOrder::BreadthFirst => self.pending.pop_front(),
+        }
+    }
+
+    /// Decide whether `node` is yielded and whether its contents are listed.
+    fn visit(&mut self, mut node: Node) -> Result<Option<TreeEntry>, String> {
+        if self.options.follow_symlinks && node.entry.qid.type_ & 0x02 != 0 {
+            let (real, qid) = self
+                .session
+                .lookup_qid(&node.real, true)
+                .map_err(|err| format!("{}: {}", lossy(&node.entry.path), err))?;
+            node.entry.attr = self.attr(&real)?;
+            node.entry.entry_type = dirent_type(qid);
+            node.entry.qid = qid;
+            node.real = real;
+        }
+        if let Some(filter) = self.filter.as_mut()
+            && !filter(&node.entry)
+        {
+            return Ok(None);
+        }
+        let entry = &mut node.entry;
+        if self.options.hard_links && !entry.is_dir() && entry.attr.as_ref().is_none_or(|attr| attr.nlink > 1) {
+            match self.links.entry(entry.qid.path) {
+                Entry::Occupied(first) => entry.hard_link_of = Some(first.get().clone()),
+                Entry::Vacant(slot) => {
+                    slot.insert(entry.path.clone());
+                }
+            }
+        }
+        let yielded = (entry.depth >= self.options.min_depth).then(|| entry.clone());
+        if entry.is_dir() && entry.depth < self.options.max_depth {
+            if self.options.follow_symlinks && node.ancestors.contains(&entry.qid.path) {
+                return Err(format!("{}: filesystem loop", lossy(&entry.path)));
+            }
+            let depth_first = self.options.order == WalkOrder::DepthFirst;
+            if self.options.post_order && depth_first && yielded.is_some() {
+                self.pending.push_back(Pending::Leave(entry.clone()));
+            }
+            self.pending.push_back(Pending::List(node));
+        }
+        Ok(yielded)
+    }
+
+    /// Queue the contents of the directory `node`.
+    fn list(&mut self, node: Node) -> Result<(), String> {
+        let children = self.session.read_tree_dir(&node.real, self.options.metadata)?;
+        let mut ancestors = (*node.ancestors).clone();
+        ancestors.push(node.entry.qid.path);
+        let ancestors = Rc::new(ancestors);
+        let nodes = children.into_iter().map(|(child, attr)| Node {
+            entry: TreeEntry {
+                path: join(&node.entry.path, &child.name),
+                depth: node.entry.depth + 1,
+                entry_type: child.entry_type,
+                qid: child.qid,
+                attr,
+                hard_link_of: None,
+            },
+            real: join(&node.real, &child.name),
+            ancestors: ancestors.clone(),
+        });
+        match self.options.order {
+            WalkOrder::DepthFirst => {
+                let nodes: Vec<Node> = nodes.collect();
+                self.pending.extend(nodes.into_iter().rev().map(Pending::Visit));
+            }
+            WalkOrder::BreadthFirst => self.pending.extend(nodes.map(Pending::Visit)),
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for WalkTree<'_> {
+    type Item = Result<WalkEvent, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take()
+            && let Err(err) = self.start(root)
+        {
+            return Some(Err(err));
+        }
+        while let Some(pending) = self.pop() {
+            match pending {
+                Pending::Visit(node) => match self.visit(node) {
+                    Ok(Some(entry)) => return Some(Ok(WalkEvent::Pre(entry))),
+                    Ok(None) => {}
+                    Err(err) => return Some(Err(err)),
+                },
+                Pending::List(node) => {
+                    if let Err(err) = self.list(node) {
+                        return Some(Err(err));
+                    }
+                }
+                Pending::Leave(entry) => return Some(Ok(WalkEvent::Post(entry))),
+            }
+        }
+        None
+    }
+}
+
+/// Append `name` to `base` with a single `/` between them.
+fn join(base: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = base.to_vec();
+    if !path.ends_with(b"/") {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    path
+}
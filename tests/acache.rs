@@ -0,0 +1,132 @@
+//! Attribute cache modes, expiry and local updates against the in-memory server.
+
+mod common;
+
+use std::time::Duration;
+
+use common::Mem;
+use fs9p::{AttrCacheConfig, CacheMode, PageCacheConfig, PageCacheMode, Session, VirtualClock};
+
+const TGETATTR: u8 = 24;
+
+fn cached(server: &Mem, mode: CacheMode) -> (Session, VirtualClock) {
+    let clock = VirtualClock::new();
+    let mut session = common::session(server);
+    session.set_clock(Box::new(clock.clone()));
+    session.enable_attr_cache(AttrCacheConfig { mode, ttl: Duration::from_secs(1), capacity: 16 });
+    (session, clock)
+}
+
+/// Change the mode of `id` as another client would: data_version moves, qid.version does not.
+fn chmod_behind_our_back(server: &Mem, id: u64, mode: u32) {
+    let mut state = server.state();
+    let node = state.nodes.get_mut(&id).unwrap();
+    node.mode = (node.mode & !0o7777) | mode;
+    node.data_version += 1;
+}
+
+#[test]
+fn entries_expire_after_the_ttl() {
+    let server = Mem::new();
+    server.add_file("/f", b"data");
+    let (mut session, clock) = cached(&server, CacheMode::Loose);
+    session.getattr("/f").unwrap();
+    session.getattr("/f").unwrap();
+    assert_eq!(server.count(TGETATTR), 1);
+
+    clock.advance(Duration::from_millis(999));
+    session.getattr("/f").unwrap();
+    assert_eq!(server.count(TGETATTR), 1);
+    clock.advance(Duration::from_millis(1));
+    session.getattr("/f").unwrap();
+    assert_eq!(server.count(TGETATTR), 2);
+}
+
+#[test]
+fn qid_version_changes_are_refetched() {
+    let server = Mem::new();
+    server.add_file("/f", b"data");
+    let (mut session, _clock) = cached(&server, CacheMode::Loose);
+    assert_eq!(session.getattr("/f").unwrap().size, 4);
+    server.rewrite_file("/f", b"longer data");
+    assert_eq!(session.getattr("/f").unwrap().size, 11);
+    assert_eq!(server.count(TGETATTR), 2);
+}
+
+#[test]
+fn mode_none_asks_the_server_every_time() {
+    let server = Mem::new();
+    server.add_file("/f", b"data");
+    let (mut session, _clock) = cached(&server, CacheMode::None);
+    for expected in 1..=3 {
+        session.getattr("/f").unwrap();
+        assert_eq!(server.count(TGETATTR), expected);
+    }
+}
+
+#[test]
+fn close_to_open_revalidates_with_data_version() {
+    for mode in [CacheMode::Loose, CacheMode::CloseToOpen] {
+        let server = Mem::new();
+        let id = server.add_file("/f", b"data");
+        let (mut session, _clock) = cached(&server, mode);
+        assert_eq!(session.getattr("/f").unwrap().mode & 0o7777, 0o644);
+
+        // Unchanged files keep their entry across an open.
+        let fid = session.open_path_with_flags("/f", 0, 0).unwrap();
+        session.close_fid(fid).unwrap();
+        assert_eq!(session.getattr("/f").unwrap().mode & 0o7777, 0o644);
+        let fetched = server.count(TGETATTR);
+
+        chmod_behind_our_back(&server, id, 0o600);
+        assert_eq!(session.getattr("/f").unwrap().mode & 0o7777, 0o644, "{mode:?}");
+        let fid = session.open_path_with_flags("/f", 0, 0).unwrap();
+        session.close_fid(fid).unwrap();
+        let seen = session.getattr("/f").unwrap().mode & 0o7777;
+        match mode {
+            CacheMode::CloseToOpen => assert_eq!(seen, 0o600),
+            _ => assert_eq!(seen, 0o644),
+        }
+        assert_eq!(server.count(TGETATTR) - fetched, usize::from(mode == CacheMode::CloseToOpen));
+    }
+}
+
+#[test]
+fn local_changes_update_the_cached_entry() {
+    let server = Mem::new();
+    server.add_file("/f", b"data");
+    let (mut session, _clock) = cached(&server, CacheMode::Loose);
+    session.getattr("/f").unwrap();
+
+    session.setattr_mode("/f", 0o600).unwrap();
+    assert_eq!(session.getattr("/f").unwrap().mode & 0o7777, 0o600);
+    let fid = session.open_path_with_flags("/f", 1, 1).unwrap();
+    session.truncate_fid(fid, 2).unwrap();
+    assert_eq!(session.getattr("/f").unwrap().size, 2);
+    assert_eq!(server.count(TGETATTR), 1, "setattr and truncate were applied locally");
+
+    // Writes move qid.version on the server, so the next lookup refetches.
+    session.write_fid(fid, 0, b"longer").unwrap();
+    assert_eq!(session.getattr("/f").unwrap().size, 6);
+    session.close_fid(fid).unwrap();
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn close_to_open_keeps_sizes_of_unflushed_writes() {
+    let server = Mem::new();
+    server.add_file("/f", b"data");
+    let (mut session, _clock) = cached(&server, CacheMode::CloseToOpen);
+    let config = PageCacheConfig { mode: PageCacheMode::Loose, ..PageCacheConfig::default() };
+    session.enable_page_cache(config).unwrap();
+    session.getattr("/f").unwrap();
+
+    let writer = session.open_path_with_flags("/f", 1, 1).unwrap();
+    session.write_fid(writer, 4, b" and more").unwrap();
+    assert!(server.state().writes.is_empty());
+    let reader = session.open_path_with_flags("/f", 0, 0).unwrap();
+    assert_eq!(session.getattr("/f").unwrap().size, 13, "server data_version did not move");
+    session.close_fid(reader).unwrap();
+    session.close_fid(writer).unwrap();
+    assert_eq!(server.file("/f").unwrap(), b"data and more");
+}
@@ -0,0 +1,127 @@
+//! Steady-state session traffic must not touch the heap.
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::Mem;
+use fs9p::Transport;
+
+/// Counts allocations made on threads that have counting switched on.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn counting() -> bool {
+    COUNTING.try_with(Cell::get).unwrap_or(false)
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if counting() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if counting() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Allocations made by `f` on this thread.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    COUNTING.with(|counting| counting.set(true));
+    f();
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// Runs the in-memory server with counting paused, so only the client is measured.
+#[derive(Clone)]
+struct Uncounted(Mem);
+
+impl Transport for Uncounted {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let was = COUNTING.with(|counting| counting.replace(false));
+        let result = self.0.request(req, resp);
+        COUNTING.with(|counting| counting.set(was));
+        result
+    }
+}
+
+fn session(server: &Mem) -> fs9p::Session {
+    let mut session = fs9p::Session::new(Box::new(Uncounted(server.clone())), String::from("test"));
+    session.negotiate().unwrap();
+    session
+}
+
+#[test]
+fn walk_getattr_clunk_does_not_allocate() {
+    let server = Mem::new();
+    server.add_file("/usr/share/doc/readme", b"hello");
+    let mut session = session(&server);
+    session.getattr("/usr/share/doc/readme").unwrap();
+    let fids = server.live_fids();
+
+    let count = allocations(|| {
+        for _ in 0..100 {
+            let attr = session.getattr("/usr/share/doc/readme").unwrap();
+            assert_eq!(attr.size, 5);
+        }
+    });
+    assert_eq!(count, 0);
+    assert_eq!(server.live_fids(), fids);
+}
+
+#[test]
+fn deep_walk_does_not_allocate() {
+    let server = Mem::new();
+    let path = (0..40).map(|depth| format!("/d{depth}")).collect::<String>() + "/leaf";
+    server.add_file(&path, b"x");
+    let mut session = session(&server);
+    session.getattr(&path).unwrap();
+
+    let count = allocations(|| {
+        session.getattr(&path).unwrap();
+    });
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn read_into_does_not_allocate() {
+    let server = Mem::new();
+    let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    server.add_file("/data", &data);
+    let mut session = session(&server);
+    let fid = session.open_path_with_flags("/data", 0, 0).unwrap();
+    let mut buf = vec![0u8; 1000];
+    session.read_into(fid, 0, &mut buf).unwrap();
+
+    let count = allocations(|| {
+        for offset in (0..data.len()).step_by(buf.len()) {
+            let len = session.read_into(fid, offset as u64, &mut buf).unwrap();
+            assert_eq!(buf[..len], data[offset..offset + len]);
+        }
+    });
+    assert_eq!(count, 0);
+    session.close_fid(fid).unwrap();
+    assert_eq!(server.live_fids(), 1);
+}
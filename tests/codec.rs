@@ -0,0 +1,230 @@
+//! Encode/decode round trips for every message type in each dialect.
+
+use fs9p::{Attr, Dialect, Flock, Getlock, Qid, Rmessage, SetAttr, StatFs, Tmessage, WalkList};
+
+const DIALECTS: [Dialect; 3] = [Dialect::P2000, Dialect::P2000U, Dialect::P2000L];
+
+const QID: Qid = Qid { type_: 0x80, version: 7, path: 0x0102_0304_0506_0708 };
+
+fn n_uname(dialect: Dialect) -> Option<u32> {
+    (dialect != Dialect::P2000).then_some(1000)
+}
+
+fn extension(dialect: Dialect) -> Option<&'static [u8]> {
+    (dialect == Dialect::P2000U).then_some(b"/target".as_slice())
+}
+
+fn errno(dialect: Dialect) -> Option<u32> {
+    (dialect == Dialect::P2000U).then_some(2)
+}
+
+fn tmessages(dialect: Dialect) -> Vec<Tmessage<'static>> {
+    let wnames = WalkList::try_from(&[b"usr".as_slice(), b"lib", b"\xffraw"][..]).unwrap();
+    vec![
+        Tmessage::Version { msize: 8192, version: "9P2000.L" },
+        Tmessage::Auth { afid: 1, uname: "glenda", aname: "/", n_uname: n_uname(dialect) },
+        Tmessage::Flush { oldtag: 3 },
+        Tmessage::Attach { fid: 0, afid: u32::MAX, uname: "glenda", aname: "", n_uname: n_uname(dialect) },
+        Tmessage::Walk { fid: 0, newfid: 1, wnames },
+        Tmessage::Walk { fid: 0, newfid: 0, wnames: WalkList::new() },
+        Tmessage::Open { fid: 1, mode: 0x11 },
+        Tmessage::Create { fid: 1, name: b"new", perm: 0o644, mode: 1, extension: extension(dialect) },
+        Tmessage::Read { fid: 1, offset: 1 << 40, count: 4096 },
+        Tmessage::Write { fid: 1, offset: 9, data: b"hello\0world" },
+        Tmessage::Clunk { fid: 1 },
+        Tmessage::Remove { fid: 2 },
+        Tmessage::Stat { fid: 3 },
+        Tmessage::Wstat { fid: 3, stat: b"\x01\x02\x03\x04" },
+        Tmessage::Statfs { fid: 4 },
+        Tmessage::Lopen { fid: 5, flags: 0o100002 },
+        Tmessage::Lcreate { fid: 5, name: b"file", flags: 0o101, mode: 0o600, gid: 100 },
+        Tmessage::Symlink { fid: 5, name: b"link", symtgt: b"../target", gid: 100 },
+        Tmessage::Mknod { dfid: 5, name: b"dev", mode: 0o20644, major: 1, minor: 3, gid: 0 },
+        Tmessage::Rename { fid: 6, dfid: 5, name: b"moved" },
+        Tmessage::Readlink { fid: 6 },
+        Tmessage::Getattr { fid: 6, request_mask: 0x3fff },
+        Tmessage::Setattr {
+            fid: 6,
+            attr: SetAttr {
+                valid: 0x1ff,
+                mode: 0o755,
+                uid: 1,
+                gid: 2,
+                size: 3,
+                atime_sec: 4,
+                atime_nsec: 5,
+                mtime_sec: 6,
+                mtime_nsec: 7,
+            },
+        },
+        Tmessage::Xattrwalk { fid: 6, newfid: 7, name: "user.tag" },
+        Tmessage::Xattrcreate { fid: 7, name: "user.tag", attr_size: 12, flags: 1 },
+        Tmessage::Readdir { fid: 5, offset: 0xdead_beef, count: 8168 },
+        Tmessage::Fsync { fid: 6, datasync: 1 },
+        Tmessage::Lock {
+            fid: 6,
+            lock: Flock { type_: 1, flags: 1, start: 10, length: 20, proc_id: 42, client_id: "host" },
+        },
+        Tmessage::Getlock {
+            fid: 6,
+            lock: Getlock { type_: 0, start: 0, length: u64::MAX, proc_id: 42, client_id: "host" },
+        },
+        Tmessage::Link { dfid: 5, fid: 6, name: b"hard" },
+        Tmessage::Mkdir { dfid: 5, name: b"sub", mode: 0o755, gid: 100 },
+        Tmessage::Renameat { olddirfid: 5, oldname: b"a", newdirfid: 8, newname: b"b" },
+        Tmessage::Unlinkat { dirfd: 5, name: b"sub", flags: 0x200 },
+    ]
+}
+
+fn rmessages(dialect: Dialect) -> Vec<Rmessage<'static>> {
+    let wqids = WalkList::try_from(&[QID, Qid { type_: 0, version: 1, path: 2 }][..]).unwrap();
+    vec![
+        Rmessage::Version { msize: 8192, version: "9P2000.u" },
+        Rmessage::Auth { aqid: QID },
+        Rmessage::Error { ename: "file not found", errno: errno(dialect) },
+        Rmessage::Flush,
+        Rmessage::Attach { qid: QID },
+        Rmessage::Walk { wqids },
+        Rmessage::Walk { wqids: WalkList::new() },
+        Rmessage::Open { qid: QID, iounit: 8168 },
+        Rmessage::Create { qid: QID, iounit: 0 },
+        Rmessage::Read { data: b"\x00\x01\x02" },
+        Rmessage::Read { data: b"" },
+        Rmessage::Write { count: 11 },
+        Rmessage::Clunk,
+        Rmessage::Remove,
+        Rmessage::Stat { stat: b"\x05\x06\x07" },
+        Rmessage::Wstat,
+        Rmessage::Lerror { ecode: 13 },
+        Rmessage::Statfs(StatFs {
+            type_: 0x01021997,
+            bsize: 4096,
+            blocks: 1,
+            bfree: 2,
+            bavail: 3,
+            files: 4,
+            ffree: 5,
+            fsid: 6,
+            namelen: 255,
+        }),
+        Rmessage::Lopen { qid: QID, iounit: 4096 },
+        Rmessage::Lcreate { qid: QID, iounit: 4096 },
+        Rmessage::Symlink { qid: QID },
+        Rmessage::Mknod { qid: QID },
+        Rmessage::Rename,
+        Rmessage::Readlink { target: b"../\xfftarget" },
+        Rmessage::Getattr(Attr {
+            valid: 0x7ff,
+            qid: QID,
+            mode: 0o100644,
+            uid: 1,
+            gid: 2,
+            nlink: 3,
+            rdev: 4,
+            size: 5,
+            blksize: 6,
+            blocks: 7,
+            atime_sec: 8,
+            atime_nsec: 9,
+            mtime_sec: 10,
+            mtime_nsec: 11,
+            ctime_sec: 12,
+            ctime_nsec: 13,
+            btime_sec: 14,
+            btime_nsec: 15,
+            generation: 16,
+            data_version: 17,
+        }),
+        Rmessage::Setattr,
+        Rmessage::Xattrwalk { size: 12 },
+        Rmessage::Xattrcreate,
+        Rmessage::Readdir { data: b"entries" },
+        Rmessage::Fsync,
+        Rmessage::Lock { status: 1 },
+        Rmessage::Getlock(Getlock { type_: 2, start: 1, length: 2, proc_id: 3, client_id: "peer" }),
+        Rmessage::Link,
+        Rmessage::Mkdir { qid: QID },
+        Rmessage::Renameat,
+        Rmessage::Unlinkat,
+    ]
+}
+
+#[test]
+fn tmessages_round_trip() {
+    for dialect in DIALECTS {
+        for (tag, msg) in tmessages(dialect).into_iter().enumerate() {
+            let mut buf = Vec::new();
+            msg.encode(tag as u16, dialect, &mut buf)
+                .unwrap_or_else(|err| panic!("{dialect:?} {msg:?}: {err}"));
+            assert_eq!(buf[4], msg.msg_type());
+            let (decoded_tag, decoded) =
+                Tmessage::decode(&buf, dialect).unwrap_or_else(|err| panic!("{dialect:?} {msg:?}: {err}"));
+            assert_eq!(decoded_tag, tag as u16);
+            assert_eq!(decoded, msg, "{dialect:?}");
+        }
+    }
+}
+
+#[test]
+fn rmessages_round_trip() {
+    for dialect in DIALECTS {
+        for (tag, msg) in rmessages(dialect).into_iter().enumerate() {
+            let mut buf = Vec::new();
+            msg.encode(tag as u16, dialect, &mut buf)
+                .unwrap_or_else(|err| panic!("{dialect:?} {msg:?}: {err}"));
+            assert_eq!(buf[4], msg.msg_type());
+            let (decoded_tag, decoded) =
+                Rmessage::decode(&buf, dialect).unwrap_or_else(|err| panic!("{dialect:?} {msg:?}: {err}"));
+            assert_eq!(decoded_tag, tag as u16);
+            assert_eq!(decoded, msg, "{dialect:?}");
+        }
+    }
+}
+
+#[test]
+fn optional_fields_must_match_dialect() {
+    for dialect in DIALECTS {
+        let wrong_uname = if dialect == Dialect::P2000 { Some(1000) } else { None };
+        let wrong_ext = if dialect == Dialect::P2000U { None } else { Some(b"x".as_slice()) };
+        let wrong_errno = if dialect == Dialect::P2000U { None } else { Some(2) };
+        let tmsgs = [
+            Tmessage::Auth { afid: 1, uname: "u", aname: "", n_uname: wrong_uname },
+            Tmessage::Attach { fid: 0, afid: u32::MAX, uname: "u", aname: "", n_uname: wrong_uname },
+            Tmessage::Create { fid: 1, name: b"f", perm: 0o644, mode: 1, extension: wrong_ext },
+        ];
+        for msg in tmsgs {
+            let mut buf = vec![1, 2, 3];
+            assert!(msg.encode(0, dialect, &mut buf).is_err(), "{dialect:?} {msg:?}");
+            assert!(buf.is_empty());
+        }
+        let msg = Rmessage::Error { ename: "e", errno: wrong_errno };
+        let mut buf = Vec::new();
+        assert!(msg.encode(0, dialect, &mut buf).is_err(), "{dialect:?} {msg:?}");
+        assert!(buf.is_empty());
+    }
+}
+
+#[test]
+fn walk_lists_hold_sixteen_elements() {
+    let names = [b"a".as_slice(); 17];
+    assert!(WalkList::try_from(&names[..16]).is_ok());
+    assert!(WalkList::try_from(&names[..]).is_err());
+    let mut list = WalkList::new();
+    for name in &names[..16] {
+        list.push(*name).unwrap();
+    }
+    assert_eq!(list.len(), 16);
+    assert!(list.push(b"b").is_err());
+}
+
+#[test]
+fn decode_rejects_trailing_bytes_and_bad_sizes() {
+    let mut buf = Vec::new();
+    Tmessage::Clunk { fid: 1 }.encode(0, Dialect::P2000L, &mut buf).unwrap();
+    let mut long = buf.clone();
+    long.push(0);
+    assert!(Tmessage::decode(&long, Dialect::P2000L).is_err());
+    long[0] += 1;
+    assert!(Tmessage::decode(&long, Dialect::P2000L).is_err());
+    assert!(Tmessage::decode(&buf[..buf.len() - 1], Dialect::P2000L).is_err());
+}
@@ -0,0 +1,593 @@
+//! In-memory 9P2000.L server shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use fs9p::{Attr, ByteStream, Dialect, Qid, Rmessage, Session, Tmessage, Transport, WalkList};
+
+pub const ENOENT: u32 = 2;
+pub const EIO: u32 = 5;
+pub const EBADF: u32 = 9;
+pub const EACCES: u32 = 13;
+pub const EEXIST: u32 = 17;
+pub const ENOTDIR: u32 = 20;
+pub const EISDIR: u32 = 21;
+pub const EINVAL: u32 = 22;
+pub const EOPNOTSUPP: u32 = 95;
+
+pub const ROOT: u64 = 1;
+
+/// Tlopen truncate flag as the session sends it.
+const LOPEN_TRUNC: u32 = 0x1000;
+
+#[derive(Clone, Debug)]
+pub enum Kind {
+    Dir(BTreeMap<Vec<u8>, u64>),
+    File(Vec<u8>),
+    Symlink(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub kind: Kind,
+    pub mode: u32,
+    pub version: u32,
+    /// Reported as the RGETATTR data_version.
+    pub data_version: u64,
+}
+
+#[derive(Debug)]
+pub struct State {
+    pub nodes: HashMap<u64, Node>,
+    pub fids: HashMap<u32, u64>,
+    pub next: u64,
+    /// Type byte of every request received, in order.
+    pub log: Vec<u8>,
+    /// Offset and byte count of every Tread and Twrite, in order.
+    pub reads: Vec<(u64, u32)>,
+    pub writes: Vec<(u64, u32)>,
+    /// iounit reported by Tlopen and Tlcreate.
+    pub iounit: u32,
+}
+
+/// A 9P2000.L file server kept in memory; clones share the same tree.
+#[derive(Clone)]
+pub struct Mem {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Mem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem {
+    pub fn new() -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(ROOT, Node { kind: Kind::Dir(BTreeMap::new()), mode: 0o40755, version: 0, data_version: 0 });
+        let state = State {
+            nodes,
+            fids: HashMap::new(),
+            next: ROOT + 1,
+            log: Vec::new(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            iounit: 0,
+        };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Fids the server currently holds, including the session's root fid.
+    pub fn live_fids(&self) -> usize {
+        self.state().fids.len()
+    }
+
+    /// Number of requests of type `msg_type` received so far.
+    pub fn count(&self, msg_type: u8) -> usize {
+        self.state().log.iter().filter(|logged| **logged == msg_type).count()
+    }
+
+    /// Add a file at `path`, creating missing parent directories.
+    pub fn add_file(&self, path: &str, data: &[u8]) -> u64 {
+        self.add(path, Kind::File(data.to_vec()), 0o100644)
+    }
+
+    pub fn add_dir(&self, path: &str) -> u64 {
+        self.add(path, Kind::Dir(BTreeMap::new()), 0o40755)
+    }
+
+    pub fn add_symlink(&self, path: &str, target: &str) -> u64 {
+        self.add(path, Kind::Symlink(target.as_bytes().to_vec()), 0o120777)
+    }
+
+    /// Replace the contents of the existing file at `path` and bump its
+    /// qid.version, as a write by another client would.
+    pub fn rewrite_file(&self, path: &str, data: &[u8]) {
+        let mut state = self.state();
+        let id = lookup(&state, path).unwrap();
+        let node = state.nodes.get_mut(&id).unwrap();
+        node.kind = Kind::File(data.to_vec());
+        node.version += 1;
+        node.data_version += 1;
+    }
+
+    /// Contents of the file at `path`, if there is one.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        let state = self.state();
+        let id = lookup(&state, path)?;
+        match &state.nodes[&id].kind {
+            Kind::File(data) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    fn add(&self, path: &str, kind: Kind, mode: u32) -> u64 {
+        let mut state = self.state();
+        let mut dir = ROOT;
+        let mut parts = path.split('/').filter(|part| !part.is_empty()).peekable();
+        while let Some(part) = parts.next() {
+            let existing = match &state.nodes[&dir].kind {
+                Kind::Dir(children) => children.get(part.as_bytes()).copied(),
+                _ => panic!("{path}: {part} is not a directory"),
+            };
+            let last = parts.peek().is_none();
+            dir = match existing {
+                Some(id) if !last => id,
+                _ => {
+                    let id = state.next;
+                    state.next += 1;
+                    let node = if last {
+                        Node { kind: kind.clone(), mode, version: 0, data_version: 0 }
+                    } else {
+                        Node { kind: Kind::Dir(BTreeMap::new()), mode: 0o40755, version: 0, data_version: 0 }
+                    };
+                    state.nodes.insert(id, node);
+                    if let Kind::Dir(children) = &mut state.nodes.get_mut(&dir).unwrap().kind {
+                        children.insert(part.as_bytes().to_vec(), id);
+                    }
+                    id
+                }
+            };
+        }
+        dir
+    }
+}
+
+/// A session attached to `server` over 9P2000.L.
+pub fn session(server: &Mem) -> Session {
+    let mut session = Session::new(Box::new(server.clone()), String::from("test"));
+    session.negotiate().unwrap();
+    session
+}
+
+fn lookup(state: &State, path: &str) -> Option<u64> {
+    let mut id = ROOT;
+    for part in path.split('/').filter(|part| !part.is_empty()) {
+        id = match &state.nodes[&id].kind {
+            Kind::Dir(children) => *children.get(part.as_bytes())?,
+            _ => return None,
+        };
+    }
+    Some(id)
+}
+
+pub fn qid(state: &State, id: u64) -> Qid {
+    let node = &state.nodes[&id];
+    let type_ = match node.kind {
+        Kind::Dir(_) => 0x80,
+        Kind::Symlink(_) => 0x02,
+        Kind::File(_) => 0,
+    };
+    Qid { type_, version: node.version, path: id }
+}
+
+fn fid(state: &State, fid: u32) -> Result<u64, u32> {
+    state.fids.get(&fid).copied().ok_or(EBADF)
+}
+
+/// Readdir cookie of the entry at `index`; not monotonic, like ext4 hashes.
+fn cookie(index: usize) -> u64 {
+    (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 1
+}
+
+fn insert_child(state: &mut State, dir: u64, name: &[u8], kind: Kind, mode: u32) -> Result<u64, u32> {
+    match &state.nodes[&dir].kind {
+        Kind::Dir(children) if children.contains_key(name) => return Err(EEXIST),
+        Kind::Dir(_) => {}
+        _ => return Err(ENOTDIR),
+    }
+    let id = state.next;
+    state.next += 1;
+    state.nodes.insert(id, Node { kind, mode, version: 0, data_version: 0 });
+    if let Kind::Dir(children) = &mut state.nodes.get_mut(&dir).unwrap().kind {
+        children.insert(name.to_vec(), id);
+    }
+    Ok(id)
+}
+
+fn unlink(state: &mut State, id: u64) {
+    for node in state.nodes.values_mut() {
+        if let Kind::Dir(children) = &mut node.kind {
+            children.retain(|_, child| *child != id);
+        }
+    }
+}
+
+fn handle<'a>(state: &mut State, request: Tmessage, scratch: &'a mut Vec<u8>) -> Result<Rmessage<'a>, u32> {
+    state.log.push(request.msg_type());
+    Ok(match request {
+        Tmessage::Version { msize, .. } => Rmessage::Version { msize, version: "9P2000.L" },
+        Tmessage::Attach { fid, .. } => {
+            state.fids.insert(fid, ROOT);
+            Rmessage::Attach { qid: qid(state, ROOT) }
+        }
+        Tmessage::Walk { fid: from, newfid, wnames } => {
+            let mut cur = fid(state, from)?;
+            let mut wqids = WalkList::new();
+            for (index, name) in wnames.iter().enumerate() {
+                let next = match &state.nodes[&cur].kind {
+                    Kind::Dir(_) if *name == b".." => Some(ROOT),
+                    Kind::Dir(children) => children.get(*name).copied(),
+                    _ => None,
+                };
+                match next {
+                    Some(id) => {
+                        cur = id;
+                        wqids.push(qid(state, id)).unwrap();
+                    }
+                    None if index == 0 => return Err(ENOENT),
+                    None => break,
+                }
+            }
+            if wqids.len() == wnames.len() {
+                if newfid != from && state.fids.contains_key(&newfid) {
+                    return Err(EBADF);
+                }
+                state.fids.insert(newfid, cur);
+            }
+            Rmessage::Walk { wqids }
+        }
+        Tmessage::Lopen { fid: fid_, flags } => {
+            let id = fid(state, fid_)?;
+            if flags & LOPEN_TRUNC != 0
+                && let Kind::File(data) = &mut state.nodes.get_mut(&id).unwrap().kind
+            {
+                data.clear();
+            }
+            Rmessage::Lopen { qid: qid(state, id), iounit: state.iounit }
+        }
+        Tmessage::Lcreate { fid: fid_, name, mode, .. } => {
+            let dir = fid(state, fid_)?;
+            let id = insert_child(state, dir, name, Kind::File(Vec::new()), 0o100000 | mode)?;
+            state.fids.insert(fid_, id);
+            Rmessage::Lcreate { qid: qid(state, id), iounit: state.iounit }
+        }
+        Tmessage::Mkdir { dfid, name, mode, .. } => {
+            let dir = fid(state, dfid)?;
+            let id = insert_child(state, dir, name, Kind::Dir(BTreeMap::new()), 0o40000 | mode)?;
+            Rmessage::Mkdir { qid: qid(state, id) }
+        }
+        Tmessage::Symlink { fid: fid_, name, symtgt, .. } => {
+            let dir = fid(state, fid_)?;
+            let id = insert_child(state, dir, name, Kind::Symlink(symtgt.to_vec()), 0o120777)?;
+            Rmessage::Symlink { qid: qid(state, id) }
+        }
+        Tmessage::Readlink { fid: fid_ } => {
+            let id = fid(state, fid_)?;
+            let Kind::Symlink(target) = &state.nodes[&id].kind else { return Err(EINVAL) };
+            scratch.clone_from(target);
+            Rmessage::Readlink { target: scratch }
+        }
+        Tmessage::Read { fid: fid_, offset, count } => {
+            state.reads.push((offset, count));
+            let id = fid(state, fid_)?;
+            let Kind::File(data) = &state.nodes[&id].kind else { return Err(EISDIR) };
+            let start = (offset as usize).min(data.len());
+            let end = (start + count as usize).min(data.len());
+            scratch.clear();
+            scratch.extend_from_slice(&data[start..end]);
+            Rmessage::Read { data: scratch }
+        }
+        Tmessage::Write { fid: fid_, offset, data } => {
+            state.writes.push((offset, data.len() as u32));
+            let id = fid(state, fid_)?;
+            let node = state.nodes.get_mut(&id).unwrap();
+            let Kind::File(contents) = &mut node.kind else { return Err(EISDIR) };
+            let end = offset as usize + data.len();
+            if contents.len() < end {
+                contents.resize(end, 0);
+            }
+            contents[offset as usize..end].copy_from_slice(data);
+            node.version += 1;
+            node.data_version += 1;
+            Rmessage::Write { count: data.len() as u32 }
+        }
+        Tmessage::Readdir { fid: fid_, offset, count } => {
+            let id = fid(state, fid_)?;
+            let Kind::Dir(children) = &state.nodes[&id].kind else { return Err(ENOTDIR) };
+            let mut entries = vec![(b".".to_vec(), id), (b"..".to_vec(), ROOT)];
+            entries.extend(children.iter().map(|(name, child)| (name.clone(), *child)));
+            let start = match offset {
+                0 => 0,
+                _ => (0..entries.len())
+                    .find(|index| cookie(*index) == offset)
+                    .map_or(entries.len(), |index| index + 1),
+            };
+            scratch.clear();
+            for (index, (name, child)) in entries.iter().enumerate().skip(start) {
+                let child_qid = qid(state, *child);
+                if scratch.len() + 24 + name.len() > count as usize {
+                    break;
+                }
+                scratch.push(child_qid.type_);
+                scratch.extend_from_slice(&child_qid.version.to_le_bytes());
+                scratch.extend_from_slice(&child_qid.path.to_le_bytes());
+                scratch.extend_from_slice(&cookie(index).to_le_bytes());
+                scratch.push(match child_qid.type_ {
+                    0x80 => 4,
+                    0x02 => 10,
+                    _ => 8,
+                });
+                scratch.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                scratch.extend_from_slice(name);
+            }
+            Rmessage::Readdir { data: scratch }
+        }
+        Tmessage::Getattr { fid: fid_, .. } => {
+            let id = fid(state, fid_)?;
+            let node = &state.nodes[&id];
+            let size = match &node.kind {
+                Kind::File(data) => data.len() as u64,
+                Kind::Symlink(target) => target.len() as u64,
+                Kind::Dir(_) => 0,
+            };
+            let links = state
+                .nodes
+                .values()
+                .map(|node| match &node.kind {
+                    Kind::Dir(children) => children.values().filter(|child| **child == id).count() as u64,
+                    _ => 0,
+                })
+                .sum::<u64>();
+            Rmessage::Getattr(Attr {
+                valid: 0x27ff,
+                qid: qid(state, id),
+                mode: node.mode,
+                size,
+                nlink: links.max(1),
+                data_version: node.data_version,
+                ..Attr::default()
+            })
+        }
+        Tmessage::Setattr { fid: fid_, attr } => {
+            let id = fid(state, fid_)?;
+            let node = state.nodes.get_mut(&id).unwrap();
+            if attr.valid & 0x1 != 0 {
+                node.mode = (node.mode & !0o7777) | (attr.mode & 0o7777);
+            }
+            if attr.valid & 0x8 != 0
+                && let Kind::File(data) = &mut node.kind
+            {
+                data.resize(attr.size as usize, 0);
+            }
+            node.data_version += 1;
+            Rmessage::Setattr
+        }
+        Tmessage::Rename { fid: fid_, dfid, name } => {
+            let id = fid(state, fid_)?;
+            let dir = fid(state, dfid)?;
+            unlink(state, id);
+            match &mut state.nodes.get_mut(&dir).unwrap().kind {
+                Kind::Dir(children) => children.insert(name.to_vec(), id),
+                _ => return Err(ENOTDIR),
+            };
+            Rmessage::Rename
+        }
+        Tmessage::Link { dfid, fid: fid_, name } => {
+            let id = fid(state, fid_)?;
+            let dir = fid(state, dfid)?;
+            match &mut state.nodes.get_mut(&dir).unwrap().kind {
+                Kind::Dir(children) => children.insert(name.to_vec(), id),
+                _ => return Err(ENOTDIR),
+            };
+            Rmessage::Link
+        }
+        Tmessage::Remove { fid: fid_ } => {
+            let id = state.fids.remove(&fid_).ok_or(EBADF)?;
+            unlink(state, id);
+            Rmessage::Remove
+        }
+        Tmessage::Fsync { fid: fid_, .. } => {
+            fid(state, fid_)?;
+            Rmessage::Fsync
+        }
+        Tmessage::Clunk { fid: fid_ } => {
+            state.fids.remove(&fid_).ok_or(EBADF)?;
+            Rmessage::Clunk
+        }
+        _ => return Err(EOPNOTSUPP),
+    })
+}
+
+impl Transport for Mem {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let (tag, request) = Tmessage::decode(req, Dialect::P2000L)?;
+        let mut state = self.state();
+        let mut scratch = Vec::new();
+        let mut out = Vec::new();
+        match handle(&mut state, request, &mut scratch) {
+            Ok(reply) => reply.encode(tag, Dialect::P2000L, &mut out)?,
+            Err(ecode) => Rmessage::Lerror { ecode }.encode(tag, Dialect::P2000L, &mut out)?,
+        }
+        let resp = resp.get_mut(..out.len()).ok_or("transport reply exceeds buffer")?;
+        resp.copy_from_slice(&out);
+        Ok(out.len())
+    }
+}
+
+/// Transport that answers version and attach for `dialect` and every other
+/// request with `handler`.
+pub struct Script<F> {
+    pub dialect: Dialect,
+    pub handler: F,
+}
+
+impl<F> Transport for Script<F>
+where
+    F: Fn(Tmessage<'_>) -> Rmessage<'static> + Send + Sync,
+{
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let (tag, request) = Tmessage::decode(req, self.dialect)?;
+        let reply = match request {
+            Tmessage::Version { msize, .. } => Rmessage::Version {
+                msize,
+                version: match self.dialect {
+                    Dialect::P2000 => "9P2000",
+                    Dialect::P2000U => "9P2000.u",
+                    Dialect::P2000L => "9P2000.L",
+                },
+            },
+            Tmessage::Attach { .. } => Rmessage::Attach { qid: Qid { type_: 0x80, version: 0, path: ROOT } },
+            request => (self.handler)(request),
+        };
+        let mut out = Vec::new();
+        reply.encode(tag, self.dialect, &mut out)?;
+        let resp = resp.get_mut(..out.len()).ok_or("transport reply exceeds buffer")?;
+        resp.copy_from_slice(&out);
+        Ok(out.len())
+    }
+}
+
+/// A session attached through a [`Script`].
+pub fn scripted<F>(dialect: Dialect, handler: F) -> Session
+where
+    F: Fn(Tmessage<'_>) -> Rmessage<'static> + Send + Sync + 'static,
+{
+    let mut session = Session::new(Box::new(Script { dialect, handler }), String::from("test"));
+    session.negotiate().unwrap();
+    session
+}
+
+/// Byte stream that answers each frame written to it through `server`.
+pub struct Loopback<T> {
+    pub server: T,
+    written: Vec<u8>,
+    replies: VecDeque<u8>,
+}
+
+impl<T> Loopback<T> {
+    pub fn new(server: T) -> Self {
+        Self { server, written: Vec::new(), replies: VecDeque::new() }
+    }
+}
+
+impl<T: Transport> ByteStream for Loopback<T> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), String> {
+        self.written.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        if self.replies.len() < buf.len() {
+            return Err(String::from("transport closed: unexpected eof"));
+        }
+        let len = buf.len();
+        for (byte, reply) in buf.iter_mut().zip(self.replies.drain(..len)) {
+            *byte = reply;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        while self.written.len() >= 4 {
+            let size = u32::from_le_bytes(self.written[..4].try_into().unwrap()) as usize;
+            if self.written.len() < size {
+                break;
+            }
+            let frame: Vec<u8> = self.written.drain(..size).collect();
+            let mut resp = vec![0u8; 1 << 20];
+            let len = self.server.request(&frame, &mut resp)?;
+            self.replies.extend(&resp[..len]);
+        }
+        Ok(())
+    }
+}
+
+/// Byte sink whose contents stay readable after a wrapper takes ownership of it.
+#[derive(Clone, Default)]
+pub struct Sink(pub Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl ByteStream for Sink {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), String> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn read_exact(&mut self, _buf: &mut [u8]) -> Result<(), String> {
+        Err(String::from("sink is write-only"))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Lets a test keep a handle on a wrapper the session owns.
+pub struct Shared<T>(pub Arc<T>);
+
+impl<T: Transport> Transport for Shared<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.0.request(req, resp)
+    }
+
+    fn is_vectored(&self) -> bool {
+        self.0.is_vectored()
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        self.0.request_vectored(req_head, req_data, resp_head, resp_data)
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.0.request_batch(reqs, resps)
+    }
+}
+
+/// Answer 9P frames read from `stream` until it closes or fails.
+pub fn serve(server: &Mem, stream: &mut (impl std::io::Read + std::io::Write)) {
+    let mut resp = vec![0u8; 1 << 20];
+    loop {
+        let mut size = [0u8; 4];
+        if stream.read_exact(&mut size).is_err() {
+            return;
+        }
+        let mut req = size.to_vec();
+        req.resize(u32::from_le_bytes(size) as usize, 0);
+        if stream.read_exact(&mut req[4..]).is_err() {
+            return;
+        }
+        let Ok(len) = server.request(&req, &mut resp) else { return };
+        if stream.write_all(&resp[..len]).and_then(|()| stream.flush()).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Walk fid cache eviction, negative entries and invalidation against the in-memory server.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{Mem, ENOENT};
+use fs9p::{DentryCacheConfig, Session, VirtualClock};
+
+const TWALK: u8 = 110;
+
+fn cached(server: &Mem, capacity: usize) -> (Session, VirtualClock) {
+    let clock = VirtualClock::new();
+    let mut session = common::session(server);
+    session.set_clock(Box::new(clock.clone()));
+    session.enable_dentry_cache(DentryCacheConfig { capacity, negative_ttl: Some(Duration::from_secs(1)) });
+    (session, clock)
+}
+
+/// Walks sent while reading `path`.
+fn walks_to_read(session: &mut Session, server: &Mem, path: &str) -> usize {
+    let before = server.count(TWALK);
+    session.read_file(path).unwrap();
+    server.count(TWALK) - before
+}
+
+#[test]
+fn least_recently_used_directories_are_evicted_and_clunked() {
+    let server = Mem::new();
+    for dir in ["a", "b", "c"] {
+        server.add_file(&format!("/{dir}/f"), dir.as_bytes());
+    }
+    let (mut session, _clock) = cached(&server, 2);
+    for dir in ["a", "b", "c"] {
+        assert_eq!(walks_to_read(&mut session, &server, &format!("/{dir}/f")), 2);
+    }
+    assert_eq!(server.live_fids(), 3, "root plus two cached directories");
+
+    // /a was evicted; /b and /c still cost a single walk each.
+    assert_eq!(walks_to_read(&mut session, &server, "/b/f"), 1);
+    assert_eq!(walks_to_read(&mut session, &server, "/a/f"), 2);
+    assert_eq!(walks_to_read(&mut session, &server, "/b/f"), 1);
+    assert_eq!(walks_to_read(&mut session, &server, "/c/f"), 2, "/c was least recently used");
+    assert_eq!(server.live_fids(), 3);
+
+    session.disable_dentry_cache();
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn negative_entries_expire_after_their_ttl() {
+    let server = Mem::new();
+    server.add_dir("/d");
+    let (mut session, clock) = cached(&server, 4);
+    let missing = format!("late: rlerror errno={ENOENT}");
+    assert_eq!(session.read_file("/d/late").unwrap_err(), missing);
+
+    server.add_file("/d/late", b"here");
+    let walks = server.count(TWALK);
+    assert_eq!(session.read_file("/d/late").unwrap_err(), missing);
+    assert_eq!(server.count(TWALK), walks, "negative entry answered locally");
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(session.read_file("/d/late").unwrap(), b"here");
+}
+
+#[test]
+fn creating_a_file_forgets_its_negative_entry() {
+    let server = Mem::new();
+    server.add_dir("/d");
+    let (mut session, _clock) = cached(&server, 4);
+    assert!(session.read_file("/d/new").is_err());
+    session.write_file("/d/new", b"made").unwrap();
+    assert_eq!(session.read_file("/d/new").unwrap(), b"made");
+}
+
+#[test]
+fn renames_and_removes_drop_cached_directories() {
+    let server = Mem::new();
+    server.add_file("/d/f", b"old");
+    let (mut session, _clock) = cached(&server, 4);
+    assert_eq!(session.read_file("/d/f").unwrap(), b"old");
+
+    session.rename_path("/d", "/e").unwrap();
+    assert_eq!(server.live_fids(), 1, "the fid cached for /d was clunked");
+    assert!(session.read_file("/d/f").is_err());
+    assert_eq!(session.read_file("/e/f").unwrap(), b"old");
+
+    session.remove_path("/e/f").unwrap();
+    session.remove_path("/e").unwrap();
+    server.add_file("/e/f", b"new");
+    assert_eq!(session.read_file("/e/f").unwrap(), b"new");
+    assert_eq!(server.live_fids(), 2);
+}
@@ -0,0 +1,168 @@
+//! Dial strings, timeouts and connection loss over local sockets.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::Mem;
+use fs9p::{connect, connect_fd, connect_with, DialOptions, Dialect, Session, Tmessage, Transport};
+
+fn tree() -> Mem {
+    let server = Mem::new();
+    server.add_file("/hello", b"world");
+    server
+}
+
+/// Answer every connection to `listener` from `server`.
+fn serve_tcp(listener: TcpListener, server: Mem) {
+    thread::spawn(move || {
+        for mut socket in listener.incoming().map_while(Result::ok) {
+            common::serve(&server, &mut socket);
+        }
+    });
+}
+
+fn read_hello(transport: Box<dyn Transport>) -> Vec<u8> {
+    let mut session = Session::new(transport, String::from("test"));
+    session.negotiate().unwrap();
+    session.read_file("/hello").unwrap()
+}
+
+fn version_frame() -> Vec<u8> {
+    let mut req = Vec::new();
+    Tmessage::Version { msize: 8192, version: "9P2000.L" }.encode(0xffff, Dialect::P2000L, &mut req).unwrap();
+    req
+}
+
+/// Send a Tversion to a peer that handles the connection with `peer`.
+fn request_against(peer: impl FnOnce(TcpStream) + Send + 'static, options: DialOptions) -> Result<usize, String> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("tcp!127.0.0.1!{}", listener.local_addr().unwrap().port());
+    let handle = thread::spawn(move || peer(listener.accept().unwrap().0));
+    let transport = connect_with(&addr, options)?;
+    let result = transport.request(&version_frame(), &mut [0; 64]);
+    drop(transport);
+    handle.join().unwrap();
+    result
+}
+
+#[test]
+fn malformed_dial_strings_are_rejected() {
+    for (addr, expected) in [
+        ("localhost", "invalid dial string"),
+        ("quic!localhost!564", "unsupported network"),
+        ("fd!3!4", "unsupported network"),
+        ("tcp!localhost!ninep", "invalid port: ninep"),
+        ("tcp!localhost!65536", "invalid port: 65536"),
+    ] {
+        let err = connect(addr).err().unwrap();
+        assert!(err.contains(expected), "{addr}: {err}");
+    }
+}
+
+#[test]
+fn tcp_ports_default_to_564() {
+    for addr in ["tcp!127.0.0.1", "tcp!127.0.0.1!9fs", "net!127.0.0.1!9pfs"] {
+        let err = connect(addr).err().unwrap();
+        assert!(err.starts_with("connect 127.0.0.1:564:"), "{addr}: {err}");
+    }
+}
+
+#[test]
+fn tcp_and_unix_addresses_reach_the_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    serve_tcp(listener, tree());
+    assert_eq!(read_hello(connect(&format!("tcp!127.0.0.1!{port}")).unwrap()), b"world");
+    assert_eq!(read_hello(connect(&format!("net!localhost!{port}")).unwrap()), b"world");
+
+    let path = std::env::temp_dir().join(format!("fs9p-dial-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || {
+        let server = tree();
+        for mut socket in listener.incoming().map_while(Result::ok) {
+            common::serve(&server, &mut socket);
+        }
+    });
+    let transport = connect(&format!("unix!{}", path.display())).unwrap();
+    assert_eq!(read_hello(transport), b"world");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn owned_descriptors_carry_a_session() {
+    let (client, mut peer) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || common::serve(&tree(), &mut peer));
+    let read = OwnedFd::from(client.try_clone().unwrap());
+    let transport = connect_fd(read, OwnedFd::from(client), DialOptions::default()).unwrap();
+    assert_eq!(read_hello(transport), b"world");
+    server.join().unwrap();
+
+    let (client, _peer) = UnixStream::pair().unwrap();
+    let read = OwnedFd::from(client.try_clone().unwrap());
+    let options = DialOptions { read_timeout: Some(Duration::from_secs(1)), ..DialOptions::default() };
+    let err = connect_fd(read, OwnedFd::from(client), options).err().unwrap();
+    assert!(err.contains("read timeout not supported"), "{err}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn connect_times_out_when_the_server_does_not_accept() {
+    // Fill the accept queue of a listener that never accepts, so further
+    // handshakes go unanswered.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let target = listener.local_addr().unwrap();
+    let mut queued = Vec::new();
+    while let Ok(stream) = TcpStream::connect_timeout(&target, Duration::from_millis(100)) {
+        queued.push(stream);
+        assert!(queued.len() < 4096, "accept queue never filled");
+    }
+
+    let options = DialOptions { connect_timeout: Some(Duration::from_millis(200)), ..DialOptions::default() };
+    let start = Instant::now();
+    let err = connect_with(&format!("tcp!127.0.0.1!{}", target.port()), options).err().unwrap();
+    assert!(err.contains("timed out"), "{err}");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn reads_time_out_when_the_server_is_silent() {
+    let options = DialOptions { read_timeout: Some(Duration::from_millis(100)), ..DialOptions::default() };
+    let start = Instant::now();
+    let peer = |mut socket: TcpStream| {
+        let _ = socket.read_to_end(&mut Vec::new());
+    };
+    let err = request_against(peer, options).unwrap_err();
+    assert!(err.starts_with("transport timed out"), "{err}");
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn eof_is_a_transport_error() {
+    // Read the whole request so the close is an orderly shutdown.
+    let peer = |mut socket: TcpStream| {
+        let mut req = vec![0; version_frame().len()];
+        socket.read_exact(&mut req).unwrap();
+    };
+    let err = request_against(peer, DialOptions::default()).unwrap_err();
+    assert_eq!(err, "transport closed: unexpected eof");
+}
+
+#[test]
+fn resets_are_transport_errors() {
+    // Closing a socket with unread data makes the kernel send a reset.
+    let peer = |socket: TcpStream| {
+        while socket.peek(&mut [0; 1]).unwrap() == 0 {}
+    };
+    let err = request_against(peer, DialOptions::default()).unwrap_err();
+    assert!(err.starts_with("transport closed:"), "{err}");
+    assert!(!err.contains("eof"), "{err}");
+}
@@ -0,0 +1,170 @@
+//! Session error paths under seeded fault injection.
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{Mem, Shared, EIO};
+use fs9p::msg_type::{TCLUNK, TGETATTR, TLOPEN, TMKDIR, TREAD, TREADDIR, TWALK, TWRITE};
+use fs9p::{Clock, Fault, FaultRule, FaultTransport, Session, Transport};
+
+const FAULTS: [Fault; 7] = [
+    Fault::Drop,
+    Fault::Truncate,
+    Fault::Corrupt,
+    Fault::Delay(Duration::from_millis(5)),
+    Fault::SwapTag,
+    Fault::WrongType,
+    Fault::Errno(EIO),
+];
+
+fn tree() -> Mem {
+    let server = Mem::new();
+    server.add_file("/dir/a", b"alpha");
+    server.add_file("/dir/b", &[7; 20_000]);
+    server.add_dir("/dir/sub");
+    server.add_symlink("/dir/link", "a");
+    server
+}
+
+/// Every session call that opens, walks or clunks fids, ignoring results.
+fn exercise(session: &mut Session) {
+    let _ = session.ensure_dir("/");
+    let _ = session.create_dir("/dir/made");
+    let _ = session.ensure_dir("/dir/made");
+    let _ = session.create_dir("/dir/made/inner");
+    let _ = session.getattr("/dir/a");
+    let _ = session.read_file("/dir/b");
+    let _ = session.read_link("/dir/link");
+    let _ = session.list_dir("/dir");
+    let _ = session.read_dir_plus("/dir");
+    let _ = session.write_file("/dir/new", b"data");
+    let _ = session.rename_path("/dir/new", "/dir/moved");
+    let _ = session.remove_path("/dir/moved");
+    if let Ok(fid) = session.open_path_with_flags("/dir/a", 2, 0) {
+        let _ = session.write_all_at(fid, 0, b"ALPHA");
+        let _ = session.fsync_fid(fid);
+        let _ = session.close_fid(fid);
+    }
+    let _ = session.walk_tree("/").take(1000).count();
+}
+
+/// Scatter/gather server counting the requests that arrive vectored.
+#[derive(Clone)]
+struct Vectored {
+    server: Mem,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Transport for Vectored {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.server.request(req, resp)
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.server.request_vectored(req_head, req_data, resp_head, resp_data)
+    }
+}
+
+/// Run the workload over `inner` through `rules`, returning the wrapper.
+fn run_on<T: Transport + 'static>(inner: T, seed: u64, rules: &[FaultRule]) -> Arc<FaultTransport<T>> {
+    let mut fault = FaultTransport::new(inner, seed);
+    for rule in rules {
+        fault = fault.with_rule(*rule);
+    }
+    let fault = Arc::new(fault);
+    let mut session = Session::new(Box::new(Shared(fault.clone())), String::from("test"));
+    if session.negotiate().is_ok() {
+        exercise(&mut session);
+    }
+    fault
+}
+
+/// Run the workload through `rules`, returning the server and the wrapper.
+fn run(seed: u64, rules: &[FaultRule]) -> (Mem, Arc<FaultTransport<Mem>>) {
+    let server = tree();
+    let fault = run_on(server.clone(), seed, rules);
+    (server, fault)
+}
+
+#[test]
+fn no_session_call_panics_or_leaks_under_any_fault() {
+    for fault in FAULTS {
+        for seed in 0..40 {
+            let (_, transport) = run(seed, &[FaultRule::new(fault).one_in(4)]);
+            assert!(transport.injected() > 0, "{fault:?} seed {seed} never fired");
+            // At most the attach fid outlives the workload.
+            let live = transport.live_fids();
+            assert!(live.iter().all(|fid| *fid == 1), "{fault:?} seed {seed}: {live:?}");
+        }
+    }
+}
+
+#[test]
+fn errors_mid_sequence_do_not_leak_fids() {
+    for msg_type in [TWALK, TLOPEN, TMKDIR, TREAD, TWRITE, TREADDIR, TGETATTR] {
+        for seed in 0..40 {
+            let rule = FaultRule::new(Fault::Errno(EIO)).on(msg_type).one_in(3);
+            let (server, transport) = run(seed, &[rule]);
+            assert_eq!(transport.live_fids(), [1], "type {msg_type} seed {seed}");
+            assert_eq!(server.live_fids(), 1, "type {msg_type} seed {seed}");
+        }
+    }
+}
+
+#[test]
+fn vectored_requests_take_faults_too() {
+    for fault in FAULTS {
+        let calls = Arc::new(AtomicUsize::new(0));
+        for seed in 0..20 {
+            let inner = Vectored { server: tree(), calls: calls.clone() };
+            let rules = [FaultRule::new(fault).on(TREAD).one_in(2), FaultRule::new(fault).on(TWRITE).one_in(2)];
+            let transport = run_on(inner.clone(), seed, &rules);
+            assert!(transport.injected() > 0, "{fault:?} seed {seed} never fired");
+            assert_eq!(transport.live_fids(), [1], "{fault:?} seed {seed}");
+            assert_eq!(inner.server.live_fids(), 1, "{fault:?} seed {seed}");
+        }
+        assert!(calls.load(Ordering::Relaxed) > 0, "{fault:?}: session never sent vectored");
+    }
+}
+
+#[test]
+fn failed_clunks_still_release_fids() {
+    for seed in 0..40 {
+        let rules = [
+            FaultRule::new(Fault::Errno(EIO)).on(TCLUNK).one_in(2),
+            FaultRule::new(Fault::Drop).on(TCLUNK).one_in(3),
+        ];
+        let (_, transport) = run(seed, &rules);
+        assert_eq!(transport.live_fids(), [1], "seed {seed}");
+    }
+}
+
+#[test]
+fn delays_advance_the_virtual_clock() {
+    let (_, transport) = run(1, &[FaultRule::new(Fault::Delay(Duration::from_millis(5))).on(TREAD)]);
+    assert!(transport.injected() > 0);
+    assert_eq!(transport.clock().now(), Duration::from_millis(5) * transport.injected());
+}
+
+#[test]
+fn same_seed_injects_same_faults() {
+    let rules = [FaultRule::new(Fault::Corrupt).one_in(5), FaultRule::new(Fault::Truncate).one_in(7)];
+    let (first_server, first) = run(99, &rules);
+    let (second_server, second) = run(99, &rules);
+    assert_eq!(first.injected(), second.injected());
+    assert_eq!(first_server.state().log, second_server.state().log);
+}
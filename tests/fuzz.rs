@@ -0,0 +1,296 @@
+//! Seeded fuzzing of every decode path: mangled frames must be rejected
+//! without panicking, allocating beyond the frame, or looping forever.
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::{Mem, Script};
+use fs9p::{Dialect, Qid, Rmessage, Session, Tmessage, Transport, WalkList};
+
+const DIALECTS: [Dialect; 3] = [Dialect::P2000, Dialect::P2000U, Dialect::P2000L];
+
+/// No single allocation may approach what a trusted 32-bit count could request.
+const ALLOCATION_LIMIT: usize = 16 << 20;
+
+/// Remembers the largest allocation made by the test binary.
+struct Largest;
+
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Largest {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Largest = Largest;
+
+fn assert_bounded_allocations() {
+    let largest = LARGEST.load(Ordering::Relaxed);
+    assert!(largest < ALLOCATION_LIMIT, "allocated {largest} bytes at once");
+}
+
+/// xorshift64, so every failure reproduces from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+/// Apply one to three random edits, favouring the length and count fields
+/// a hostile server would lie about.
+fn mutate(rng: &mut Rng, frame: &mut Vec<u8>, max_len: usize) {
+    for _ in 0..1 + rng.below(3) {
+        let at = rng.below(frame.len());
+        match rng.below(7) {
+            0 if !frame.is_empty() => frame[at] ^= 1 << rng.below(8),
+            1 if !frame.is_empty() => frame[at] = rng.next() as u8,
+            2 => frame.truncate(at),
+            3 => {
+                let extra = rng.below(16);
+                frame.extend((0..extra).map(|_| rng.next() as u8));
+            }
+            4 if frame.len() >= 2 => {
+                let at = rng.below(frame.len() - 1);
+                let value = [0, 1, u16::MAX, rng.next() as u16][rng.below(4)];
+                frame[at..at + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            5 if frame.len() >= 4 => {
+                let at = rng.below(frame.len() - 3);
+                let value = [0, u32::MAX, i32::MAX as u32, frame.len() as u32 + 1][rng.below(4)];
+                frame[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            _ if frame.len() >= 4 => {
+                let size = [frame.len() as u32 - 1, frame.len() as u32 + 1, rng.next() as u32][rng.below(3)];
+                frame[..4].copy_from_slice(&size.to_le_bytes());
+            }
+            _ => {}
+        }
+    }
+    frame.truncate(max_len);
+}
+
+/// Records every frame that passes through, to seed the mutations.
+struct Tap<T> {
+    inner: T,
+    frames: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl<T: Transport> Transport for Tap<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let size = self.inner.request(req, resp)?;
+        let mut frames = self.frames.lock().unwrap();
+        frames.push(req.to_vec());
+        frames.push(resp[..size].to_vec());
+        Ok(size)
+    }
+}
+
+/// Mangles roughly one reply in four on its way back to the client.
+struct Mangling<T> {
+    inner: T,
+    rng: Mutex<Rng>,
+}
+
+impl<T: Transport> Transport for Mangling<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let size = self.inner.request(req, resp)?;
+        let mut rng = self.rng.lock().unwrap();
+        if rng.below(4) != 0 {
+            return Ok(size);
+        }
+        let mut frame = resp[..size].to_vec();
+        mutate(&mut rng, &mut frame, resp.len());
+        resp[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+}
+
+fn tree() -> Mem {
+    let server = Mem::new();
+    server.add_file("/dir/a", b"alpha");
+    server.add_file("/dir/b", &[7; 3000]);
+    server.add_dir("/dir/sub");
+    server.add_symlink("/dir/link", "a");
+    server
+}
+
+/// Drive every session operation that decodes server data, ignoring results.
+fn exercise(session: &mut Session) {
+    let _ = session.getattr("/dir/a");
+    let _ = session.read_file("/dir/b");
+    let _ = session.read_link("/dir/link");
+    let _ = session.list_dir_entries("/dir");
+    let _ = session.read_dir_plus("/dir");
+    let _ = session.write_file("/dir/new", b"data");
+    let _ = session.rename_path("/dir/new", "/dir/moved");
+    let _ = session.remove_path("/dir/moved");
+    if let Ok(entries) = session.read_dir("/dir") {
+        // Any cookie sequence must end well before this.
+        assert!(entries.take(10_000).count() < 10_000, "readdir did not terminate");
+    }
+}
+
+/// Frames from a clean run of [`exercise`], requests and replies interleaved.
+fn seed_frames() -> Vec<Vec<u8>> {
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let tap = Tap { inner: tree(), frames: frames.clone() };
+    let mut session = Session::new(Box::new(tap), String::from("test"));
+    session.negotiate().unwrap();
+    exercise(&mut session);
+    drop(session);
+    let mut frames = Arc::try_unwrap(frames).unwrap().into_inner().unwrap();
+
+    // Messages the 9P2000.L run never produces.
+    let qid = Qid { type_: 0, version: 1, path: 2 };
+    for dialect in [Dialect::P2000, Dialect::P2000U] {
+        let errno = (dialect == Dialect::P2000U).then_some(2);
+        let extension = (dialect == Dialect::P2000U).then_some(b"".as_slice());
+        let n_uname = (dialect == Dialect::P2000U).then_some(0);
+        let mut push = |encode: &dyn Fn(&mut Vec<u8>) -> Result<(), String>| {
+            let mut buf = Vec::new();
+            encode(&mut buf).unwrap();
+            frames.push(buf);
+        };
+        push(&|buf| Rmessage::Error { ename: "file not found", errno }.encode(1, dialect, buf));
+        push(&|buf| Rmessage::Stat { stat: &stat_entry(dialect)[2..] }.encode(1, dialect, buf));
+        push(&|buf| Rmessage::Read { data: &stat_entry(dialect) }.encode(1, dialect, buf));
+        push(&|buf| Rmessage::Open { qid, iounit: 0 }.encode(1, dialect, buf));
+        push(&|buf| Tmessage::Create { fid: 1, name: b"f", perm: 0o644, mode: 1, extension }.encode(1, dialect, buf));
+        push(&|buf| Tmessage::Attach { fid: 0, afid: !0, uname: "u", aname: "", n_uname }.encode(1, dialect, buf));
+        push(&|buf| Tmessage::Wstat { fid: 1, stat: &stat_entry(dialect) }.encode(1, dialect, buf));
+    }
+    frames
+}
+
+/// A directory entry as 9P2000 and 9P2000.u `Rread` and `Rstat` carry it.
+fn stat_entry(dialect: Dialect) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.push(0);
+    body.extend_from_slice(&1u32.to_le_bytes());
+    body.extend_from_slice(&2u64.to_le_bytes());
+    body.extend_from_slice(&0o644u32.to_le_bytes());
+    body.extend_from_slice(&[0; 8]);
+    body.extend_from_slice(&5u64.to_le_bytes());
+    for field in ["a", "glenda", "glenda", "glenda"] {
+        body.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        body.extend_from_slice(field.as_bytes());
+    }
+    if dialect == Dialect::P2000U {
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&[0; 12]);
+    }
+    let mut entry = (body.len() as u16).to_le_bytes().to_vec();
+    entry.extend_from_slice(&body);
+    entry
+}
+
+#[test]
+fn decoders_reject_mangled_frames() {
+    let frames = seed_frames();
+    let mut rng = Rng::new(43);
+    for frame in &frames {
+        for _ in 0..200 {
+            let mut mangled = frame.clone();
+            mutate(&mut rng, &mut mangled, 1 << 16);
+            for dialect in DIALECTS {
+                let _ = Tmessage::decode(&mangled, dialect);
+                let _ = Rmessage::decode(&mangled, dialect);
+                let _ = fs9p::decode_header(&mangled);
+            }
+        }
+    }
+    assert_bounded_allocations();
+}
+
+#[test]
+fn decoders_reject_random_bytes() {
+    let mut rng = Rng::new(1);
+    for _ in 0..20_000 {
+        let len = rng.below(64);
+        let mut frame: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        if len >= 5 {
+            // Mostly valid sizes and message types, so decoding gets past the header.
+            frame[..4].copy_from_slice(&(len as u32).to_le_bytes());
+            frame[4] = 6 + rng.below(122) as u8;
+        }
+        for dialect in DIALECTS {
+            let _ = Tmessage::decode(&frame, dialect);
+            let _ = Rmessage::decode(&frame, dialect);
+        }
+    }
+    assert_bounded_allocations();
+}
+
+#[test]
+fn sessions_survive_mangled_replies() {
+    for seed in 0..300 {
+        let mangling = Mangling { inner: tree(), rng: Mutex::new(Rng::new(seed)) };
+        let mut session = Session::new(Box::new(mangling), String::from("test"));
+        if session.negotiate().is_ok() {
+            exercise(&mut session);
+        }
+    }
+    assert_bounded_allocations();
+}
+
+/// A 9P2000.u server whose files and directories all read as one stat entry.
+fn legacy(request: Tmessage<'_>) -> Rmessage<'static> {
+    static ENTRY: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+    let entry = ENTRY.get_or_init(|| stat_entry(Dialect::P2000U));
+    let qid = Qid { type_: 0x80, version: 0, path: 1 };
+    match request {
+        Tmessage::Walk { wnames, .. } => Rmessage::Walk { wqids: WalkList::try_from(&[qid; 16][..wnames.len()]).unwrap() },
+        Tmessage::Open { .. } | Tmessage::Create { .. } => Rmessage::Open { qid, iounit: 0 },
+        Tmessage::Read { offset: 0, .. } => Rmessage::Read { data: entry },
+        Tmessage::Read { .. } => Rmessage::Read { data: b"" },
+        Tmessage::Write { data, .. } => Rmessage::Write { count: data.len() as u32 },
+        Tmessage::Stat { .. } => Rmessage::Stat { stat: &entry[2..] },
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        Tmessage::Remove { .. } => Rmessage::Remove,
+        Tmessage::Wstat { .. } => Rmessage::Wstat,
+        _ => Rmessage::Error { ename: "not supported", errno: Some(95) },
+    }
+}
+
+#[test]
+fn legacy_sessions_survive_mangled_replies() {
+    for seed in 0..300 {
+        let script = Script { dialect: Dialect::P2000U, handler: legacy };
+        let mangling = Mangling { inner: script, rng: Mutex::new(Rng::new(seed)) };
+        let mut session = Session::new(Box::new(mangling), String::from("test"));
+        if session.negotiate().is_ok() {
+            exercise(&mut session);
+        }
+    }
+    assert_bounded_allocations();
+}
@@ -0,0 +1,93 @@
+//! Requests the encoder cannot represent are rejected before they reach the transport.
+
+mod common;
+
+use common::Mem;
+use fs9p::{Dialect, Session, Tmessage, WalkList};
+
+const TVERSION: u8 = 100;
+const TSYMLINK: u8 = 16;
+
+fn encode(msg: &Tmessage<'_>) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    let result = msg.encode(1, Dialect::P2000L, &mut buf);
+    result.map(|()| buf)
+}
+
+#[test]
+fn the_codec_rejects_long_strings_nul_bytes_and_long_walks() {
+    let long = vec![b'a'; 65536];
+    let wnames = WalkList::try_from(&[long.as_slice()][..]).unwrap();
+    let err = encode(&Tmessage::Walk { fid: 0, newfid: 1, wnames }).unwrap_err();
+    assert_eq!(err, "9p string of 65536 bytes exceeds 65535");
+    let wnames = WalkList::try_from(&[&long[..65535]][..]).unwrap();
+    assert!(encode(&Tmessage::Walk { fid: 0, newfid: 1, wnames }).is_ok());
+
+    let attach = Tmessage::Attach { fid: 0, afid: u32::MAX, uname: "ro\0ot", aname: "", n_uname: Some(0) };
+    assert_eq!(encode(&attach).unwrap_err(), "9p string \"ro\\0ot\" contains a NUL byte");
+    let symlink = Tmessage::Symlink { fid: 0, name: b"l", symtgt: b"a\0b", gid: 0 };
+    assert!(encode(&symlink).unwrap_err().contains("NUL"));
+
+    let names = [&b"a"[..]; 17];
+    assert_eq!(WalkList::try_from(&names[..]).unwrap_err(), "walk has 17 elements, more than 16");
+    // A 17-name Twalk built by hand does not decode either.
+    let wnames = WalkList::try_from(&names[..16]).unwrap();
+    let mut frame = encode(&Tmessage::Walk { fid: 0, newfid: 1, wnames }).unwrap();
+    frame[15..17].copy_from_slice(&17u16.to_le_bytes());
+    frame.extend_from_slice(&[1, 0, b'a']);
+    let size = frame.len() as u32;
+    frame[..4].copy_from_slice(&size.to_le_bytes());
+    assert_eq!(Tmessage::decode(&frame, Dialect::P2000L).unwrap_err(), "Twalk has 17 names, more than 16");
+}
+
+/// Run `op` and return its error along with the requests it sent.
+fn rejected(server: &Mem, session: &mut Session, op: impl FnOnce(&mut Session) -> Result<(), String>) -> (String, Vec<u8>) {
+    let before = server.state().log.len();
+    let err = op(session).unwrap_err();
+    (err, server.state().log[before..].to_vec())
+}
+
+#[test]
+fn sessions_send_nothing_they_cannot_encode() {
+    let server = Mem::new();
+    let mut session = common::session(&server);
+
+    let long = format!("/{}", "n".repeat(70_000));
+    let (err, sent) = rejected(&server, &mut session, |session| session.read_file(&long).map(drop));
+    assert_eq!(err, "9p string of 70000 bytes exceeds 65535");
+    assert!(sent.is_empty());
+
+    let (err, sent) = rejected(&server, &mut session, |session| session.read_file(b"/a\0b").map(drop));
+    assert_eq!(err, "invalid path component \"a\\0b\"");
+    assert!(sent.is_empty());
+
+    let (err, sent) = rejected(&server, &mut session, |session| session.symlink(b"a\0b", "/link"));
+    assert!(err.contains("NUL"), "{err}");
+    assert!(!sent.contains(&TSYMLINK));
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn requests_over_msize_are_not_sent() {
+    let server = Mem::new();
+    let mut session = common::session(&server);
+    let name = format!("/{}", "n".repeat(20_000));
+    let (err, sent) = rejected(&server, &mut session, |session| session.read_file(&name).map(drop));
+    assert_eq!(err, "Twalk of 20019 bytes exceeds msize 16384");
+    assert!(sent.is_empty());
+
+    let target = "t".repeat(20_000);
+    let (err, sent) = rejected(&server, &mut session, |session| session.symlink(&target, "/link"));
+    assert!(err.starts_with("Tsymlink of"), "{err}");
+    assert!(!sent.contains(&TSYMLINK));
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn attach_names_with_nul_bytes_stop_after_the_version() {
+    let server = Mem::new();
+    let mut session = Session::new(Box::new(server.clone()), String::from("ex\0port"));
+    let err = session.negotiate().unwrap_err();
+    assert!(err.contains("contains a NUL byte"), "{err}");
+    assert_eq!(server.state().log, [TVERSION]);
+}
@@ -0,0 +1,59 @@
+//! Names that are not UTF-8, end to end against the in-memory server.
+
+mod common;
+
+use common::{Mem, ENOENT};
+use fs9p::WalkEvent;
+
+/// "café" in Latin-1 and a name with no valid UTF-8 at all.
+const LATIN1: &[u8] = b"caf\xe9";
+const BINARY: &[u8] = b"\xff\xfe\x80";
+
+fn path(parts: &[&[u8]]) -> Vec<u8> {
+    parts.iter().flat_map(|part| [&b"/"[..], part].concat()).collect()
+}
+
+#[test]
+fn byte_names_survive_every_operation() {
+    let server = Mem::new();
+    server.add_dir("/d");
+    let mut session = common::session(&server);
+
+    let latin1 = path(&[b"d", LATIN1]);
+    session.write_file(&latin1, b"bonjour").unwrap();
+    assert_eq!(session.read_file(&latin1).unwrap(), b"bonjour");
+    assert_eq!(session.getattr(&latin1).unwrap().size, 7);
+
+    session.create_dir(path(&[b"d", BINARY])).unwrap();
+    let moved = path(&[b"d", BINARY, LATIN1]);
+    session.rename_path(&latin1, &moved).unwrap();
+    assert_eq!(session.read_file(&moved).unwrap(), b"bonjour");
+
+    let link = path(&[b"d", b"link"]);
+    session.symlink(&moved, &link).unwrap();
+    assert_eq!(session.read_link(&link).unwrap(), moved);
+
+    let mut names: Vec<Vec<u8>> = session.read_dir("/d").unwrap().map(|entry| entry.unwrap().name).collect();
+    names.sort();
+    assert_eq!(names, [b".".to_vec(), b"..".to_vec(), b"link".to_vec(), BINARY.to_vec()]);
+    let entries = session.list_dir_entries(path(&[b"d", BINARY])).unwrap();
+    assert_eq!(entries[0].name, LATIN1);
+    assert_eq!(entries[0].name_lossy(), "caf\u{fffd}");
+
+    let walked: Vec<Vec<u8>> = session
+        .walk_tree("/d")
+        .map(|event| match event.unwrap() {
+            WalkEvent::Pre(entry) | WalkEvent::Post(entry) => entry.path,
+        })
+        .collect();
+    assert_eq!(walked, [b"/d".to_vec(), link, path(&[b"d", BINARY]), moved]);
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn errors_render_byte_names_lossily() {
+    let server = Mem::new();
+    let mut session = common::session(&server);
+    let err = session.read_file(path(&[LATIN1])).unwrap_err();
+    assert_eq!(err, format!("caf\u{fffd}: rlerror errno={ENOENT}"));
+}
@@ -0,0 +1,126 @@
+//! Lexical path resolution: `..` policies, error messages and long walks.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{scripted, Mem, ENOENT, ENOTDIR};
+use fs9p::{Attr, Dialect, DotDotPolicy, Qid, Rmessage, Session, Tmessage, WalkList};
+
+const TWALK: u8 = 110;
+const DIR: Qid = Qid { type_: 0x80, version: 0, path: 2 };
+
+#[test]
+fn dotdot_policies() {
+    let server = Mem::new();
+    server.add_file("/a/b/f", b"data");
+    let mut session = common::session(&server);
+    let cases: [(DotDotPolicy, &str, Result<(), &str>); 8] = [
+        (DotDotPolicy::Clamp, "/a/../a/b/f", Ok(())),
+        (DotDotPolicy::Clamp, "/../../a/b/f", Ok(())),
+        (DotDotPolicy::Clamp, "a/b/../b/./f", Ok(())),
+        (DotDotPolicy::RejectEscape, "/a/../a/b/f", Ok(())),
+        (DotDotPolicy::RejectEscape, "/a/../../a/b/f", Err("path \"/a/../../a/b/f\" escapes the root")),
+        (DotDotPolicy::RejectEscape, "/../a/b/f", Err("path \"/../a/b/f\" escapes the root")),
+        (DotDotPolicy::Reject, "/a/../a/b/f", Err("path \"/a/../a/b/f\" contains \"..\"")),
+        (DotDotPolicy::Reject, "/a/./b/f", Ok(())),
+    ];
+    for (policy, path, expected) in cases {
+        session.set_dotdot_policy(policy);
+        let walks = server.count(TWALK);
+        match expected {
+            Ok(()) => assert_eq!(session.read_file(path).unwrap(), b"data", "{policy:?} {path}"),
+            Err(message) => {
+                assert_eq!(session.read_file(path).unwrap_err(), message, "{policy:?}");
+                assert_eq!(server.count(TWALK), walks, "rejected paths reach the server");
+            }
+        }
+    }
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn walk_errors_name_the_failing_component() {
+    let server = Mem::new();
+    server.add_file("/a/b/f", b"data");
+    let mut session = common::session(&server);
+    for (path, expected) in [
+        ("/missing", format!("missing: rlerror errno={ENOENT}")),
+        ("/a/missing/f", format!("missing: rlerror errno={ENOENT}")),
+        ("/a/b/f/below", format!("f: rlerror errno={ENOTDIR}")),
+    ] {
+        assert_eq!(session.read_file(path).unwrap_err(), expected, "{path}");
+    }
+    assert_eq!(server.live_fids(), 1);
+}
+
+/// Walks seen by a scripted server as (fid, newfid, number of names), plus clunked fids.
+#[derive(Default)]
+struct Walks {
+    walks: Vec<(u32, u32, usize)>,
+    clunks: Vec<u32>,
+}
+
+/// A server where every name is a directory except `fail`, which does not exist.
+fn deep_server(fail: &'static [u8]) -> (Session, Arc<Mutex<Walks>>) {
+    let seen = Arc::new(Mutex::new(Walks::default()));
+    let log = seen.clone();
+    let session = scripted(Dialect::P2000L, move |request| match request {
+        Tmessage::Walk { fid, newfid, wnames } => {
+            log.lock().unwrap().walks.push((fid, newfid, wnames.len()));
+            // Only a missing first name is an error; otherwise the walk stops short.
+            let walked = wnames.iter().position(|name| *name == fail).unwrap_or(wnames.len());
+            match walked {
+                0 => Rmessage::Lerror { ecode: ENOENT },
+                _ => Rmessage::Walk { wqids: WalkList::try_from(&vec![DIR; walked][..]).unwrap() },
+            }
+        }
+        Tmessage::Clunk { fid } => {
+            log.lock().unwrap().clunks.push(fid);
+            Rmessage::Clunk
+        }
+        Tmessage::Getattr { .. } => Rmessage::Getattr(Attr { qid: DIR, mode: 0o40755, ..Default::default() }),
+        other => panic!("unexpected {other:?}"),
+    });
+    (session, seen)
+}
+
+fn deep_path(depth: usize) -> String {
+    (0..depth).map(|index| format!("/d{index:02}")).collect()
+}
+
+#[test]
+fn long_walks_are_split_into_sixteen_names() {
+    let (mut session, seen) = deep_server(b"none");
+    session.getattr(deep_path(40)).unwrap();
+    let seen = seen.lock().unwrap();
+    let (root, fid, _) = seen.walks[0];
+    assert_eq!(seen.walks, [(root, fid, 16), (fid, fid, 16), (fid, fid, 8)]);
+    assert_eq!(seen.clunks, [fid]);
+}
+
+#[test]
+fn failed_later_chunks_clunk_the_partial_fid() {
+    // d16 opens the second chunk, so the server answers with an error;
+    // d18 is part way through it, so the Rwalk comes back short.
+    for fail in [&b"d16"[..], b"d18"] {
+        let (mut session, seen) = deep_server(fail);
+        let err = session.getattr(deep_path(20)).unwrap_err();
+        assert_eq!(err, format!("{}: rlerror errno={ENOENT}", String::from_utf8_lossy(fail)));
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.walks.len(), 2);
+        assert_eq!(seen.clunks, [seen.walks[0].1]);
+    }
+}
+
+#[test]
+fn failed_first_chunks_leave_no_fid_behind() {
+    for fail in [&b"d00"[..], b"d03"] {
+        let (mut session, seen) = deep_server(fail);
+        let err = session.getattr(deep_path(20)).unwrap_err();
+        assert_eq!(err, format!("{}: rlerror errno={ENOENT}", String::from_utf8_lossy(fail)));
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.walks.len(), 1);
+        assert!(seen.clunks.is_empty(), "a failed Twalk creates no fid");
+    }
+}
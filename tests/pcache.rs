@@ -0,0 +1,120 @@
+//! Page cache read-ahead, write-back and invalidation against the in-memory server.
+
+mod common;
+
+use common::{Kind, Mem};
+use fs9p::{PageCacheConfig, PageCacheMode, Session, PAGE_SIZE};
+
+/// Open modes for 9P2000 and 9P2000.L.
+const RDONLY: (u8, u32) = (0, 0);
+
+fn cached(server: &Mem, mode: PageCacheMode) -> Session {
+    let mut session = common::session(server);
+    session.enable_page_cache(PageCacheConfig { mode, ..PageCacheConfig::default() }).unwrap();
+    session
+}
+
+/// Change the contents and size of file `id` without bumping its qid.version.
+fn resize_in_place(server: &Mem, id: u64, data: &[u8]) {
+    server.state().nodes.get_mut(&id).unwrap().kind = Kind::File(data.to_vec());
+}
+
+#[test]
+fn reopening_sees_server_changes() {
+    for mode in [PageCacheMode::Loose, PageCacheMode::Mmap] {
+        let server = Mem::new();
+        server.add_file("/f", b"original");
+        let mut session = cached(&server, mode);
+        assert_eq!(session.read_file("/f").unwrap(), b"original");
+        server.rewrite_file("/f", b"replaced");
+        assert_eq!(session.read_file("/f").unwrap(), b"replaced", "{mode:?}");
+
+        let reads = server.state().reads.len();
+        assert_eq!(session.read_file("/f").unwrap(), b"replaced");
+        if mode == PageCacheMode::Loose {
+            assert_eq!(server.state().reads.len(), reads, "unchanged file was read again");
+        }
+    }
+}
+
+#[test]
+fn getattr_drops_pages_when_the_size_changes() {
+    for mode in [PageCacheMode::Loose, PageCacheMode::Mmap] {
+        let server = Mem::new();
+        let id = server.add_file("/f", b"short");
+        let mut session = cached(&server, mode);
+        assert_eq!(session.getattr("/f").unwrap().size, 5);
+        let fid = session.open_path_with_flags("/f", RDONLY.0, RDONLY.1).unwrap();
+        let mut buf = [0; 64];
+        assert_eq!(session.read_into(fid, 0, &mut buf).unwrap(), 5);
+
+        resize_in_place(&server, id, b"much longer");
+        assert_eq!(session.getattr("/f").unwrap().size, 11);
+        let len = session.read_into(fid, 0, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"much longer", "{mode:?}");
+        session.close_fid(fid).unwrap();
+    }
+}
+
+#[test]
+fn sequential_reads_grow_the_readahead_window() {
+    let server = Mem::new();
+    let data: Vec<u8> = (0..256 * PAGE_SIZE).map(|i| (i / PAGE_SIZE) as u8).collect();
+    server.add_file("/big", &data);
+    let mut session = common::session(&server);
+    let config = PageCacheConfig { max_readahead: 32, ..PageCacheConfig::default() };
+    session.enable_page_cache(config).unwrap();
+    let fid = session.open_path_with_flags("/big", RDONLY.0, RDONLY.1).unwrap();
+
+    // Bytes fetched from the server by each read that missed the cache.
+    let mut fetches = Vec::new();
+    let mut buf = vec![0; PAGE_SIZE];
+    for page in 0..128 {
+        let before = server.state().reads.len();
+        let offset = (page * PAGE_SIZE) as u64;
+        session.read_exact_at(fid, offset, &mut buf).unwrap();
+        assert_eq!(buf, data[page * PAGE_SIZE..][..PAGE_SIZE]);
+        let fetched: u32 = server.state().reads[before..].iter().map(|(_, count)| count).sum();
+        if fetched > 0 {
+            fetches.push(fetched as usize / PAGE_SIZE);
+        }
+    }
+    assert!(fetches.windows(2).all(|pair| pair[0] <= pair[1]), "{fetches:?}");
+    assert_eq!(fetches[..4], [5, 9, 17, 33], "window doubles from four pages");
+    assert_eq!(*fetches.last().unwrap(), 33, "window stops at max_readahead");
+    assert!(fetches.len() < 10, "{fetches:?}");
+
+    // A jump away from the sequential position fetches only the page needed.
+    let before = server.state().reads.len();
+    session.read_exact_at(fid, (200 * PAGE_SIZE) as u64, &mut buf).unwrap();
+    let fetched: u32 = server.state().reads[before..].iter().map(|(_, count)| count).sum();
+    assert_eq!(fetched as usize, PAGE_SIZE);
+    session.close_fid(fid).unwrap();
+}
+
+#[test]
+fn dirty_pages_are_written_back_in_iounit_sized_writes() {
+    let server = Mem::new();
+    server.state().iounit = 8192;
+    let mut session = cached(&server, PageCacheMode::Loose);
+    let fid = session.create_file("/out").unwrap();
+
+    let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    for (index, chunk) in data.chunks(500).enumerate() {
+        assert_eq!(session.write_fid(fid, (index * 500) as u64, chunk).unwrap(), chunk.len());
+    }
+    assert!(server.state().writes.is_empty(), "loose writes are buffered");
+
+    session.fsync_fid(fid).unwrap();
+    let writes = server.state().writes.clone();
+    let expected: Vec<(u64, u32)> = (0..data.len())
+        .step_by(8192)
+        .map(|offset| (offset as u64, (data.len() - offset).min(8192) as u32))
+        .collect();
+    assert_eq!(writes, expected);
+    assert_eq!(server.file("/out").unwrap(), data);
+
+    session.close_fid(fid).unwrap();
+    assert_eq!(server.state().writes.len(), expected.len(), "clean pages are not written again");
+    assert_eq!(server.live_fids(), 1);
+}
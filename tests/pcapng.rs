@@ -0,0 +1,227 @@
+//! pcapng captures checked against the block layout in the pcapng spec.
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{Mem, Sink};
+use fs9p::{CaptureTransport, Session, Transport, VirtualClock};
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 1;
+const BLOCK_EPB: u32 = 6;
+const LINKTYPE_RAW: u16 = 101;
+const TCP_SYN: u8 = 0x02;
+
+fn u16_le(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn u32_le(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u16_be(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn u32_be(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// Split a capture into (type, body) blocks, checking the framing of each:
+/// a 32-bit aligned total length repeated after the body.
+fn blocks(capture: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < capture.len() {
+        let block_type = u32_le(capture, offset);
+        let total = u32_le(capture, offset + 4) as usize;
+        assert_eq!(total % 4, 0, "block at {offset} is not 32-bit aligned");
+        assert!(total >= 12 && offset + total <= capture.len(), "block at {offset} overruns the capture");
+        assert_eq!(u32_le(capture, offset + total - 4) as usize, total, "trailing length at {offset}");
+        blocks.push((block_type, &capture[offset + 8..offset + total - 4]));
+        offset += total;
+    }
+    blocks
+}
+
+/// Internet checksum; zero when computed over data that includes its own checksum.
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let bytes: Vec<u8> = chunks.concat();
+    let mut sum: u32 = bytes.chunks(2).map(|pair| u32::from(pair[0]) << 8 | u32::from(*pair.get(1).unwrap_or(&0))).sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// One TCP segment pulled out of an enhanced packet block.
+struct Segment {
+    timestamp: u64,
+    from_client: bool,
+    seq: u32,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+/// Check an enhanced packet block and the IPv4/TCP packet it carries.
+fn segment(body: &[u8]) -> Segment {
+    assert_eq!(u32_le(body, 0), 0, "interface id");
+    let timestamp = u64::from(u32_le(body, 4)) << 32 | u64::from(u32_le(body, 8));
+    let captured = u32_le(body, 12) as usize;
+    assert_eq!(u32_le(body, 16) as usize, captured, "original length");
+    let padded = captured.div_ceil(4) * 4;
+    assert_eq!(body.len(), 20 + padded, "packet data is padded to 32 bits and nothing else follows");
+    assert!(body[20 + captured..].iter().all(|byte| *byte == 0), "padding is zeroed");
+
+    let packet = &body[20..20 + captured];
+    assert_eq!(packet[0], 0x45, "IPv4 with a bare 20-byte header");
+    assert_eq!(u16_be(packet, 2) as usize, captured, "IP total length");
+    assert_eq!(packet[9], 6, "TCP");
+    assert_eq!(checksum(&[&packet[..20]]), 0, "IP header checksum");
+
+    let tcp = &packet[20..];
+    let pseudo = [&packet[12..20], &[0, 6], &(tcp.len() as u16).to_be_bytes()[..]].concat();
+    assert_eq!(checksum(&[&pseudo, tcp]), 0, "TCP checksum");
+    assert_eq!(tcp[12] >> 4, 5, "bare 20-byte TCP header");
+    let (sport, dport) = (u16_be(tcp, 0), u16_be(tcp, 2));
+    assert!(sport == 564 || dport == 564, "9P port");
+    Segment {
+        timestamp,
+        from_client: dport == 564,
+        seq: u32_be(tcp, 4),
+        flags: tcp[13],
+        payload: tcp[20..].to_vec(),
+    }
+}
+
+/// Check the section header and interface description, then return every packet.
+fn segments(capture: &[u8]) -> Vec<Segment> {
+    let blocks = blocks(capture);
+    let (shb, shb_body) = blocks[0];
+    assert_eq!(shb, BLOCK_SHB);
+    assert_eq!(shb_body.len(), 16);
+    assert_eq!(u32_le(shb_body, 0), 0x1A2B_3C4D, "byte-order magic");
+    assert_eq!((u16_le(shb_body, 4), u16_le(shb_body, 6)), (1, 0), "version 1.0");
+    assert_eq!(&shb_body[8..], &(-1i64).to_le_bytes(), "unspecified section length");
+
+    let (idb, idb_body) = blocks[1];
+    assert_eq!(idb, BLOCK_IDB);
+    assert_eq!(idb_body.len(), 8);
+    assert_eq!(u16_le(idb_body, 0), LINKTYPE_RAW);
+    assert_eq!(u16_le(idb_body, 2), 0, "reserved");
+
+    blocks[2..]
+        .iter()
+        .map(|(block_type, body)| {
+            assert_eq!(*block_type, BLOCK_EPB);
+            segment(body)
+        })
+        .collect()
+}
+
+/// The bytes each side sent after the handshake, checking sequence numbers run on.
+fn streams(segments: &[Segment]) -> (Vec<u8>, Vec<u8>) {
+    let (mut client, mut server) = (Vec::new(), Vec::new());
+    let (mut client_seq, mut server_seq) = (None, None);
+    for segment in segments {
+        let (stream, seq) = if segment.from_client {
+            (&mut client, &mut client_seq)
+        } else {
+            (&mut server, &mut server_seq)
+        };
+        if let Some(expected) = *seq {
+            assert_eq!(segment.seq, expected, "sequence numbers are contiguous");
+        }
+        let syn = u32::from(segment.flags & TCP_SYN != 0);
+        *seq = Some(segment.seq.wrapping_add(segment.payload.len() as u32 + syn));
+        stream.extend_from_slice(&segment.payload);
+    }
+    (client, server)
+}
+
+/// Records the bytes each side put on the wire.
+#[derive(Clone, Default)]
+struct Tap<T> {
+    inner: T,
+    requests: Arc<Mutex<Vec<u8>>>,
+    replies: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<T: Transport> Transport for Tap<T> {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        let size = self.inner.request(req, resp)?;
+        self.requests.lock().unwrap().extend_from_slice(req);
+        self.replies.lock().unwrap().extend_from_slice(&resp[..size]);
+        Ok(size)
+    }
+}
+
+#[test]
+fn session_traffic_follows_the_block_layout() {
+    let server = Mem::new();
+    let tap = Tap { inner: server, ..Tap::default() };
+    let sink = Sink::default();
+    let capture = CaptureTransport::new(tap.clone(), sink.clone(), None).unwrap();
+    let mut session = Session::new(Box::new(capture), String::from("test"));
+    session.negotiate().unwrap();
+    // Payloads of every length modulo four, so every padding width appears.
+    for len in 0..8 {
+        let path = format!("/f{len}");
+        session.write_file(&path, &vec![b'x'; len]).unwrap();
+        assert_eq!(session.read_file(&path).unwrap().len(), len);
+    }
+
+    let segments = segments(&sink.bytes());
+    assert!(segments[0].flags & TCP_SYN != 0 && segments[0].from_client, "handshake opens the capture");
+    let (client, server) = streams(&segments);
+    assert_eq!(client, *tap.requests.lock().unwrap());
+    assert_eq!(server, *tap.replies.lock().unwrap());
+    let paddings: BTreeSet<usize> = segments.iter().map(|s| (40 + s.payload.len()) % 4).collect();
+    assert_eq!(paddings.len(), 4, "not every padding width was exercised");
+}
+
+/// Answers every request with a `len`-byte reply.
+struct Large(usize);
+
+impl Transport for Large {
+    fn request(&self, _req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        for (index, byte) in resp[..self.0].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        Ok(self.0)
+    }
+}
+
+#[test]
+fn large_messages_span_several_segments() {
+    let sink = Sink::default();
+    let capture = CaptureTransport::new(Large(150_000), sink.clone(), None).unwrap();
+    let mut resp = vec![0; 150_000];
+    capture.request(b"request", &mut resp).unwrap();
+
+    let segments = segments(&sink.bytes());
+    let replies: Vec<_> = segments.iter().filter(|s| !s.from_client && !s.payload.is_empty()).collect();
+    assert_eq!(replies.len(), 3);
+    assert!(replies.iter().all(|s| 40 + s.payload.len() <= usize::from(u16::MAX)));
+    let (client, server) = streams(&segments);
+    assert_eq!(client, b"request");
+    assert_eq!(server, resp);
+}
+
+#[test]
+fn timestamps_are_clock_microseconds() {
+    let clock = VirtualClock::new();
+    // Past 2^32 microseconds, so both timestamp halves are used.
+    clock.advance(Duration::from_secs(5000));
+    let sink = Sink::default();
+    let capture = CaptureTransport::new(Large(11), sink.clone(), Some(Box::new(clock.clone()))).unwrap();
+    let mut resp = [0; 11];
+    capture.request(b"request", &mut resp).unwrap();
+
+    let segments = segments(&sink.bytes());
+    assert!(segments.iter().all(|s| s.timestamp == 5_000_000_000));
+}
@@ -0,0 +1,119 @@
+//! End-to-end tests against a child server speaking 9P on stdin/stdout.
+//!
+//! The test binary is its own server: when `FS9P_CHILD` is set it serves an
+//! in-memory tree on its stdio instead of running tests, which is why this
+//! target runs without the libtest harness.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{env, fs, thread};
+
+use common::Mem;
+use fs9p::{ProcessTransport, Session, Transport};
+
+const MSIZE: u32 = 64 * 1024;
+
+/// Serve 9P on stdio in the way `mode` asks.
+fn child(mode: &str) {
+    if let Ok(path) = env::var("FS9P_PID_FILE") {
+        fs::write(path, std::process::id().to_string()).unwrap();
+    }
+    let server = Mem::new();
+    server.add_file("/dir/a", b"alpha");
+    server.add_file("/dir/b", b"beta");
+    let (mut stdin, mut stdout) = (std::io::stdin().lock(), std::io::stdout().lock());
+    let mut resp = vec![0u8; MSIZE as usize];
+    for served in 0.. {
+        if mode == "crash" && served == 2 {
+            eprintln!("fatal: disk on fire");
+            std::process::exit(3);
+        }
+        let mut size = [0u8; 4];
+        if stdin.read_exact(&mut size).is_err() {
+            break;
+        }
+        let mut req = size.to_vec();
+        req.resize(u32::from_le_bytes(size) as usize, 0);
+        stdin.read_exact(&mut req[4..]).unwrap();
+        let len = server.request(&req, &mut resp).unwrap();
+        stdout.write_all(&resp[..len]).unwrap();
+        stdout.flush().unwrap();
+    }
+    if mode == "linger" {
+        // Outlive the closed pipe, so only a kill ends this process promptly.
+        thread::sleep(Duration::from_secs(60));
+    }
+}
+
+fn spawn(mode: &str, envs: &[(&str, &str)]) -> ProcessTransport {
+    let mut command = Command::new(env::current_exe().unwrap());
+    command.env("FS9P_CHILD", mode).envs(envs.iter().copied());
+    ProcessTransport::spawn(&mut command, MSIZE).unwrap()
+}
+
+fn session(transport: ProcessTransport) -> Session {
+    let mut session = Session::new(Box::new(transport), String::from("test"));
+    session.negotiate().unwrap();
+    session
+}
+
+fn serves_a_session() {
+    let mut session = session(spawn("serve", &[]));
+    session.write_file("/dir/c", b"gamma").unwrap();
+    assert_eq!(session.read_file("/dir/c").unwrap(), b"gamma");
+    // Directory attributes are fetched in batches.
+    let mut names: Vec<_> = session.read_dir_plus("/dir").unwrap().into_iter().map(|(entry, _)| entry.name).collect();
+    names.sort();
+    assert_eq!(names, [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+}
+
+fn errors_report_exit_status_and_stderr() {
+    let mut session = session(spawn("crash", &[]));
+    let err = session.read_file("/dir/a").unwrap_err();
+    assert!(err.contains("exit status: 3"), "{err}");
+    assert!(err.contains("fatal: disk on fire"), "{err}");
+}
+
+fn drop_kills_the_child() {
+    let pid_file = env::temp_dir().join(format!("fs9p-process-{}", std::process::id()));
+    let transport = spawn("linger", &[("FS9P_PID_FILE", pid_file.to_str().unwrap())]);
+    let session = session(transport);
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    fs::remove_file(&pid_file).unwrap();
+
+    let (done, dropped) = mpsc::channel();
+    thread::spawn(move || {
+        drop(session);
+        done.send(()).unwrap();
+    });
+    dropped.recv_timeout(Duration::from_secs(10)).expect("drop waited for the child to exit by itself");
+    let alive = Command::new("kill").args(["-0", pid.trim()]).stderr(Stdio::null()).status().unwrap().success();
+    assert!(!alive, "child {pid} survived the transport");
+}
+
+fn spawn_failures_are_reported() {
+    let err = ProcessTransport::spawn(&mut Command::new("/nonexistent/9p-server"), MSIZE).err().unwrap();
+    assert!(err.starts_with("spawn failed"), "{err}");
+}
+
+fn main() {
+    if let Ok(mode) = env::var("FS9P_CHILD") {
+        return child(&mode);
+    }
+    let tests: [(&str, fn()); 4] = [
+        ("serves_a_session", serves_a_session),
+        ("errors_report_exit_status_and_stderr", errors_report_exit_status_and_stderr),
+        ("drop_kills_the_child", drop_kills_the_child),
+        ("spawn_failures_are_reported", spawn_failures_are_reported),
+    ];
+    println!("\nrunning {} tests", tests.len());
+    for (name, test) in tests {
+        test();
+        println!("test {name} ... ok");
+    }
+    println!("\ntest result: ok. {} passed\n", tests.len());
+}
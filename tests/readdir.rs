@@ -0,0 +1,78 @@
+//! ReadDir cursors: resuming from cookies and releasing fids.
+
+mod common;
+
+use common::Mem;
+use fs9p::Session;
+
+const TREADDIR: u8 = 40;
+
+/// A directory large enough to need several Treaddir replies.
+fn big_dir() -> (Mem, Vec<Vec<u8>>) {
+    let server = Mem::new();
+    server.add_dir("/d");
+    let names: Vec<Vec<u8>> = (0..1000).map(|index| format!("entry-{index:04}").into_bytes()).collect();
+    for name in &names {
+        server.add_file(&format!("/d/{}", String::from_utf8_lossy(name)), b"");
+    }
+    (server, names)
+}
+
+fn names(session: &mut Session) -> Vec<Vec<u8>> {
+    let entries = session.read_dir("/d").unwrap();
+    entries.map(|entry| entry.unwrap().name).collect()
+}
+
+#[test]
+fn listings_with_hashed_cookies_are_complete() {
+    let (server, expected) = big_dir();
+    let mut session = common::session(&server);
+    let listed = names(&mut session);
+    assert!(server.count(TREADDIR) > 3, "the listing spans several replies");
+    assert_eq!(listed[..2], [b".".to_vec(), b"..".to_vec()]);
+    assert_eq!(listed[2..], expected);
+}
+
+#[test]
+fn seek_resumes_from_a_cookie() {
+    let (server, _) = big_dir();
+    let mut session = common::session(&server);
+    let all = names(&mut session);
+
+    // Stop part way, as a getdents call with a small buffer would.
+    let mut entries = session.read_dir("/d").unwrap();
+    let first: Vec<_> = entries.by_ref().take(600).map(|entry| entry.unwrap()).collect();
+    let cookie = entries.tell();
+    assert_eq!(cookie, first.last().unwrap().cookie);
+    drop(entries);
+
+    let mut entries = session.read_dir("/d").unwrap();
+    entries.seek(cookie);
+    let rest: Vec<Vec<u8>> = entries.by_ref().map(|entry| entry.unwrap().name).collect();
+    assert_eq!(rest, all[600..]);
+
+    // Cookies of earlier entries resume there too, and 0 rewinds.
+    entries.seek(first[99].cookie);
+    assert_eq!(entries.next().unwrap().unwrap().name, all[100]);
+    entries.seek(0);
+    assert_eq!(entries.next().unwrap().unwrap().name, b".");
+}
+
+#[test]
+fn dropping_the_cursor_clunks_its_fid() {
+    let (server, _) = big_dir();
+    let mut session = common::session(&server);
+    let mut entries = session.read_dir("/d").unwrap();
+    entries.next().unwrap().unwrap();
+    assert_eq!(server.live_fids(), 2);
+    drop(entries);
+    assert_eq!(server.live_fids(), 1);
+
+    // A cursor over a caller's fid leaves it open.
+    let fid = session.open_path_with_flags("/d", 0, 0).unwrap();
+    let count = session.read_dir_fid(fid).count();
+    assert_eq!(count, 1002);
+    assert_eq!(server.live_fids(), 2);
+    session.close_fid(fid).unwrap();
+    assert_eq!(server.live_fids(), 1);
+}
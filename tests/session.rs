@@ -0,0 +1,201 @@
+//! Session behaviour against in-memory and scripted servers.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{scripted, Mem, EACCES};
+use fs9p::{Dialect, PageCacheConfig, Qid, Rmessage, Session, Tmessage, Transport};
+
+const FILE: Qid = Qid { type_: 0, version: 0, path: 2 };
+
+#[test]
+fn create_sends_an_empty_extension_on_9p2000u() {
+    let extensions = Arc::new(Mutex::new(Vec::new()));
+    let seen = extensions.clone();
+    let mut session = scripted(Dialect::P2000U, move |request| match request {
+        Tmessage::Walk { wnames, .. } => {
+            assert!(wnames.is_empty());
+            Rmessage::Walk { wqids: Default::default() }
+        }
+        Tmessage::Create { extension, .. } => {
+            seen.lock().unwrap().push(extension.map(<[u8]>::to_vec));
+            Rmessage::Create { qid: FILE, iounit: 0 }
+        }
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        other => panic!("unexpected {other:?}"),
+    });
+    let fid = session.create_file("/new").unwrap();
+    session.close_fid(fid).unwrap();
+    assert_eq!(*extensions.lock().unwrap(), [Some(Vec::new())]);
+}
+
+#[test]
+fn create_on_9p2000l_uses_lcreate() {
+    let server = Mem::new();
+    let mut session = common::session(&server);
+    let fid = session.create_file("/new").unwrap();
+    session.write_all_at(fid, 0, b"data").unwrap();
+    session.close_fid(fid).unwrap();
+    assert_eq!(server.file("/new").unwrap(), b"data");
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn writes_are_split_by_iounit_and_msize() {
+    let server = Mem::new();
+    server.state().iounit = 1000;
+    let mut session = common::session(&server);
+    session.write_file("/f", &[7; 2500]).unwrap();
+    assert_eq!(server.state().writes, [(0, 1000), (1000, 1000), (2000, 500)]);
+    assert_eq!(session.read_file("/f").unwrap(), [7; 2500]);
+
+    // Without an iounit each Twrite fills msize less its 24-byte header.
+    server.state().iounit = 0;
+    server.state().writes.clear();
+    session.write_file("/g", &[1; 40_000]).unwrap();
+    assert_eq!(server.state().writes, [(0, 16360), (16360, 16360), (32720, 7280)]);
+    assert_eq!(server.file("/g").unwrap(), [1; 40_000]);
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn write_file_creates_missing_files() {
+    let server = Mem::new();
+    let mut session = common::session(&server);
+    session.write_file("/new", b"first").unwrap();
+    session.write_file("/new", b"2nd").unwrap();
+    assert_eq!(server.file("/new").unwrap(), b"2nd");
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn write_file_returns_walk_errors_other_than_enoent() {
+    let mut session = scripted(Dialect::P2000L, |request| match request {
+        Tmessage::Walk { wnames, .. } if wnames.is_empty() => Rmessage::Walk { wqids: Default::default() },
+        Tmessage::Walk { .. } => Rmessage::Lerror { ecode: EACCES },
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        other => panic!("unexpected {other:?}"),
+    });
+    let err = session.write_file("/secret", b"data").unwrap_err();
+    assert!(err.contains("errno=13"), "{err}");
+}
+
+#[test]
+fn write_file_creates_on_rerror_not_found() {
+    let created = Arc::new(Mutex::new(false));
+    let seen = created.clone();
+    let mut session = scripted(Dialect::P2000, move |request| match request {
+        Tmessage::Walk { wnames, .. } if wnames.is_empty() => Rmessage::Walk { wqids: Default::default() },
+        Tmessage::Walk { .. } => Rmessage::Error { ename: "file does not exist", errno: None },
+        Tmessage::Create { .. } => {
+            *seen.lock().unwrap() = true;
+            Rmessage::Create { qid: FILE, iounit: 0 }
+        }
+        Tmessage::Write { data, .. } => Rmessage::Write { count: data.len() as u32 },
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        other => panic!("unexpected {other:?}"),
+    });
+    session.write_file("/new", b"data").unwrap();
+    assert!(*created.lock().unwrap());
+}
+
+#[test]
+fn write_file_writes_back_loose_pages_before_clunking() {
+    let server = Mem::new();
+    server.add_file("/old", b"previous contents");
+    let mut session = common::session(&server);
+    session.enable_page_cache(PageCacheConfig::default()).unwrap();
+
+    session.write_file("/new", b"created").unwrap();
+    session.write_file("/old", b"replaced").unwrap();
+    assert_eq!(server.file("/new").unwrap(), b"created");
+    assert_eq!(server.file("/old").unwrap(), b"replaced");
+    session.flush_page_cache().unwrap();
+    assert_eq!(session.read_file("/old").unwrap(), b"replaced");
+    assert_eq!(server.live_fids(), 1);
+}
+
+/// One 9P2000.L directory entry for `name` whose offset is `cookie`.
+fn dirent(name: &[u8], cookie: u64) -> &'static [u8] {
+    let mut entry = vec![0];
+    entry.extend_from_slice(&0u32.to_le_bytes());
+    entry.extend_from_slice(&u64::from(name[0]).to_le_bytes());
+    entry.extend_from_slice(&cookie.to_le_bytes());
+    entry.push(8);
+    entry.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    entry.extend_from_slice(name);
+    Vec::leak(entry)
+}
+
+#[test]
+fn read_dir_stops_when_cookies_cycle() {
+    // 0 -> 7 -> 9 -> 7 -> ...
+    let (a, b) = (dirent(b"a", 7), dirent(b"b", 9));
+    let mut session = scripted(Dialect::P2000L, move |request| match request {
+        Tmessage::Walk { .. } => Rmessage::Walk { wqids: Default::default() },
+        Tmessage::Lopen { .. } => Rmessage::Lopen { qid: Qid { type_: 0x80, version: 0, path: 1 }, iounit: 0 },
+        Tmessage::Readdir { offset: 0 | 9, .. } => Rmessage::Readdir { data: a },
+        Tmessage::Readdir { offset: 7, .. } => Rmessage::Readdir { data: b },
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        other => panic!("unexpected {other:?}"),
+    });
+    let entries: Vec<_> = session.read_dir("/").unwrap().take(10).collect();
+    assert_eq!(entries.len(), 3, "{entries:?}");
+    assert_eq!(entries[0].as_ref().unwrap().name, b"a");
+    assert_eq!(entries[1].as_ref().unwrap().name, b"b");
+    assert!(entries[2].as_ref().unwrap_err().contains("repeated"));
+}
+
+/// Scatter/gather transport that records where each payload buffer lives.
+#[derive(Clone, Default)]
+struct Scatter {
+    server: Mem,
+    /// Address and length of every `req_data` slice.
+    sent: Arc<Mutex<Vec<(usize, usize)>>>,
+    /// Address and length of every `resp_data` buffer.
+    received: Arc<Mutex<Vec<(usize, usize)>>>,
+}
+
+impl Transport for Scatter {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.server.request(req, resp)
+    }
+
+    fn is_vectored(&self) -> bool {
+        true
+    }
+
+    fn request_vectored(
+        &self,
+        req_head: &[u8],
+        req_data: &[&[u8]],
+        resp_head: &mut [u8],
+        resp_data: &mut [u8],
+    ) -> Result<usize, String> {
+        let sent = req_data.iter().map(|data| (data.as_ptr() as usize, data.len()));
+        self.sent.lock().unwrap().extend(sent);
+        self.received.lock().unwrap().push((resp_data.as_ptr() as usize, resp_data.len()));
+        self.server.request_vectored(req_head, req_data, resp_head, resp_data)
+    }
+}
+
+#[test]
+fn vectored_payloads_stay_in_caller_buffers() {
+    let transport = Scatter::default();
+    let mut session = Session::new(Box::new(transport.clone()), String::from("test"));
+    session.negotiate().unwrap();
+    let fid = session.create_file("/f").unwrap();
+
+    let (first, second) = (vec![1u8; 3000], vec![2u8; 5000]);
+    assert_eq!(session.write_vectored_at(fid, 0, &[&first, &second]).unwrap(), 8000);
+    let sent = transport.sent.lock().unwrap().clone();
+    assert_eq!(sent, [(first.as_ptr() as usize, 3000), (second.as_ptr() as usize, 5000)]);
+
+    let mut buf = vec![0u8; 8000];
+    transport.received.lock().unwrap().clear();
+    assert_eq!(session.read_into(fid, 0, &mut buf).unwrap(), 8000);
+    assert_eq!(*transport.received.lock().unwrap(), [(buf.as_ptr() as usize, 8000)]);
+    assert_eq!(buf, [first, second].concat());
+    session.close_fid(fid).unwrap();
+}
@@ -0,0 +1,105 @@
+//! StreamTransport framing and how the session copes with oversized replies.
+
+mod common;
+
+use common::{Loopback, Script};
+use fs9p::{Dialect, Qid, Rmessage, Session, StreamTransport, Tmessage, Transport, WalkList};
+
+const FILE: Qid = Qid { type_: 0, version: 0, path: 2 };
+const DENIED: &str = "permission denied: the server refuses to write this file";
+
+/// A 9P2000.u server whose file can be opened but neither read nor written.
+fn read_only(request: Tmessage<'_>) -> Rmessage<'static> {
+    match request {
+        Tmessage::Walk { wnames, .. } => {
+            let wqids = wnames.iter().map(|_| FILE).collect::<Vec<_>>();
+            Rmessage::Walk { wqids: WalkList::try_from(&wqids[..]).unwrap() }
+        }
+        Tmessage::Open { .. } => Rmessage::Open { qid: FILE, iounit: 0 },
+        Tmessage::Read { .. } | Tmessage::Write { .. } => Rmessage::Error { ename: DENIED, errno: Some(13) },
+        Tmessage::Clunk { .. } => Rmessage::Clunk,
+        other => panic!("unexpected {other:?}"),
+    }
+}
+
+type Handler = fn(Tmessage<'_>) -> Rmessage<'static>;
+
+fn read_only_server() -> Script<Handler> {
+    Script { dialect: Dialect::P2000U, handler: read_only }
+}
+
+fn stream_session() -> Session {
+    let transport = StreamTransport::new(Loopback::new(read_only_server()), 16384);
+    let mut session = Session::new(Box::new(transport), String::from("test"));
+    session.negotiate().unwrap();
+    session
+}
+
+#[test]
+fn write_errors_reach_the_caller_over_a_stream() {
+    let mut session = stream_session();
+    let fid = session.open_path_with_flags("/file", 1, 1).unwrap();
+    for _ in 0..3 {
+        let err = session.write_fid(fid, 0, &[7u8; 4096]).unwrap_err();
+        assert_eq!(err, DENIED);
+        let err = session.write_vectored_at(fid, 0, &[b"a", b"b"]).unwrap_err();
+        assert_eq!(err, DENIED);
+    }
+    session.close_fid(fid).unwrap();
+}
+
+#[test]
+fn small_read_errors_reach_the_caller_over_a_stream() {
+    let mut session = stream_session();
+    let fid = session.open_path_with_flags("/file", 0, 0).unwrap();
+    for len in [1, 16, 255, 256, 4096] {
+        let mut buf = vec![0u8; len];
+        let err = session.read_into(fid, 0, &mut buf).unwrap_err();
+        assert_eq!(err, DENIED, "read of {len}");
+    }
+    session.close_fid(fid).unwrap();
+}
+
+#[test]
+fn oversized_reply_is_skipped_without_breaking_the_stream() {
+    let transport = StreamTransport::new(Loopback::new(read_only_server()), 16384);
+    let mut req = Vec::new();
+    Tmessage::Read { fid: 1, offset: 0, count: 8 }.encode(1, Dialect::P2000U, &mut req).unwrap();
+    let mut small = [0u8; 16];
+    let err = transport.request(&req, &mut small).unwrap_err();
+    assert!(err.contains("larger than buffer"), "{err}");
+
+    Tmessage::Clunk { fid: 1 }.encode(2, Dialect::P2000U, &mut req).unwrap();
+    let len = transport.request(&req, &mut small).unwrap();
+    assert_eq!(Rmessage::decode(&small[..len], Dialect::P2000U).unwrap(), (2, Rmessage::Clunk));
+}
+
+#[test]
+fn oversized_batch_reply_fails_the_batch_but_keeps_the_stream() {
+    let transport = StreamTransport::new(Loopback::new(read_only_server()), 16384);
+    let mut read = Vec::new();
+    Tmessage::Read { fid: 1, offset: 0, count: 8 }.encode(1, Dialect::P2000U, &mut read).unwrap();
+    let mut clunk = Vec::new();
+    Tmessage::Clunk { fid: 1 }.encode(2, Dialect::P2000U, &mut clunk).unwrap();
+    let (mut first, mut second) = ([0u8; 16], [0u8; 16]);
+    let err = transport
+        .request_batch(&[&read, &clunk], &mut [&mut first, &mut second])
+        .unwrap_err();
+    assert!(err.contains("larger than buffer"), "{err}");
+
+    let len = transport.request(&clunk, &mut second).unwrap();
+    assert_eq!(Rmessage::decode(&second[..len], Dialect::P2000U).unwrap(), (2, Rmessage::Clunk));
+}
+
+#[test]
+fn lost_framing_breaks_the_stream() {
+    let transport = StreamTransport::new(Loopback::new(read_only_server()), 16384);
+    let mut clunk = Vec::new();
+    Tmessage::Clunk { fid: 1 }.encode(1, Dialect::P2000U, &mut clunk).unwrap();
+    let mut resp = [0u8; 16];
+    // A frame cut short leaves the stream somewhere in the middle of a reply.
+    let err = transport.request(&clunk[..clunk.len() - 1], &mut resp).unwrap_err();
+    assert!(err.contains("transport closed"), "{err}");
+    let err = transport.request(&clunk, &mut resp).unwrap_err();
+    assert_eq!(err, "transport closed: framing lost");
+}
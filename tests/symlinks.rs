@@ -0,0 +1,95 @@
+//! Client-side symlink expansion against the in-memory server.
+
+mod common;
+
+use common::{Mem, ENOENT};
+use fs9p::{DotDotPolicy, Session};
+
+const ELOOP: u32 = 40;
+
+fn following(server: &Mem) -> Session {
+    let mut session = common::session(server);
+    session.set_follow_symlinks(true);
+    session
+}
+
+/// /usr/lib/libc.so and links to it from around the tree.
+fn tree() -> Mem {
+    let server = Mem::new();
+    server.add_file("/usr/lib/libc.so", b"elf");
+    server.add_symlink("/usr/bin/rel", "../lib/libc.so");
+    server.add_symlink("/usr/bin/abs", "/usr/lib/libc.so");
+    server.add_symlink("/usr/bin/libdir", "../lib");
+    server.add_symlink("/escape", "../../../usr/lib/libc.so");
+    server
+}
+
+#[test]
+fn relative_and_absolute_targets() {
+    let server = tree();
+    let mut session = following(&server);
+    for path in ["/usr/bin/rel", "/usr/bin/abs", "/usr/bin/libdir/libc.so", "/escape"] {
+        assert_eq!(session.read_file(path).unwrap(), b"elf", "{path}");
+        assert_eq!(session.canonicalize(path).unwrap(), b"/usr/lib/libc.so", "{path}");
+    }
+    assert_eq!(session.resolve("/usr/bin/libdir/../bin/rel").unwrap(), b"/usr/bin/rel");
+    assert_eq!(session.getattr("/usr/bin/abs").unwrap().size, 3);
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn links_are_not_followed_unless_enabled() {
+    let server = tree();
+    let mut session = common::session(&server);
+    assert_eq!(session.getattr("/usr/bin/abs").unwrap().size, "/usr/lib/libc.so".len() as u64);
+    assert_eq!(session.read_link("/usr/bin/abs").unwrap(), b"/usr/lib/libc.so");
+}
+
+#[test]
+fn expansion_stops_after_forty_links() {
+    let server = Mem::new();
+    server.add_file("/end", b"done");
+    // /l0 -> /l1 -> ... -> /l40 -> /end is 41 expansions.
+    for index in 0..=40 {
+        let target = if index == 40 { String::from("/end") } else { format!("/l{}", index + 1) };
+        server.add_symlink(&format!("/l{index}"), &target);
+    }
+    let mut session = following(&server);
+    assert_eq!(session.read_file("/l1").unwrap(), b"done", "40 expansions are allowed");
+    assert_eq!(session.read_file("/l0").unwrap_err(), format!("l40: rlerror errno={ELOOP}"));
+
+    server.add_symlink("/self", "self");
+    assert_eq!(session.canonicalize("/self").unwrap_err(), format!("self: rlerror errno={ELOOP}"));
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn dangling_and_empty_targets_are_not_found() {
+    let server = Mem::new();
+    server.add_symlink("/dangling", "nowhere");
+    server.add_symlink("/empty", "");
+    let mut session = following(&server);
+    assert_eq!(session.read_file("/dangling").unwrap_err(), format!("nowhere: rlerror errno={ENOENT}"));
+    assert_eq!(session.read_file("/empty").unwrap_err(), format!("empty: rlerror errno={ENOENT}"));
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn the_dotdot_policy_covers_caller_paths_not_link_targets() {
+    let server = tree();
+    let mut session = following(&server);
+    for policy in [DotDotPolicy::Clamp, DotDotPolicy::RejectEscape, DotDotPolicy::Reject] {
+        session.set_dotdot_policy(policy);
+        for path in ["/usr/bin/rel", "/usr/bin/libdir/libc.so", "/escape"] {
+            assert_eq!(session.read_file(path).unwrap(), b"elf", "{policy:?} {path}");
+        }
+    }
+
+    session.set_dotdot_policy(DotDotPolicy::Reject);
+    let err = session.read_file("/usr/bin/../lib/libc.so").unwrap_err();
+    assert_eq!(err, "path \"/usr/bin/../lib/libc.so\" contains \"..\"");
+    session.set_dotdot_policy(DotDotPolicy::RejectEscape);
+    let err = session.read_file("/usr/bin/libdir/../../../x").unwrap_err();
+    assert_eq!(err, "path \"/usr/bin/libdir/../../../x\" escapes the root");
+    assert_eq!(server.live_fids(), 1);
+}
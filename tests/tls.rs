@@ -0,0 +1,126 @@
+//! TLS transport against a local rustls server.
+//!
+//! The certificates in `fixtures/tls` were made with openssl: a test CA
+//! signs the server (localhost, 127.0.0.1) and client certificates, and
+//! `other` is an unrelated self-signed localhost certificate.
+
+mod common;
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use common::Mem;
+use fs9p::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use fs9p::rustls::server::WebPkiClientVerifier;
+use fs9p::rustls::{crypto, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use fs9p::{connect_tls, DialOptions, Session, TlsOptions, TlsVerify};
+
+fn cert(name: &str) -> CertificateDer<'static> {
+    let path = format!("{}/tests/fixtures/tls/{name}.crt.der", env!("CARGO_MANIFEST_DIR"));
+    CertificateDer::from(std::fs::read(path).unwrap())
+}
+
+fn key(name: &str) -> PrivateKeyDer<'static> {
+    let path = format!("{}/tests/fixtures/tls/{name}.key.der", env!("CARGO_MANIFEST_DIR"));
+    PrivateKeyDer::try_from(std::fs::read(path).unwrap()).unwrap()
+}
+
+fn roots(name: &str) -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert(name)).unwrap();
+    roots
+}
+
+/// Serve one TLS connection on a local port, presenting `identity` and,
+/// with `require_client`, demanding a client certificate from the test CA.
+fn server(identity: &str, require_client: bool) -> String {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions().unwrap();
+    let builder = if require_client {
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots("ca")), provider).build().unwrap();
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let config = Arc::new(builder.with_single_cert(vec![cert(identity)], key(identity)).unwrap());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let server = Mem::new();
+        server.add_file("/secret", b"ciphertext only");
+        let mut stream = StreamOwned::new(ServerConnection::new(config).unwrap(), socket);
+        common::serve(&server, &mut stream);
+    });
+    format!("tcp!127.0.0.1!{port}")
+}
+
+fn options(server_name: &str, verify: TlsVerify) -> TlsOptions {
+    TlsOptions { server_name: String::from(server_name), verify, client_auth: None }
+}
+
+/// Connect, attach and read a file, so failures after the handshake count too.
+fn read_secret(addr: &str, tls: TlsOptions) -> Result<Vec<u8>, String> {
+    let transport = connect_tls(addr, DialOptions::default(), tls)?;
+    let mut session = Session::new(transport, String::from("test"));
+    session.negotiate()?;
+    session.read_file("/secret")
+}
+
+#[test]
+fn roots_verify_the_server() {
+    let addr = server("server", false);
+    assert_eq!(read_secret(&addr, TlsOptions::new("localhost", roots("ca"))).unwrap(), b"ciphertext only");
+}
+
+#[test]
+fn roots_check_the_server_name() {
+    let addr = server("server", false);
+    let err = read_secret(&addr, TlsOptions::new("example.com", roots("ca"))).unwrap_err();
+    assert!(err.starts_with("tls handshake"), "{err}");
+}
+
+#[test]
+fn roots_reject_untrusted_servers() {
+    let addr = server("other", false);
+    let err = read_secret(&addr, TlsOptions::new("localhost", roots("ca"))).unwrap_err();
+    assert!(err.starts_with("tls handshake"), "{err}");
+}
+
+#[test]
+fn pinned_certificates_skip_chain_and_name_checks() {
+    let addr = server("other", false);
+    let tls = options("any.name", TlsVerify::Pinned(vec![cert("other")]));
+    assert_eq!(read_secret(&addr, tls).unwrap(), b"ciphertext only");
+}
+
+#[test]
+fn pinned_mode_rejects_other_certificates() {
+    let addr = server("server", false);
+    let err = read_secret(&addr, options("localhost", TlsVerify::Pinned(vec![cert("other")]))).unwrap_err();
+    assert!(err.starts_with("tls handshake"), "{err}");
+    let err = connect_tls(&addr, DialOptions::default(), options("localhost", TlsVerify::Pinned(Vec::new()))).err().unwrap();
+    assert_eq!(err, "no pinned tls certificates");
+}
+
+#[test]
+fn client_certificates_authenticate_the_client() {
+    let addr = server("server", true);
+    let mut tls = TlsOptions::new("localhost", roots("ca"));
+    tls.client_auth = Some((vec![cert("client")], key("client")));
+    assert_eq!(read_secret(&addr, tls).unwrap(), b"ciphertext only");
+}
+
+#[test]
+fn servers_requiring_client_certificates_refuse_anonymous_clients() {
+    let addr = server("server", true);
+    assert!(read_secret(&addr, TlsOptions::new("localhost", roots("ca"))).is_err());
+
+    let addr = server("server", true);
+    let mut tls = TlsOptions::new("localhost", roots("ca"));
+    tls.client_auth = Some((vec![cert("other")], key("other")));
+    assert!(read_secret(&addr, tls).is_err());
+}
+
@@ -0,0 +1,366 @@
+//! Transport wrappers composed over boxed transports.
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread::{self, ThreadId};
+
+use common::{Mem, Shared, Sink};
+use fs9p::{
+    CaptureTransport, Dialect, FaultTransport, LoggingTransport, RateLimit, RateLimitTransport, RecordingTransport,
+    ReplayTransport, RetryTransport, Rmessage, Session, Tmessage, Transport, VirtualClock,
+};
+
+#[test]
+fn wrappers_accept_boxed_transports() {
+    let server = Mem::new();
+    server.add_file("/hello", b"world");
+    let boxed: Box<dyn Transport> = Box::new(server.clone());
+    let transport = LoggingTransport::new(RetryTransport::new(boxed, 3));
+    let mut session = Session::new(Box::new(transport), String::from("test"));
+    session.negotiate().unwrap();
+    assert_eq!(session.read_file("/hello").unwrap(), b"world");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn wrappers_accept_dialed_transports() {
+    let dial = |addr: &str| -> Result<RetryTransport<Box<dyn Transport>>, String> {
+        Ok(RetryTransport::new(fs9p::connect(addr)?, 3))
+    };
+    assert!(dial("unix!/nonexistent/9p.sock").is_err());
+}
+
+/// Serves `Mem` and counts how many batches reach it.
+#[derive(Clone)]
+struct Batching {
+    server: Mem,
+    batches: Arc<AtomicUsize>,
+}
+
+impl Batching {
+    fn new(server: &Mem) -> Self {
+        Self { server: server.clone(), batches: Arc::default() }
+    }
+
+    fn batches(&self) -> usize {
+        self.batches.load(Ordering::Relaxed)
+    }
+}
+
+impl Transport for Batching {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        self.server.request(req, resp)
+    }
+
+    fn request_batch(&self, reqs: &[&[u8]], resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        reqs.iter().zip(resps.iter_mut()).map(|(req, resp)| self.server.request(req, resp)).collect()
+    }
+}
+
+fn directory() -> Mem {
+    let server = Mem::new();
+    server.add_file("/dir/a", b"1");
+    server.add_file("/dir/b", b"22");
+    server.add_file("/dir/c", b"333");
+    server
+}
+
+/// Names and sizes from `read_dir_plus("/dir")`, sorted by name.
+fn listing(transport: impl Transport + 'static) -> Vec<(Vec<u8>, u64)> {
+    let mut session = Session::new(Box::new(transport), String::from("test"));
+    session.negotiate().unwrap();
+    let mut entries: Vec<_> = session
+        .read_dir_plus("/dir")
+        .unwrap()
+        .into_iter()
+        .map(|(entry, attr)| (entry.name, attr.size))
+        .collect();
+    entries.sort();
+    entries
+}
+
+fn expected() -> Vec<(Vec<u8>, u64)> {
+    vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2), (b"c".to_vec(), 3)]
+}
+
+fn assert_forwards_batches<W: Transport + 'static>(wrap: impl FnOnce(Batching) -> W) {
+    let server = directory();
+    let inner = Batching::new(&server);
+    assert_eq!(listing(wrap(inner.clone())), expected());
+    assert!(inner.batches() > 0, "batches were split into single requests");
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn wrappers_forward_batches() {
+    assert_forwards_batches(|inner| RetryTransport::new(inner, 3));
+    assert_forwards_batches(|inner| {
+        let limit = RateLimit { ops_per_sec: Some(1000), ..RateLimit::default() };
+        RateLimitTransport::new(inner, limit, Box::new(VirtualClock::new()))
+    });
+    assert_forwards_batches(LoggingTransport::new);
+    assert_forwards_batches(|inner| FaultTransport::new(inner, 7));
+    assert_forwards_batches(|inner| RecordingTransport::new(inner, Sink::default()).unwrap());
+    assert_forwards_batches(|inner| CaptureTransport::new(inner, Sink::default(), None).unwrap());
+}
+
+#[test]
+fn fault_transport_tracks_fids_across_batches() {
+    let server = directory();
+    let fault = Arc::new(FaultTransport::new(Batching::new(&server), 7));
+    assert_eq!(listing(Shared(fault.clone())), expected());
+    assert_eq!(fault.live_fids().len(), server.live_fids());
+}
+
+#[test]
+fn recorded_batches_replay() {
+    let server = directory();
+    let sink = Sink::default();
+    let recording = RecordingTransport::new(Batching::new(&server), sink.clone()).unwrap();
+    assert_eq!(listing(recording), expected());
+
+    let replay = Arc::new(ReplayTransport::new(&sink.bytes()).unwrap());
+    assert_eq!(listing(Shared(replay.clone())), expected());
+    assert_eq!(replay.divergence(), None);
+    assert_eq!(replay.remaining(), 0);
+}
+
+#[test]
+fn capture_records_every_batched_message() {
+    let server = directory();
+    let sink = Sink::default();
+    let capture = CaptureTransport::new(Batching::new(&server), sink.clone(), None).unwrap();
+    assert_eq!(listing(capture), expected());
+
+    let capture = sink.bytes();
+    let mut offset = 0;
+    let mut packets = 0;
+    while offset < capture.len() {
+        let block_type = u32::from_le_bytes(capture[offset..offset + 4].try_into().unwrap());
+        let len = u32::from_le_bytes(capture[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        // Enhanced packet blocks longer than the bare IP and TCP headers carry 9P messages.
+        let captured = u32::from_le_bytes(capture[offset + 20..offset + 24].try_into().unwrap_or([0; 4]));
+        if block_type == 6 && captured > 40 {
+            packets += 1;
+        }
+        offset += len;
+    }
+    assert_eq!(packets, 2 * server.state().log.len());
+}
+
+/// Fails every request and batch with a timeout, counting attempts.
+#[derive(Default)]
+struct TimingOut(AtomicUsize);
+
+impl Transport for TimingOut {
+    fn request(&self, _req: &[u8], _resp: &mut [u8]) -> Result<usize, String> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Err(String::from("transport timed out"))
+    }
+
+    fn request_batch(&self, _reqs: &[&[u8]], _resps: &mut [&mut [u8]]) -> Result<Vec<usize>, String> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Err(String::from("transport timed out"))
+    }
+}
+
+fn encode(tag: u16, msg: Tmessage) -> Vec<u8> {
+    let mut buf = Vec::new();
+    msg.encode(tag, Dialect::P2000L, &mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn retry_resends_only_idempotent_requests() {
+    let read = encode(1, Tmessage::Read { fid: 2, offset: 0, count: 10 });
+    let clunk = encode(2, Tmessage::Clunk { fid: 2 });
+    let mut resp = [0u8; 64];
+
+    let retry = RetryTransport::new(TimingOut::default(), 2);
+    assert!(retry.request(&read, &mut resp).is_err());
+    assert_eq!(retry.into_inner().0.into_inner(), 3);
+
+    let retry = RetryTransport::new(TimingOut::default(), 2);
+    assert!(retry.request(&clunk, &mut resp).is_err());
+    assert_eq!(retry.into_inner().0.into_inner(), 1);
+}
+
+#[test]
+fn retry_resends_only_idempotent_batches() {
+    let getattr = encode(1, Tmessage::Getattr { fid: 1, request_mask: 0x3fff });
+    let read = encode(2, Tmessage::Read { fid: 2, offset: 0, count: 10 });
+    let clunk = encode(3, Tmessage::Clunk { fid: 2 });
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+
+    let retry = RetryTransport::new(TimingOut::default(), 2);
+    assert!(retry.request_batch(&[&getattr, &read], &mut [&mut a, &mut b]).is_err());
+    assert_eq!(retry.into_inner().0.into_inner(), 3);
+
+    let retry = RetryTransport::new(TimingOut::default(), 2);
+    assert!(retry.request_batch(&[&getattr, &clunk], &mut [&mut a, &mut b]).is_err());
+    assert_eq!(retry.into_inner().0.into_inner(), 1);
+}
+
+/// Read, list and write through `transport`, returning what was read.
+fn workload(transport: impl Transport + 'static) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut session = Session::new(Box::new(transport), String::from("test"));
+    session.negotiate().unwrap();
+    let data = session.read_file("/dir/a").unwrap();
+    session.write_file("/dir/b", b"written").unwrap();
+    let mut names = session.list_dir("/dir").unwrap();
+    names.sort();
+    (data, names)
+}
+
+fn recorded() -> (Mem, Vec<u8>) {
+    let server = Mem::new();
+    server.add_file("/dir/a", b"alpha");
+    let sink = Sink::default();
+    let recording = RecordingTransport::new(server.clone(), sink.clone()).unwrap();
+    workload(recording);
+    (server, sink.bytes())
+}
+
+#[test]
+fn recorded_sessions_replay() {
+    let (server, trace) = recorded();
+    assert!(trace.starts_with(b"9PTRACE\0"));
+    assert_eq!(server.file("/dir/b").unwrap(), b"written");
+
+    let replay = Arc::new(ReplayTransport::new(&trace).unwrap());
+    let (data, names) = workload(Shared(replay.clone()));
+    assert_eq!(data, b"alpha");
+    assert_eq!(names, [b"a".to_vec(), b"b".to_vec()]);
+    assert_eq!(replay.divergence(), None);
+    assert_eq!(replay.remaining(), 0);
+}
+
+#[test]
+fn replay_reports_the_first_divergence() {
+    let (_, trace) = recorded();
+    let replay = Arc::new(ReplayTransport::new(&trace).unwrap());
+    let mut session = Session::new(Box::new(Shared(replay.clone())), String::from("test"));
+    session.negotiate().unwrap();
+    let err = session.read_file("/dir/b").unwrap_err();
+    assert!(err.starts_with("replay diverged at request 2: expected Twalk"), "{err}");
+    assert_eq!(replay.divergence().as_deref(), Some(err.as_str()));
+    assert_eq!(session.read_file("/dir/a").unwrap_err(), err);
+}
+
+#[test]
+fn replay_rejects_damaged_traces() {
+    let (_, trace) = recorded();
+    assert_eq!(ReplayTransport::new(b"not a trace at all").err().unwrap(), "not a 9p trace");
+    let mut future = trace.clone();
+    future[8] = 2;
+    assert_eq!(ReplayTransport::new(&future).err().unwrap(), "unsupported trace version: 2");
+    assert!(ReplayTransport::new(&trace[..trace.len() - 1]).is_err());
+}
+
+#[test]
+fn transport_errors_are_recorded() {
+    let sink = Sink::default();
+    let recording = RecordingTransport::new(TimingOut::default(), sink.clone()).unwrap();
+    let mut resp = [0u8; 64];
+    let clunk = encode(1, Tmessage::Clunk { fid: 2 });
+    assert_eq!(recording.request(&clunk, &mut resp).unwrap_err(), "transport timed out");
+
+    let replay = ReplayTransport::new(&sink.bytes()).unwrap();
+    assert_eq!(replay.request(&clunk, &mut resp).unwrap_err(), "transport timed out");
+    assert_eq!(replay.remaining(), 0);
+}
+
+/// Log lines captured on each test thread.
+static LINES: Mutex<Vec<(ThreadId, String)>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl log::Log for Capture {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LINES.lock().unwrap().push((thread::current().id(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Lines logged by this thread while running `body`.
+fn logged(body: impl FnOnce()) -> Vec<String> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+    body();
+    let id = thread::current().id();
+    LINES.lock().unwrap().iter().filter(|(thread, _)| *thread == id).map(|(_, line)| line.clone()).collect()
+}
+
+#[test]
+fn logging_decodes_each_message() {
+    let server = Mem::new();
+    server.add_file("/hello", b"world");
+    let lines = logged(|| {
+        let mut session = Session::new(Box::new(LoggingTransport::new(server.clone())), String::from("test"));
+        session.negotiate().unwrap();
+        assert_eq!(session.read_file("/hello").unwrap(), b"world");
+        assert!(session.read_file("/missing").is_err());
+    });
+    for expected in [
+        "9p -> Tversion tag=65535 { msize: 16384, version: \"9P2000.L\" }",
+        "9p <- Rversion tag=65535 { msize: 16384, version: \"9P2000.L\" }",
+        "9p -> Tattach tag=1 { fid: 1, afid: 4294967295, uname: \"root\", aname: \"test\", n_uname: Some(0) }",
+        "9p -> Twalk tag=2 { fid: 1, newfid: 2, wnames: [\"hello\"] }",
+        "9p <- Rwalk tag=2 { wqids: [Qid { type_: 0, version: 0, path: 2 }] }",
+        "9p -> Tlopen tag=3 { fid: 2, flags: 0 }",
+        "9p <- Rread tag=4 { count: 5 }",
+        "9p <- Rlerror tag=7 { ecode: 2 }",
+    ] {
+        assert!(lines.iter().any(|line| line == expected), "{expected}\n{lines:#?}");
+    }
+}
+
+#[test]
+fn logging_joins_vectored_messages_and_follows_the_dialect() {
+    /// Negotiates 9P2000.u, then fails every request with an errno.
+    struct Dotu;
+
+    impl Transport for Dotu {
+        fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+            let (tag, msg) = Tmessage::decode(req, Dialect::P2000U)?;
+            let reply = match msg {
+                Tmessage::Version { msize, .. } => Rmessage::Version { msize, version: "9P2000.u" },
+                _ => Rmessage::Error { ename: "gone", errno: Some(2) },
+            };
+            let mut buf = Vec::new();
+            reply.encode(tag, Dialect::P2000U, &mut buf)?;
+            resp[..buf.len()].copy_from_slice(&buf);
+            Ok(buf.len())
+        }
+    }
+
+    let logging = LoggingTransport::new(Dotu);
+    let version = encode(0xffff, Tmessage::Version { msize: 8192, version: "9P2000.u" });
+    let read = encode(1, Tmessage::Read { fid: 2, offset: 0, count: 10 });
+    let lines = logged(|| {
+        let (mut head, mut data) = ([0u8; 7], [0u8; 64]);
+        logging.request_vectored(&version[..7], &[&version[7..]], &mut head, &mut data).unwrap();
+        logging.request(&read, &mut data).unwrap();
+    });
+    assert_eq!(
+        lines,
+        [
+            "9p -> Tversion tag=65535 { msize: 8192, version: \"9P2000.u\" }",
+            "9p <- Rversion tag=65535 { msize: 8192, version: \"9P2000.u\" }",
+            "9p -> Tread tag=1 { fid: 2, offset: 0, count: 10 }",
+            "9p <- Rerror tag=1 { ename: \"gone\", errno: Some(2) }",
+        ]
+    );
+}
@@ -0,0 +1,311 @@
+//! virtio-9p transport against a fake device that services the virtqueue in
+//! memory, so no hypervisor is needed.
+
+mod common;
+
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::ptr::{read_volatile, write_volatile, NonNull};
+use std::sync::atomic::{fence, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::Mem;
+use fs9p::{Dialect, Tmessage, Transport, VirtioTransport};
+use virtio_drivers::transport::{DeviceStatus, DeviceType, InterruptStatus, Transport as DeviceTransport};
+use virtio_drivers::{BufferDirection, Error, Hal, PhysAddr};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+const PAGE_SIZE: usize = 4096;
+const QUEUE_SIZE: u32 = 16;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// DMA straight from the heap: physical addresses are virtual addresses.
+struct IdentityHal;
+
+fn dma_layout(pages: usize) -> Layout {
+    Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap()
+}
+
+unsafe impl Hal for IdentityHal {
+    fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
+        let vaddr = NonNull::new(unsafe { alloc_zeroed(dma_layout(pages)) }).unwrap();
+        (vaddr.as_ptr() as PhysAddr, vaddr)
+    }
+
+    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
+        unsafe { dealloc(vaddr.as_ptr(), dma_layout(pages)) };
+        0
+    }
+
+    unsafe fn mmio_phys_to_virt(_paddr: PhysAddr, _size: usize) -> NonNull<u8> {
+        unreachable!("the fake device has no MMIO regions")
+    }
+
+    unsafe fn share(buffer: NonNull<[u8]>, _direction: BufferDirection) -> PhysAddr {
+        buffer.as_ptr() as *mut u8 as PhysAddr
+    }
+
+    unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
+}
+
+#[derive(Clone, Copy)]
+struct Queue {
+    size: u16,
+    descriptors: PhysAddr,
+    avail: PhysAddr,
+    used: PhysAddr,
+}
+
+/// A virtio-9p device that answers each request from `backend` as soon as
+/// the driver notifies it.
+struct FakeDevice<B> {
+    backend: B,
+    config: Vec<u8>,
+    status: DeviceStatus,
+    queue: Option<Queue>,
+    last_avail: u16,
+    /// Address and length of every descriptor served, in chain order.
+    descriptors: Arc<Mutex<Vec<(u64, usize)>>>,
+}
+
+impl<B: Transport> FakeDevice<B> {
+    fn new(backend: B, mount_tag: &str) -> Self {
+        let mut config = (mount_tag.len() as u16).to_le_bytes().to_vec();
+        config.extend_from_slice(mount_tag.as_bytes());
+        let descriptors = Arc::default();
+        Self { backend, config, status: DeviceStatus::empty(), queue: None, last_avail: 0, descriptors }
+    }
+
+    /// Serve one descriptor chain, returning the number of bytes written back.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must describe the rings the driver registered and `head` a chain it made available.
+    unsafe fn serve(&self, queue: Queue, head: u16) -> u32 {
+        let mut req = Vec::new();
+        let mut outputs = Vec::new();
+        let mut index = head;
+        loop {
+            let desc = queue.descriptors + 16 * u64::from(index);
+            let (addr, len, flags, next) = unsafe {
+                (
+                    read_volatile(desc as *const u64),
+                    read_volatile((desc + 8) as *const u32) as usize,
+                    read_volatile((desc + 12) as *const u16),
+                    read_volatile((desc + 14) as *const u16),
+                )
+            };
+            self.descriptors.lock().unwrap().push((addr, len));
+            if flags & VIRTQ_DESC_F_WRITE != 0 {
+                outputs.push((addr as *mut u8, len));
+            } else {
+                req.extend_from_slice(unsafe { std::slice::from_raw_parts(addr as *const u8, len) });
+            }
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            index = next;
+        }
+
+        let mut resp = vec![0u8; outputs.iter().map(|(_, len)| len).sum()];
+        let Ok(len) = self.backend.request(&req, &mut resp) else { return 0 };
+        let mut written = 0;
+        for (addr, capacity) in outputs {
+            let take = capacity.min(len - written);
+            unsafe { std::ptr::copy_nonoverlapping(resp[written..].as_ptr(), addr, take) };
+            written += take;
+        }
+        written as u32
+    }
+}
+
+impl<B: Transport> DeviceTransport for FakeDevice<B> {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::_9P
+    }
+
+    fn read_device_features(&mut self) -> u64 {
+        VIRTIO_F_VERSION_1
+    }
+
+    fn write_driver_features(&mut self, _driver_features: u64) {}
+
+    fn max_queue_size(&mut self, _queue: u16) -> u32 {
+        QUEUE_SIZE
+    }
+
+    fn notify(&mut self, _queue: u16) {
+        let queue = self.queue.expect("notified before the queue was set up");
+        let avail_idx = unsafe { read_volatile((queue.avail + 2) as *const u16) };
+        while self.last_avail != avail_idx {
+            let slot = queue.avail + 4 + 2 * u64::from(self.last_avail % queue.size);
+            let head = unsafe { read_volatile(slot as *const u16) };
+            let len = unsafe { self.serve(queue, head) };
+            unsafe {
+                let used_idx = read_volatile((queue.used + 2) as *const u16);
+                let elem = queue.used + 4 + 8 * u64::from(used_idx % queue.size);
+                write_volatile(elem as *mut u32, u32::from(head));
+                write_volatile((elem + 4) as *mut u32, len);
+                fence(Ordering::SeqCst);
+                write_volatile((queue.used + 2) as *mut u16, used_idx.wrapping_add(1));
+            }
+            self.last_avail = self.last_avail.wrapping_add(1);
+        }
+    }
+
+    fn get_status(&self) -> DeviceStatus {
+        self.status
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        self.status = status;
+    }
+
+    fn set_guest_page_size(&mut self, _guest_page_size: u32) {}
+
+    fn requires_legacy_layout(&self) -> bool {
+        false
+    }
+
+    fn queue_set(&mut self, _queue: u16, size: u32, descriptors: PhysAddr, driver_area: PhysAddr, device_area: PhysAddr) {
+        self.queue = Some(Queue { size: size as u16, descriptors, avail: driver_area, used: device_area });
+    }
+
+    fn queue_unset(&mut self, _queue: u16) {
+        self.queue = None;
+    }
+
+    fn queue_used(&mut self, _queue: u16) -> bool {
+        self.queue.is_some()
+    }
+
+    fn ack_interrupt(&mut self) -> InterruptStatus {
+        InterruptStatus::empty()
+    }
+
+    fn read_config_generation(&self) -> u32 {
+        0
+    }
+
+    fn read_config_space<T: FromBytes + IntoBytes>(&self, offset: usize) -> Result<T, Error> {
+        let bytes = self.config.get(offset..offset + size_of::<T>()).ok_or(Error::ConfigSpaceTooSmall)?;
+        Ok(T::read_from_bytes(bytes).unwrap())
+    }
+
+    fn write_config_space<T: IntoBytes + Immutable>(&mut self, _offset: usize, _value: T) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+type Virtio<B> = VirtioTransport<IdentityHal, FakeDevice<B>>;
+
+/// Records the aname of every attach before handing it to `Mem`.
+#[derive(Clone, Default)]
+struct Attaches {
+    server: Mem,
+    anames: Arc<Mutex<Vec<String>>>,
+}
+
+impl Transport for Attaches {
+    fn request(&self, req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        if let Ok((_, Tmessage::Attach { aname, .. })) = Tmessage::decode(req, Dialect::P2000L) {
+            self.anames.lock().unwrap().push(String::from(aname));
+        }
+        self.server.request(req, resp)
+    }
+}
+
+#[test]
+fn sessions_run_over_the_virtqueue() {
+    let server = Mem::new();
+    server.add_file("/dir/a", b"alpha");
+    let transport = Virtio::new(FakeDevice::new(server.clone(), "hostshare")).unwrap();
+    let mut session = transport.into_session();
+    session.negotiate().unwrap();
+
+    let data: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+    session.write_file("/dir/big", &data).unwrap();
+    assert_eq!(session.read_file("/dir/big").unwrap(), data);
+    assert_eq!(session.read_file("/dir/a").unwrap(), b"alpha");
+    let mut names: Vec<_> = session.read_dir_plus("/dir").unwrap().into_iter().map(|(entry, _)| entry.name).collect();
+    names.sort();
+    assert_eq!(names, [b"a".to_vec(), b"big".to_vec()]);
+    assert_eq!(server.file("/dir/big").unwrap(), data);
+}
+
+#[test]
+fn payloads_are_chained_as_their_own_descriptors() {
+    let device = FakeDevice::new(Mem::new(), "hostshare");
+    let descriptors = device.descriptors.clone();
+    let mut session = Virtio::new(device).unwrap().into_session();
+    session.negotiate().unwrap();
+    let fid = session.create_file("/f").unwrap();
+    let chained = |buf: &[u8]| descriptors.lock().unwrap().contains(&(buf.as_ptr() as u64, buf.len()));
+
+    let (first, second) = (vec![1u8; 3000], vec![2u8; 5000]);
+    assert_eq!(session.write_vectored_at(fid, 0, &[&first, &second]).unwrap(), 8000);
+    assert!(chained(&first) && chained(&second));
+
+    let mut buf = vec![0u8; 8000];
+    assert_eq!(session.read_into(fid, 0, &mut buf).unwrap(), 8000);
+    assert!(chained(&buf));
+    assert_eq!(buf, [first, second].concat());
+    session.close_fid(fid).unwrap();
+}
+
+#[test]
+fn long_gathers_fall_back_to_one_descriptor() {
+    let device = FakeDevice::new(Mem::new(), "hostshare");
+    let descriptors = device.descriptors.clone();
+    let mut session = Virtio::new(device).unwrap().into_session();
+    session.negotiate().unwrap();
+    let fid = session.create_file("/f").unwrap();
+
+    let pieces: Vec<Vec<u8>> = (0..32u8).map(|i| vec![i; 100]).collect();
+    let bufs: Vec<&[u8]> = pieces.iter().map(Vec::as_slice).collect();
+    descriptors.lock().unwrap().clear();
+    assert_eq!(session.write_vectored_at(fid, 0, &bufs).unwrap(), 3200);
+    // One gathered request descriptor, then the RWRITE head and the spill buffer.
+    let chain = descriptors.lock().unwrap().clone();
+    assert_eq!(chain.len(), 3, "{chain:?}");
+    assert_eq!(chain[0].1, 23 + 3200);
+    let mut buf = vec![0u8; 3200];
+    assert_eq!(session.read_into(fid, 0, &mut buf).unwrap(), 3200);
+    assert_eq!(buf, pieces.concat());
+    session.close_fid(fid).unwrap();
+}
+
+#[test]
+fn sessions_attach_to_the_mount_tag() {
+    let backend = Attaches::default();
+    let transport = Virtio::new(FakeDevice::new(backend.clone(), "hostshare")).unwrap();
+    assert_eq!(transport.mount_tag(), "hostshare");
+    transport.into_session().negotiate().unwrap();
+    assert_eq!(*backend.anames.lock().unwrap(), ["hostshare"]);
+}
+
+#[test]
+fn devices_without_a_mount_tag_are_rejected() {
+    let err = Virtio::new(FakeDevice::new(Mem::new(), "")).err().unwrap();
+    assert!(err.starts_with("virtio-9p init failed"), "{err}");
+}
+
+/// Claims one byte more in the size header than it writes.
+struct Misframed;
+
+impl Transport for Misframed {
+    fn request(&self, _req: &[u8], resp: &mut [u8]) -> Result<usize, String> {
+        resp[..7].copy_from_slice(&[8, 0, 0, 0, 101, 0xff, 0xff]);
+        Ok(7)
+    }
+}
+
+#[test]
+fn misframed_replies_are_errors() {
+    let transport = Virtio::new(FakeDevice::new(Misframed, "tag")).unwrap();
+    let mut req = Vec::new();
+    Tmessage::Version { msize: 8192, version: "9P2000.L" }.encode(0xffff, Dialect::P2000L, &mut req).unwrap();
+    let err = transport.request(&req, &mut [0; 64]).unwrap_err();
+    assert!(err.starts_with("virtio-9p request failed"), "{err}");
+}
@@ -0,0 +1,140 @@
+//! Tree walks against the in-memory server.
+
+mod common;
+
+use common::Mem;
+use fs9p::{Session, WalkEvent, WalkOrder, WalkTree, WalkTreeOptions};
+
+const TREADDIR: u8 = 40;
+
+/// /a/b/y, /a/x, /c and the empty directory /d.
+fn tree() -> Mem {
+    let server = Mem::new();
+    server.add_file("/a/b/y", b"y");
+    server.add_file("/a/x", b"x");
+    server.add_file("/c", b"c");
+    server.add_dir("/d");
+    server
+}
+
+/// Render each event as its path, with a leading `-` for [`WalkEvent::Post`].
+fn events(walk: WalkTree<'_>) -> Vec<String> {
+    walk.map(|event| match event.unwrap() {
+        WalkEvent::Pre(entry) => String::from_utf8(entry.path).unwrap(),
+        WalkEvent::Post(entry) => format!("-{}", String::from_utf8(entry.path).unwrap()),
+    })
+    .collect()
+}
+
+fn walk(session: &mut Session, options: WalkTreeOptions) -> Vec<String> {
+    events(session.walk_tree_with("/", options))
+}
+
+#[test]
+fn depth_first_and_breadth_first_orders() {
+    let server = tree();
+    let mut session = common::session(&server);
+    assert_eq!(events(session.walk_tree("/")), ["/", "/a", "/a/b", "/a/b/y", "/a/x", "/c", "/d"]);
+    let options = WalkTreeOptions { order: WalkOrder::BreadthFirst, ..WalkTreeOptions::default() };
+    assert_eq!(walk(&mut session, options), ["/", "/a", "/c", "/d", "/a/b", "/a/x", "/a/b/y"]);
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn depth_limits() {
+    let server = tree();
+    let mut session = common::session(&server);
+    let options = WalkTreeOptions { min_depth: 2, ..WalkTreeOptions::default() };
+    assert_eq!(walk(&mut session, options), ["/a/b", "/a/b/y", "/a/x"]);
+
+    let readdirs = server.count(TREADDIR);
+    let options = WalkTreeOptions { max_depth: 1, ..WalkTreeOptions::default() };
+    assert_eq!(walk(&mut session, options), ["/", "/a", "/c", "/d"]);
+    assert_eq!(server.count(TREADDIR) - readdirs, 2, "only the root is listed");
+
+    let options = WalkTreeOptions { min_depth: 1, max_depth: 1, ..WalkTreeOptions::default() };
+    assert_eq!(walk(&mut session, options), ["/a", "/c", "/d"]);
+}
+
+#[test]
+fn post_events_follow_directory_contents() {
+    let server = tree();
+    let mut session = common::session(&server);
+    let options = WalkTreeOptions { post_order: true, ..WalkTreeOptions::default() };
+    assert_eq!(
+        walk(&mut session, options),
+        ["/", "/a", "/a/b", "/a/b/y", "-/a/b", "/a/x", "-/a", "/c", "/d", "-/d", "-/"]
+    );
+
+    // Directories above min_depth are not yielded, so neither is their Post.
+    let options = WalkTreeOptions { post_order: true, min_depth: 2, ..WalkTreeOptions::default() };
+    assert_eq!(walk(&mut session, options), ["/a/b", "/a/b/y", "-/a/b", "/a/x"]);
+}
+
+#[test]
+fn filtered_directories_are_not_listed() {
+    let server = tree();
+    let mut session = common::session(&server);
+    let readdirs = server.count(TREADDIR);
+    let walk = session.walk_tree("/").filter_entry(|entry| entry.file_name() != b"a");
+    assert_eq!(events(walk), ["/", "/c", "/d"]);
+    // The root and /d, each listed with one Treaddir plus the empty one that ends it.
+    assert_eq!(server.count(TREADDIR) - readdirs, 4);
+}
+
+#[test]
+fn symlink_loops_are_detected_by_qid() {
+    let server = tree();
+    server.add_symlink("/a/b/up", "/a");
+    server.add_symlink("/a/x-link", "x");
+    let mut session = common::session(&server);
+
+    // Without following, links are plain entries.
+    let paths = events(session.walk_tree("/a"));
+    assert_eq!(paths, ["/a", "/a/b", "/a/b/up", "/a/b/y", "/a/x", "/a/x-link"]);
+
+    let options = WalkTreeOptions { follow_symlinks: true, ..WalkTreeOptions::default() };
+    let results: Vec<_> = session.walk_tree_with("/a", options).collect();
+    let errors: Vec<&String> = results.iter().filter_map(|result| result.as_ref().err()).collect();
+    assert_eq!(errors, ["/a/b/up: filesystem loop"]);
+    let link = results.iter().find_map(|result| match result {
+        Ok(WalkEvent::Pre(entry)) if entry.path == b"/a/x-link" => Some(entry.clone()),
+        _ => None,
+    });
+    assert_eq!(link.unwrap().entry_type, 8, "followed links report their target");
+    assert_eq!(server.live_fids(), 1);
+}
+
+#[test]
+fn hard_links_point_at_the_first_path_seen() {
+    let server = tree();
+    let mut session = common::session(&server);
+    session.link("/a/x", "/d/x-again").unwrap();
+    let options = WalkTreeOptions { hard_links: true, metadata: true, ..WalkTreeOptions::default() };
+    let links: Vec<(String, Option<String>)> = session
+        .walk_tree_with("/", options)
+        .map(|event| match event.unwrap() {
+            WalkEvent::Pre(entry) | WalkEvent::Post(entry) => (
+                String::from_utf8(entry.path).unwrap(),
+                entry.hard_link_of.map(|path| String::from_utf8(path).unwrap()),
+            ),
+        })
+        .filter(|(_, first)| first.is_some())
+        .collect();
+    assert_eq!(links, [(String::from("/d/x-again"), Some(String::from("/a/x")))]);
+}
+
+#[test]
+fn deep_walks_hold_no_fids_between_entries() {
+    let server = Mem::new();
+    let deep: String = (0..30).map(|depth| format!("/level{depth}")).collect();
+    server.add_file(&format!("{deep}/leaf"), b"bottom");
+    let mut session = common::session(&server);
+    let mut entries = 0;
+    for event in session.walk_tree("/") {
+        event.unwrap();
+        entries += 1;
+        assert_eq!(server.live_fids(), 1, "only the root fid stays open");
+    }
+    assert_eq!(entries, 32);
+}